@@ -1,6 +1,30 @@
+#[cfg(any(feature = "hfst", feature = "openfst"))]
 extern crate cpp_build;
 
+#[cfg(any(feature = "hfst", feature = "openfst"))]
 fn main() {
-    println!("cargo:rustc-link-lib=hfst");
-    cpp_build::build("src/hfst.rs");
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    // cpp_build takes a single crate root and can't parse lib.rs, so give it
+    // one which only has the enabled bindings in it.
+    let src = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("src");
+    let root = PathBuf::from(env::var("OUT_DIR").unwrap()).join("cpp_root.rs");
+    {
+        let mut file = File::create(&root).unwrap();
+        if cfg!(feature = "hfst") {
+            println!("cargo:rustc-link-lib=hfst");
+            writeln!(file, "#[path = {:?}] mod hfst_ffi;", src.join("hfst/ffi.rs")).unwrap();
+        }
+        if cfg!(feature = "openfst") {
+            println!("cargo:rustc-link-lib=fst");
+            writeln!(file, "#[path = {:?}] mod openfst_ffi;", src.join("openfst/ffi.rs")).unwrap();
+        }
+    }
+    cpp_build::build(&root);
 }
+
+#[cfg(not(any(feature = "hfst", feature = "openfst")))]
+fn main() {}
@@ -0,0 +1,99 @@
+use smallvec::{self, SmallVec};
+
+use adapters::{WeightedNFA, BeamSearchAdapter, DFAInitialsAdapter,
+               AutomatonDFAAdapter, compare_weights, WeightedStateAutomaton};
+use config::SearchConfig;
+use encoding::InitialEncoder;
+use error::Result;
+
+// Weight of each word whose initial isn't part of the acronym, e.g. "the"
+// in "the new york city" for nyc.
+pub const DEFAULT_SKIP_WEIGHT: f64 = 1.0;
+
+// Matches keys whose words' initials spell the query, e.g. nyc for "new
+// york city", when stepped with the initials of the key's words by
+// DFAInitialsAdapter. Words can be skipped at skip_weight each, before,
+// between or after the ones spelling the query.
+#[derive(Clone, Debug)]
+pub struct AcronymNFA {
+    query: Vec<char>,
+    pub skip_weight: f64,
+    // compare initials ignoring case
+    pub fold_case: bool,
+}
+
+impl AcronymNFA {
+    pub fn new(query: &str) -> AcronymNFA {
+        AcronymNFA {
+            query: query.chars().collect(),
+            skip_weight: DEFAULT_SKIP_WEIGHT,
+            fold_case: true,
+        }
+    }
+
+    pub fn query(&self) -> &[char] {
+        self.query.as_slice()
+    }
+
+    fn same(&self, query: char, initial: char) -> bool {
+        query == initial ||
+            self.fold_case && query.to_lowercase().eq(initial.to_lowercase())
+    }
+}
+
+pub type AcronymNextStates = smallvec::IntoIter<[(usize, f64); 2]>;
+
+impl WeightedNFA for AcronymNFA {
+    // how much of the query has been spelt
+    type State = usize;
+    type NextStateIter<'a> = AcronymNextStates;
+    type InputType = char;
+
+    fn start(&self) -> usize {
+        0
+    }
+
+    fn is_match(&self, state: &usize) -> bool {
+        *state == self.query.len()
+    }
+
+    fn can_match(&self, _state: &usize) -> bool {
+        true
+    }
+
+    fn will_always_match(&self, _state: &usize) -> bool {
+        false
+    }
+
+    fn accept<'a>(&'a self, state: &usize, initial: char) -> AcronymNextStates {
+        let mut next: SmallVec<[(usize, f64); 2]> = SmallVec::new();
+        if let Some(&chr) = self.query.get(*state) {
+            if self.same(chr, initial) {
+                next.push((*state + 1, 0.0));
+            }
+        }
+        next.push((*state, self.skip_weight));
+        next.into_iter()
+    }
+}
+
+pub type AcronymStack = AutomatonDFAAdapter<DFAInitialsAdapter<BeamSearchAdapter<AcronymNFA>>>;
+
+pub fn mk_acronym(query: &str, separator: u8, config: &SearchConfig) -> Result<AcronymStack> {
+    Ok(AutomatonDFAAdapter(DFAInitialsAdapter::new(
+        InitialEncoder { separator: separator },
+        config.beam_search(AcronymNFA::new(query))?)))
+}
+
+impl WeightedStateAutomaton for AcronymStack {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let weights = state.0.iter().filter_map(|&(state, weight)|
+            if (self.0).inner.aut.is_match(&state) {
+                Some(weight)
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
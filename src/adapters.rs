@@ -1,21 +1,44 @@
 use std::cmp::{Eq, Ordering};
-use std::hash::{Hash};
+use std::hash::{Hash, Hasher, BuildHasher};
 use std::str::from_utf8;
-use std::collections::{BinaryHeap, HashSet};
-use std::iter::Iterator;
+use std::collections::{BinaryHeap, HashMap};
+use std::collections::hash_map::RandomState;
+use std::cell::{RefCell, OnceCell};
+use std::iter::{self, Iterator};
+use std::rc::Rc;
+use std::alloc::{self, Layout};
+use std::mem::{self, ManuallyDrop};
+use std::ptr;
 use std::f64;
+use std::fmt;
 
 use fst::automaton::Automaton;
+use smallvec::SmallVec;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "unicode-segmentation")]
+use encoding::GraphemeEncoder;
+use encoding::{InputEncoder, Utf8Encoder, TokenEncoder, InitialEncoder};
+#[cfg(feature = "profiling")]
+use profile::{self, Timer, Phase};
+use pruning::{PruningStrategy, Prune, Composite, AbsoluteThreshold, RelativeThreshold, Histogram};
 
 pub trait WeightedNFA {
     type State;
-    type NextStateIter: Iterator<Item=(Self::State, f64)>;
+    type NextStateIter<'a>: Iterator<Item=(Self::State, f64)> where Self: 'a;
     type InputType: Copy;
 
     fn start(&self) -> Self::State;
 
     fn is_match(&self, state: &Self::State) -> bool;
 
+    // The weight of stopping in a match state, on top of the weight of the
+    // path there.
+    fn final_weight(&self, _state: &Self::State) -> f64 {
+        0.0
+    }
+
     fn can_match(&self, _state: &Self::State) -> bool {
         true
     }
@@ -24,8 +47,15 @@ pub trait WeightedNFA {
         false
     }
 
-    fn accept(&self, state: &Self::State, inp: Self::InputType) ->
-        Self::NextStateIter;
+    // Whether a state will always match and nothing read from it adds any
+    // weight, e.g. a final state with free loops on every input, so the beam
+    // search can stop stepping it.
+    fn is_free_sink(&self, _state: &Self::State) -> bool {
+        false
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: Self::InputType) ->
+        Self::NextStateIter<'a>;
 }
 
 pub trait DFA {
@@ -45,31 +75,182 @@ pub trait DFA {
     }
 
     fn accept(&self, state: &Self::State, inp: Self::InputType) -> Self::State;
+
+    fn accept_bytes(&self, state: &Self::State, inps: &[Self::InputType])
+            -> Self::State
+                where Self::State: Clone, Self::InputType: Copy {
+        let mut state = state.clone();
+        for &inp in inps {
+            state = self.accept(&state, inp);
+        }
+        state
+    }
 }
 
 pub trait FollowEpsilonNFA : WeightedNFA {
-    fn follow_epsilon(&self, state: &Self::State) -> Self::NextStateIter;
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a>;
 }
 
+// Puts the states of the wrapped NFA behind an Rc, so the beam adapters
+// can clone, hash and store them without deep copying states which own
+// allocations.
+#[derive(Clone, Debug)]
+pub struct RcStateNFA<Wrapped: WeightedNFA>(pub Wrapped);
+
+fn share_state<S>((state, weight): (S, f64)) -> (Rc<S>, f64) {
+    (Rc::new(state), weight)
+}
+
+impl<Wrapped: WeightedNFA> WeightedNFA for RcStateNFA<Wrapped> {
+    type State = Rc<Wrapped::State>;
+    type NextStateIter<'a> = iter::Map<Wrapped::NextStateIter<'a>,
+        fn((Wrapped::State, f64)) -> (Rc<Wrapped::State>, f64)> where Self: 'a;
+    type InputType = Wrapped::InputType;
+
+    fn start(&self) -> Self::State {
+        Rc::new(self.0.start())
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.0.is_match(state)
+    }
+
+    fn final_weight(&self, state: &Self::State) -> f64 {
+        self.0.final_weight(state)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.0.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.0.will_always_match(state)
+    }
+
+    fn is_free_sink(&self, state: &Self::State) -> bool {
+        self.0.is_free_sink(state)
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: Self::InputType)
+            -> Self::NextStateIter<'a> {
+        self.0.accept(state, inp).map(share_state)
+    }
+}
+
+impl<Wrapped: FollowEpsilonNFA> FollowEpsilonNFA for RcStateNFA<Wrapped> {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        self.0.follow_epsilon(state).map(share_state)
+    }
+}
+
+// How an arc's weight is combined with the weight of the path to it. Custom
+// functions have to be monotone and never give less than the path weight,
+// since the agenda relies on weights only growing along a path.
+#[derive(Copy, Clone, Debug)]
+pub enum Combine {
+    Sum,
+    // e.g. for models whose weights are per arc confidences
+    Max,
+    Custom(fn(f64, f64) -> f64),
+}
+
+impl Combine {
+    pub fn apply(&self, path: f64, arc: f64) -> f64 {
+        match *self {
+            Combine::Sum => path + arc,
+            Combine::Max => path.max(arc),
+            Combine::Custom(combine) => combine(path, arc),
+        }
+    }
+}
+
+// Most beams are small, so keep up to 4 hypotheses inline.
+pub type Beam<S> = SmallVec<[(S, f64); 4]>;
+
 pub struct BeamSearchAdapter<NFA: WeightedNFA> where NFA::State: Eq + Hash {
     pub aut: NFA,
-    pub threshold: f64,
-    pub beam_size: usize
+    pub pruning: Box<dyn PruningStrategy>,
+    // called with the new beam after every step, e.g. to log why a
+    // hypothesis fell out
+    pub on_step: Option<Box<dyn Fn(&[(NFA::State, f64)]) + Send + Sync>>,
+    pub combine: Combine,
+    // only read by EpsilonExpandingBeamSearchAdapter, which follows no
+    // epsilons if it's unset
+    pub expand_epsilons: bool,
+    // makes the adapter !Sync, so a search can be moved to another thread
+    // but not shared, see ParallelBeamSearchAdapter for that
+    scratch: RefCell<Scratch<NFA>>,
+}
+
+// The pruning strategy and on_step callback are trait objects, so can't be
+// printed, and the adapter can't be cloned.
+impl<NFA: WeightedNFA + fmt::Debug> fmt::Debug for BeamSearchAdapter<NFA>
+        where NFA::State: Eq + Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BeamSearchAdapter")
+            .field("aut", &self.aut)
+            .field("combine", &self.combine)
+            .field("expand_epsilons", &self.expand_epsilons)
+            .field("on_step", &self.on_step.is_some())
+            .finish()
+    }
+}
+
+impl<NFA: WeightedNFA> BeamSearchAdapter<NFA> where NFA::State: Eq + Hash {
+    pub fn new(aut: NFA, threshold: f64, beam_size: usize)
+            -> BeamSearchAdapter<NFA> {
+        BeamSearchAdapter::with_pruning(aut, Box::new(Composite(vec![
+            Box::new(AbsoluteThreshold(threshold)),
+            Box::new(Histogram(beam_size)),
+        ])))
+    }
+
+    // What setting renormalize did before pruning was pluggable: the
+    // threshold is applied relative to each step's best weight.
+    pub fn renormalized(aut: NFA, threshold: f64, beam_size: usize)
+            -> BeamSearchAdapter<NFA> {
+        BeamSearchAdapter::with_pruning(aut, Box::new(Composite(vec![
+            Box::new(RelativeThreshold(threshold)),
+            Box::new(Histogram(beam_size)),
+        ])))
+    }
+
+    // Only keeps the beam_size best, for top-k ranking without a cutoff.
+    pub fn unbounded(aut: NFA, beam_size: usize) -> BeamSearchAdapter<NFA> {
+        BeamSearchAdapter::with_pruning(aut, Box::new(Histogram(beam_size)))
+    }
+
+    pub fn with_pruning(aut: NFA, pruning: Box<dyn PruningStrategy>)
+            -> BeamSearchAdapter<NFA> {
+        BeamSearchAdapter {
+            aut: aut,
+            pruning: pruning,
+            on_step: None,
+            combine: Combine::Sum,
+            expand_epsilons: true,
+            scratch: RefCell::new(Scratch::new()),
+        }
+    }
 }
 
 struct AgendaItem<IterT: Iterator> {
     base_weight: f64,
+    combine: Combine,
     peek: Option<IterT::Item>,
     iter: IterT,
+    // order added to the agenda, to break ties
+    seq: u64,
 }
 
 impl<IterT: Iterator> AgendaItem<IterT> 
         where IterT::Item: Clone {
-    fn new(base_weight: f64, mut iter: IterT) -> AgendaItem<IterT> {
+    fn new(base_weight: f64, combine: Combine, mut iter: IterT) -> AgendaItem<IterT> {
         AgendaItem {
             base_weight: base_weight,
+            combine: combine,
             peek: iter.next(),
             iter: iter,
+            seq: 0,
         }
     }
 
@@ -82,17 +263,26 @@ impl<IterT: Iterator> AgendaItem<IterT>
 
 fn weight<S, IterT: Iterator<Item=(S, f64)>>(item: &AgendaItem<IterT>) -> f64 {
     item.peek.as_ref().map(|&(_, next_weight)| {
-        item.base_weight + next_weight
+        item.combine.apply(item.base_weight, next_weight)
     }).unwrap_or(f64::INFINITY)
 }
 
+// A total order where NaN counts as infinite, so a model which produces NaN
+// weights only loses those hypotheses.
 pub fn compare_weights(w1: &f64, w2: &f64) -> Ordering {
-    w1.partial_cmp(&w2).expect("Uncomparable weights found.")
+    fn nan_as_inf(weight: f64) -> f64 {
+        if weight.is_nan() { f64::INFINITY } else { weight }
+    }
+    nan_as_inf(*w1).partial_cmp(&nan_as_inf(*w2)).unwrap()
 }
 
+// Ties go to whichever item was added first rather than being left to the
+// heap's internals, so results don't depend on how BinaryHeap happens to be
+// implemented.
 impl<S, IterT: Iterator<Item=(S, f64)>> Ord for AgendaItem<IterT> {
     fn cmp(&self, other: &AgendaItem<IterT>) -> Ordering {
         compare_weights(&weight(other), &weight(self))
+            .then_with(|| other.seq.cmp(&self.seq))
     }
 }
 
@@ -104,85 +294,368 @@ impl<S, IterT: Iterator<Item=(S, f64)>> PartialOrd for AgendaItem<IterT> {
 
 impl<S, IterT: Iterator<Item=(S, f64)>> PartialEq for AgendaItem<IterT> {
     fn eq(&self, other: &AgendaItem<IterT>) -> bool {
-        weight(self) == weight(other)
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl<S, IterT: Iterator<Item=(S, f64)>> Eq for AgendaItem<IterT> {}
 
-type Agenda<NFA: WeightedNFA> = BinaryHeap<AgendaItem<NFA::NextStateIter>>;
+struct AgendaQueue<IterT: Iterator> {
+    heap: BinaryHeap<AgendaItem<IterT>>,
+    next_seq: u64,
+}
+
+impl<S, IterT: Iterator<Item=(S, f64)>> AgendaQueue<IterT> where S: Clone {
+    fn new() -> AgendaQueue<IterT> {
+        AgendaQueue { heap: BinaryHeap::new(), next_seq: 0 }
+    }
+
+    fn add(&mut self, mut item: AgendaItem<IterT>) {
+        item.seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(item);
+    }
+
+    // Puts back an item which has been popped, keeping its place among ties.
+    fn push(&mut self, item: AgendaItem<IterT>) {
+        self.heap.push(item);
+    }
+
+    fn pop(&mut self) -> Option<AgendaItem<IterT>> {
+        self.heap.pop()
+    }
+
+    fn clear(&mut self) {
+        self.heap.clear();
+        self.next_seq = 0;
+    }
+
+    fn from_vec(items: Vec<AgendaItem<IterT>>) -> AgendaQueue<IterT> {
+        AgendaQueue { heap: BinaryHeap::from(items), next_seq: 0 }
+    }
+
+    fn into_vec(self) -> Vec<AgendaItem<IterT>> {
+        self.heap.into_vec()
+    }
+}
+
+type Agenda<'a, NFA> = AgendaQueue<<NFA as WeightedNFA>::NextStateIter<'a>>;
 //type ExtraExpand<NFA: WeightedNFA, S> = Fn(&mut Agenda<NFA>, S, f64) -> ();
 
+// Buffers used while stepping which are kept around between calls to
+// accept so their allocations can be reused.
+struct Scratch<NFA: WeightedNFA> {
+    seen_states: StateIndex,
+    // handed over as the new beam at the end of each step
+    result: Beam<NFA::State>,
+    agenda: AgendaBuffer,
+}
+
+impl<NFA: WeightedNFA> Scratch<NFA> where NFA::State: Eq + Hash {
+    fn new() -> Scratch<NFA> {
+        Scratch {
+            seen_states: StateIndex::new(),
+            result: Beam::new(),
+            agenda: AgendaBuffer::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.seen_states.clear();
+        self.result.clear();
+    }
+}
+
+// The agenda's allocation, kept while it's empty. Its items hold iterators
+// borrowing from the automaton for just one call, so their type can't be
+// named here and only the raw allocation is kept, to be taken back as a Vec
+// of the same item type.
+struct AgendaBuffer {
+    ptr: *mut u8,
+    capacity: usize,
+    layout: Layout,
+}
+
+// It's only ever an empty allocation.
+unsafe impl Send for AgendaBuffer {}
+
+impl AgendaBuffer {
+    fn new() -> AgendaBuffer {
+        AgendaBuffer { ptr: ptr::null_mut(), capacity: 0, layout: Layout::new::<()>() }
+    }
+
+    // Unsafe since T has to be the type it was put back with, up to
+    // lifetimes, which don't change the layout.
+    unsafe fn take<T>(&mut self) -> Vec<T> {
+        let ptr = mem::replace(&mut self.ptr, ptr::null_mut());
+        if ptr.is_null() {
+            return Vec::new();
+        }
+        Vec::from_raw_parts(ptr as *mut T, 0, self.capacity)
+    }
+
+    fn put<T>(&mut self, mut items: Vec<T>) {
+        items.clear();
+        if !self.ptr.is_null() || items.capacity() == 0 {
+            return;
+        }
+        let mut items = ManuallyDrop::new(items);
+        *self = AgendaBuffer {
+            ptr: items.as_mut_ptr() as *mut u8,
+            capacity: items.capacity(),
+            layout: Layout::array::<T>(items.capacity()).unwrap(),
+        };
+    }
+}
+
+impl Drop for AgendaBuffer {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() && self.layout.size() != 0 {
+            unsafe { alloc::dealloc(self.ptr, self.layout) }
+        }
+    }
+}
+
+// Finds the states of a beam being built by their hashes, so that each
+// state is only stored once, in the beam, rather than being cloned into a
+// map as well.
+struct StateIndex {
+    hasher: RandomState,
+    // indices in the beam of the states with each hash
+    by_hash: HashMap<u64, SmallVec<[usize; 1]>>,
+}
+
+impl StateIndex {
+    fn new() -> StateIndex {
+        StateIndex {
+            hasher: RandomState::new(),
+            by_hash: HashMap::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_hash.clear();
+    }
+
+    fn hash<S: Hash>(&self, state: &S) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        state.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn find<S: Eq>(&self, hash: u64, state: &S, beam: &[(S, f64)]) -> Option<usize> {
+        self.by_hash.get(&hash)?.iter().cloned()
+            .find(|&idx| beam[idx].0 == *state)
+    }
+
+    fn insert(&mut self, hash: u64, idx: usize) {
+        self.by_hash.entry(hash).or_insert_with(SmallVec::new).push(idx);
+    }
+}
+
 impl<NFA: WeightedNFA> BeamSearchAdapter<NFA> where NFA::State: Eq + Hash + Clone {
-    fn step<ExtraExpand>(&self, state: &<Self as DFA>::State, inp: NFA::InputType,
-            extra_expand: ExtraExpand) -> <Self as DFA>::State
-                where ExtraExpand: Fn(&mut Agenda<NFA>, &NFA::State, f64) -> () {
-        // initialise heap
-        let heap: Agenda<NFA> = state
-                .iter().map(|&(ref nfa_state, weight)| {
-            AgendaItem::new(
+    fn fill_agenda<'a>(&'a self, heap: &mut Agenda<'a, NFA>,
+                       state: &<Self as DFA>::State, inp: NFA::InputType) {
+        for &(ref nfa_state, weight) in state.iter() {
+            heap.add(AgendaItem::new(
                 weight,
+                self.combine,
                 self.aut.accept(nfa_state, inp),
-            )
-        }).collect();
+            ));
+        }
+    }
 
-        self.step_inner(extra_expand, heap, HashSet::new(), vec![])
+    fn with_scratch<'a, F, R>(&'a self, f: F) -> R
+            where F: FnOnce(&mut Agenda<'a, NFA>, &mut Scratch<NFA>) -> R {
+        match self.scratch.try_borrow_mut() {
+            Ok(mut scratch) => {
+                #[cfg(feature = "profiling")]
+                let _timer = Timer::start(Phase::Stepping);
+                // only ever put back by this, with the same item type
+                let mut heap = AgendaQueue::from_vec(unsafe { scratch.agenda.take() });
+                let result = f(&mut heap, &mut scratch);
+                scratch.agenda.put(heap.into_vec());
+                result
+            }
+            // re-entrant use (e.g. the wrapped automaton steps this adapter),
+            // which the outer call is already timing
+            Err(_) => f(&mut AgendaQueue::new(), &mut Scratch::new()),
+        }
+    }
+
+    fn step<'a, ExtraExpand>(&'a self, state: &<Self as DFA>::State,
+            inp: NFA::InputType, extra_expand: ExtraExpand)
+                -> <Self as DFA>::State
+                where ExtraExpand: Fn(&mut Agenda<'a, NFA>, &NFA::State, f64) -> () {
+        if let Some(sink) = self.sink(state) {
+            return sink;
+        }
+        if self.is_dead(state) {
+            return self.dead();
+        }
+        self.with_scratch(|heap, scratch| {
+            // initialise heap
+            scratch.clear();
+            self.fill_agenda(heap, state, inp);
+
+            self.step_inner(extra_expand, heap, scratch)
+        })
+    }
+
+    // Like calling step once per input, but without going through the
+    // scratch buffers' RefCell each time.
+    fn step_many<'a, ExtraExpand>(&'a self, state: &<Self as DFA>::State,
+            inps: &[NFA::InputType], extra_expand: ExtraExpand)
+                -> <Self as DFA>::State
+                where ExtraExpand: Fn(&mut Agenda<'a, NFA>, &NFA::State, f64) -> () {
+        let (&first, rest) = match inps.split_first() {
+            Some(split) => split,
+            None => return state.to_owned(),
+        };
+        if let Some(sink) = self.sink(state) {
+            return sink;
+        }
+        if self.is_dead(state) {
+            return self.dead();
+        }
+        self.with_scratch(|heap, scratch| {
+            scratch.clear();
+            self.fill_agenda(heap, state, first);
+            let mut state = self.step_inner(&extra_expand, heap, scratch);
+            for &inp in rest {
+                if let Some(sink) = self.sink(&state) {
+                    return sink;
+                }
+                if self.is_dead(&state) {
+                    return self.dead();
+                }
+                scratch.clear();
+                self.fill_agenda(heap, &state, inp);
+                state = self.step_inner(&extra_expand, heap, scratch);
+            }
+            state
+        })
     }
 
-    fn step_inner<ExtraExpand>(&self,
+    // Once a hypothesis is a free sink, stepping changes neither whether it
+    // matches nor its weight, so if it's also the lightest in the beam,
+    // nothing else can end up ahead of it and it's carried forward by itself
+    // without any more work. A lighter hypothesis which might still match
+    // keeps the beam going as usual, as does one which will always match but
+    // can still get heavier.
+    fn sink(&self, state: &<Self as DFA>::State) -> Option<<Self as DFA>::State> {
+        let sink = state.iter()
+            .filter(|&&(ref nfa_state, _)| self.aut.is_free_sink(nfa_state))
+            .min_by(|&&(_, ref w1), &&(_, ref w2)| compare_weights(w1, w2));
+        let sink = sink.filter(|&&(_, sink_weight)| state.iter().all(|&(_, weight)|
+            compare_weights(&sink_weight, &weight) != Ordering::Greater));
+        sink.map(|sink| {
+            let mut beam = Beam::new();
+            beam.push(sink.clone());
+            self.observe(&beam);
+            beam
+        })
+    }
+
+    // Weights only grow along a path, so once every hypothesis is past what
+    // the pruning strategy would ever keep, or there are none, nothing can
+    // match from here and stepping can skip the agenda altogether.
+    fn is_dead(&self, state: &<Self as DFA>::State) -> bool {
+        state.iter().all(|&(_, weight)| self.pruning.is_hopeless(weight))
+    }
+
+    // The empty beam, which doesn't allocate.
+    fn dead(&self) -> <Self as DFA>::State {
+        self.observe(&[]);
+        Beam::new()
+    }
+
+    fn step_inner<'a, ExtraExpand>(&'a self,
                   extra_expand: ExtraExpand,
-                  mut heap: Agenda<NFA>,
-                  mut seen_states: HashSet<NFA::State>,
-                  mut result: <Self as DFA>::State)
+                  heap: &mut Agenda<'a, NFA>,
+                  scratch: &mut Scratch<NFA>)
                         -> <Self as DFA>::State
-                where ExtraExpand: Fn(&mut Agenda<NFA>, &NFA::State, f64) -> () {
+                where ExtraExpand: Fn(&mut Agenda<'a, NFA>, &NFA::State, f64) -> () {
+        #[cfg(feature = "profiling")]
+        profile::count_step();
+        let Scratch { ref mut seen_states, ref mut result, .. } = *scratch;
+        // items come off the heap lightest first, so the first is the best,
+        // unless the caller has already put something in the result
+        let mut best = result.first().map(|&(_, weight)| weight);
         while let Some(mut item) = heap.pop() {
             let next_weight = weight(&item);
             if let Some((next_state, _)) = item.next() {
                 //println!("State: {:?} {}", next_state, next_weight);
-                // filter threshold
-                if next_weight > self.threshold ||
-                        next_weight == f64::INFINITY {
+                // infinite and NaN weights are dead ends whatever the pruning
+                if next_weight == f64::INFINITY || next_weight.is_nan() {
+                    continue;
+                }
+                let best = *best.get_or_insert(next_weight);
+                let prune = self.pruning.prune(next_weight, best, result.len());
+                if prune == Prune::Drop {
                     continue;
                 }
-                // filter states already in result set
-                if !seen_states.contains(&next_state) {
-                    seen_states.insert(next_state.clone());
+                // recombine with states already in result set, keeping
+                // the lightest weight
+                let hash = seen_states.hash(&next_state);
+                if let Some(idx) = seen_states.find(hash, &next_state, result) {
+                    if next_weight < result[idx].1 {
+                        result[idx].1 = next_weight;
+                        // whatever was reached from it is now lighter too
+                        extra_expand(heap, &next_state, next_weight);
+                    }
+                } else {
+                    let beam_full = prune == Prune::KeepLast;
+                    if !beam_full {
+                        // maybe expand epsilons
+                        extra_expand(heap, &next_state, next_weight);
+                    }
+                    seen_states.insert(hash, result.len());
                     //println!("Got result {:?}", next_state);
-                    result.push((next_state.clone(), next_weight));
-                    // filter by beam
-                    if result.len() >= self.beam_size {
+                    result.push((next_state, next_weight));
+                    if beam_full {
                         break;
                     }
-                    // maybe expand epsilons
-                    extra_expand(&mut heap, &next_state, next_weight);
                 }
                 // may have more edges, put back
-                heap.push(AgendaItem::<NFA::NextStateIter> {
+                heap.push(AgendaItem::<NFA::NextStateIter<'a>> {
                     .. item
                 });
             }
         }
 
         //result.as_mut_slice().sort_by(|&(_, w1), &(_, w2)| compare_weights(w1, w2));
-        result
+        heap.clear();
+        self.observe(result);
+        mem::replace(result, Beam::with_capacity(result.len()))
+    }
+
+    fn observe(&self, beam: &[(NFA::State, f64)]) {
+        if let Some(ref on_step) = self.on_step {
+            on_step(beam);
+        }
     }
 }
 
 impl<NFA: WeightedNFA> DFA for BeamSearchAdapter<NFA> where NFA::State: Eq + Hash + Clone {
-    type State = Vec<(NFA::State, f64)>;
+    type State = Beam<NFA::State>;
     type InputType = NFA::InputType;
 
     fn start(&self) -> Self::State {
-        vec![(self.aut.start(), 0.0)]
+        let mut start = Beam::new();
+        start.push((self.aut.start(), 0.0));
+        start
     }
 
+    // A final weight can take a match past what the pruning would keep.
     fn is_match(&self, state: &Self::State) -> bool {
-        state.iter().any(|&(ref state, _weight)| self.aut.is_match(state))
+        state.iter().any(|&(ref state, weight)| self.aut.is_match(state) &&
+            !self.pruning.is_hopeless(weight + self.aut.final_weight(state)))
     }
 
     fn can_match(&self, state: &Self::State) -> bool {
-        state.iter().any(|&(ref state, _weight)| self.aut.can_match(state))
+        !self.is_dead(state) &&
+            state.iter().any(|&(ref state, _weight)| self.aut.can_match(state))
     }
 
     fn will_always_match(&self, state: &Self::State) -> bool {
@@ -192,20 +665,189 @@ impl<NFA: WeightedNFA> DFA for BeamSearchAdapter<NFA> where NFA::State: Eq + Has
     fn accept(&self, state: &Self::State, inp: NFA::InputType) -> Self::State {
         self.step(state, inp, |_, _, _| {})
     }
+
+    fn accept_bytes(&self, state: &Self::State, inps: &[NFA::InputType])
+            -> Self::State {
+        self.step_many(state, inps, |_, _, _| {})
+    }
+}
+
+// Expands the hypotheses of beams of at least min_beam hypotheses in
+// parallel, and is just the wrapped adapter for smaller ones.
+#[cfg(feature = "rayon")]
+#[derive(Debug)]
+pub struct ParallelBeamSearchAdapter<NFA: WeightedNFA> where NFA::State: Eq + Hash {
+    pub inner: BeamSearchAdapter<NFA>,
+    pub min_beam: usize,
+}
+
+#[cfg(feature = "rayon")]
+impl<NFA: WeightedNFA + Sync> ParallelBeamSearchAdapter<NFA>
+        where NFA::State: Eq + Hash + Clone + Send + Sync,
+              NFA::InputType: Send + Sync {
+    pub fn new(inner: BeamSearchAdapter<NFA>, min_beam: usize)
+            -> ParallelBeamSearchAdapter<NFA> {
+        ParallelBeamSearchAdapter {
+            inner: inner,
+            min_beam: min_beam,
+        }
+    }
+
+    fn par_step(&self, state: &Beam<NFA::State>, inp: NFA::InputType)
+            -> Beam<NFA::State> {
+        if let Some(sink) = self.inner.sink(state) {
+            return sink;
+        }
+        if self.inner.is_dead(state) {
+            return self.inner.dead();
+        }
+        let aut = &self.inner.aut;
+        let combine = self.inner.combine;
+        let expanded: Vec<Vec<(NFA::State, f64)>> = state[..].par_iter()
+            .map(|&(ref nfa_state, weight)| {
+                aut.accept(nfa_state, inp)
+                    .map(|(next_state, next_weight)| (next_state, combine.apply(weight, next_weight)))
+                    .collect()
+            }).collect();
+        let mut candidates: Vec<(NFA::State, f64)> =
+            expanded.into_iter().flat_map(|next| next).collect();
+        // stable, so ties come out in the same order as the agenda would
+        // give them, item by item in the order they were added
+        candidates.sort_by(|&(_, ref w1), &(_, ref w2)| compare_weights(w1, w2));
+
+        // the same selection as step_inner, minus the heap
+        let mut result = Beam::new();
+        let best = match candidates.first() {
+            Some(&(_, best)) => best,
+            None => return result,
+        };
+        // sorted, so the first time a state is seen is its lightest
+        let mut seen_states = StateIndex::new();
+        for (next_state, next_weight) in candidates {
+            // NaNs sort last along with infinities
+            if next_weight == f64::INFINITY || next_weight.is_nan() {
+                break;
+            }
+            let prune = self.inner.pruning.prune(next_weight, best, result.len());
+            if prune == Prune::Drop {
+                continue;
+            }
+            let hash = seen_states.hash(&next_state);
+            if seen_states.find(hash, &next_state, &result).is_none() {
+                seen_states.insert(hash, result.len());
+                result.push((next_state, next_weight));
+                if prune == Prune::KeepLast {
+                    break;
+                }
+            }
+        }
+        self.inner.observe(&result);
+        result
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<NFA: WeightedNFA + Sync> DFA for ParallelBeamSearchAdapter<NFA>
+        where NFA::State: Eq + Hash + Clone + Send + Sync,
+              NFA::InputType: Send + Sync {
+    type State = Beam<NFA::State>;
+    type InputType = NFA::InputType;
+
+    fn start(&self) -> Self::State {
+        self.inner.start()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.inner.is_match(state)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.inner.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.inner.will_always_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, inp: NFA::InputType) -> Self::State {
+        if state.len() >= self.min_beam {
+            self.par_step(state, inp)
+        } else {
+            self.inner.accept(state, inp)
+        }
+    }
 }
 
 pub struct EpsilonExpandingBeamSearchAdapter
-    <Wrapped: WeightedNFA + FollowEpsilonNFA>(pub BeamSearchAdapter<Wrapped>)
-    where Wrapped::State: Eq + Hash + Clone;
+        <Wrapped: WeightedNFA + FollowEpsilonNFA>
+        where Wrapped::State: Eq + Hash + Clone {
+    inner: BeamSearchAdapter<Wrapped>,
+    // the epsilon closure of the start state, which only depends on the
+    // automaton and the search config, so is worked out once, e.g. for
+    // batches which start a stream per key
+    start: OnceCell<Beam<Wrapped::State>>,
+}
 
+// The cached start beam is left out, like BeamSearchAdapter's scratch.
+impl<Wrapped: WeightedNFA + FollowEpsilonNFA + fmt::Debug> fmt::Debug
+        for EpsilonExpandingBeamSearchAdapter<Wrapped>
+        where Wrapped::State: Eq + Hash + Clone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("EpsilonExpandingBeamSearchAdapter")
+            .field(&self.inner)
+            .finish()
+    }
+}
 
 impl<Wrapped: WeightedNFA + FollowEpsilonNFA> EpsilonExpandingBeamSearchAdapter<Wrapped>
         where Wrapped::State: Eq + Hash + Clone {
-    fn expand_epsilon(&self, heap: &mut Agenda<Wrapped>,
-                      next_state: &Wrapped::State, next_weight: f64) {
-        heap.push(AgendaItem::new(
+    pub fn new(inner: BeamSearchAdapter<Wrapped>) -> EpsilonExpandingBeamSearchAdapter<Wrapped> {
+        EpsilonExpandingBeamSearchAdapter {
+            inner: inner,
+            start: OnceCell::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &BeamSearchAdapter<Wrapped> {
+        &self.inner
+    }
+
+    // Changing the search config or automaton through this drops the cached
+    // start beam, since it may no longer be right.
+    pub fn inner_mut(&mut self) -> &mut BeamSearchAdapter<Wrapped> {
+        self.start.take();
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> BeamSearchAdapter<Wrapped> {
+        self.inner
+    }
+
+    fn expand_start(&self) -> Beam<Wrapped::State> {
+        let start_state = self.inner.start();
+        let (ref state, weight) = start_state[0];
+        self.inner.with_scratch(|heap, scratch| {
+            scratch.clear();
+            self.expand_epsilon(heap, state, weight);
+            let hash = scratch.seen_states.hash(state);
+            scratch.seen_states.insert(hash, 0);
+            scratch.result.push((state.to_owned(), weight));
+            self.inner.step_inner(
+                |heap, next_state, next_weight|
+                    self.expand_epsilon(heap, next_state, next_weight),
+                heap, scratch)
+        })
+    }
+
+    fn expand_epsilon<'a>(&'a self, heap: &mut Agenda<'a, Wrapped>,
+                          next_state: &Wrapped::State, next_weight: f64) {
+        if !self.inner.expand_epsilons {
+            return;
+        }
+        heap.add(AgendaItem::new(
             next_weight,
-            self.0.aut.follow_epsilon(next_state),
+            self.inner.combine,
+            self.inner.aut.follow_epsilon(next_state),
         ));
     }
 }
@@ -215,83 +857,174 @@ impl<Wrapped: WeightedNFA + FollowEpsilonNFA> DFA for EpsilonExpandingBeamSearch
     type InputType = <BeamSearchAdapter<Wrapped> as DFA>::InputType;
 
     fn start(&self) -> Self::State {
-        let start_state = self.0.start();
-        let (ref state, weight) = start_state[0];
-        let mut heap: Agenda<Wrapped> = BinaryHeap::new();
-
-        self.expand_epsilon(&mut heap, state, weight);
-
-        let mut seen = HashSet::new();
-        seen.insert(state.to_owned());
-        let expanded_state = self.0.step_inner(
-            |heap, next_state, next_weight|
-                self.expand_epsilon(heap, next_state, next_weight),
-            heap, seen, vec![(state.to_owned(), weight)]);
-        expanded_state
+        self.start.get_or_init(|| self.expand_start()).clone()
     }
 
     fn is_match(&self, state: &Self::State) -> bool {
-        self.0.is_match(state)
+        self.inner.is_match(state)
     }
 
     fn can_match(&self, state: &Self::State) -> bool {
-        self.0.can_match(state)
+        self.inner.can_match(state)
     }
 
     fn will_always_match(&self, state: &Self::State) -> bool {
-        self.0.will_always_match(state)
+        self.inner.will_always_match(state)
     }
 
     fn accept(&self, state: &Self::State, inp: Wrapped::InputType)
             -> Self::State {
-        self.0.step(state, inp, |heap, next_state, next_weight| {
+        self.inner.step(state, inp, |heap, next_state, next_weight| {
             self.expand_epsilon(heap, next_state, next_weight)
         })
     }
+
+    fn accept_bytes(&self, state: &Self::State, inps: &[Wrapped::InputType])
+            -> Self::State {
+        self.inner.step_many(state, inps, |heap, next_state, next_weight| {
+            self.expand_epsilon(heap, next_state, next_weight)
+        })
+    }
+}
+
+// Holds the bytes of a partially read UTF-8 character inline, so that
+// stepping byte by byte doesn't allocate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Utf8Buffer {
+    bytes: [u8; 4],
+    len: u8,
+}
+
+fn utf8_width(first: u8) -> usize {
+    match first {
+        0x00..=0x7F => 1,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        // never completes
+        _ => 5,
+    }
+}
+
+impl Utf8Buffer {
+    pub fn new() -> Utf8Buffer {
+        Utf8Buffer::default()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.bytes.len()
+    }
+
+    // Once full, further bytes are dropped and the buffer stays full.
+    pub fn push(&mut self, byte: u8) {
+        if !self.is_full() {
+            self.bytes[self.len as usize] = byte;
+            self.len += 1;
+        }
+    }
+
+    // Pushes a byte, returning the character if it completes one. Invalid
+    // sequences never complete.
+    pub fn push_utf8(&mut self, byte: u8) -> Option<char> {
+        self.push(byte);
+        if self.len() != utf8_width(self.bytes[0]) {
+            return None;
+        }
+        // Should never panic since there's at least one byte in buffer, and from_utf8
+        // would fail if it didn't produce at least one char
+        from_utf8(self.as_slice()).ok().map(|st| st.chars().next().unwrap())
+    }
 }
 
-pub struct DFAUtf8Adapter<Wrapped: DFA<InputType=char>>(pub Wrapped);
+// Feeds the wrapped DFA the symbols the encoder makes of the bytes, e.g.
+// chars or whole tokens.
+#[derive(Clone, Debug)]
+pub struct EncodedDFAAdapter<E: InputEncoder, Wrapped: DFA<InputType=E::Symbol>> {
+    pub encoder: E,
+    pub inner: Wrapped,
+}
 
-impl<Wrapped: DFA<InputType=char>> DFA for DFAUtf8Adapter<Wrapped>
+impl<E: InputEncoder, Wrapped: DFA<InputType=E::Symbol>> EncodedDFAAdapter<E, Wrapped> {
+    pub fn new(encoder: E, inner: Wrapped) -> EncodedDFAAdapter<E, Wrapped> {
+        EncodedDFAAdapter { encoder: encoder, inner: inner }
+    }
+}
+
+pub type DFAUtf8Adapter<Wrapped> = EncodedDFAAdapter<Utf8Encoder, Wrapped>;
+
+pub type DFATokenAdapter<Wrapped> = EncodedDFAAdapter<TokenEncoder, Wrapped>;
+pub type DFAInitialsAdapter<Wrapped> = EncodedDFAAdapter<InitialEncoder, Wrapped>;
+#[cfg(feature = "unicode-segmentation")]
+pub type DFAGraphemeAdapter<Wrapped> = EncodedDFAAdapter<GraphemeEncoder, Wrapped>;
+
+impl<E: InputEncoder, Wrapped: DFA<InputType=E::Symbol>> DFA for EncodedDFAAdapter<E, Wrapped>
         where Wrapped::State: Clone {
-    type State = (Wrapped::State, Vec<u8>);
+    type State = (Wrapped::State, E::Buffer);
     type InputType = u8;
 
     fn start(&self) -> Self::State {
-        (self.0.start(), Vec::with_capacity(4))
+        (self.inner.start(), self.encoder.empty())
     }
 
     fn is_match(&self, &(ref state, ref buffer): &Self::State) -> bool {
-        buffer.len() == 0 && self.0.is_match(state)
+        if !self.encoder.can_end(buffer) {
+            return false;
+        }
+        match self.encoder.finish(buffer) {
+            Some(symbol) => self.inner.is_match(&self.inner.accept(state, symbol)),
+            None => self.inner.is_match(state),
+        }
     }
 
-    fn can_match(&self, &(ref state, ref _buffer): &Self::State) -> bool {
-        self.0.can_match(state)
+    fn can_match(&self, &(ref state, _): &Self::State) -> bool {
+        self.inner.can_match(state)
     }
 
-    fn will_always_match(&self, &(ref state, ref _buffer): &Self::State) -> bool {
-        self.0.will_always_match(state)
+    fn will_always_match(&self, &(ref state, _): &Self::State) -> bool {
+        self.inner.will_always_match(state)
     }
 
     fn accept(&self, &(ref state, ref buffer): &Self::State, inp: u8) -> Self::State {
-        let mut buffer = buffer.to_owned();
-        buffer.push(inp);
-        let chr_res;
-        {
-            chr_res = from_utf8(buffer.as_slice()).map(|st| st.chars().next().unwrap());
-        }
-        if let Ok(chr) = chr_res {
-            // Should never panic since there's at least one byte in buffer, and from_utf8
-            // would fail if it didn't produce at least one char
-            (self.0.accept(state, chr), vec![])
-        } else {
-            ((*state).clone(), buffer)
+        let mut buffer = buffer.clone();
+        match self.encoder.push(&mut buffer, inp) {
+            Some(symbol) => (self.inner.accept(state, symbol), buffer),
+            None => (state.clone(), buffer),
         }
     }
+
+    fn accept_bytes(&self, &(ref state, ref buffer): &Self::State, inps: &[u8])
+            -> Self::State {
+        let mut buffer = buffer.clone();
+        let symbols: Vec<E::Symbol> = inps.iter()
+            .filter_map(|&inp| self.encoder.push(&mut buffer, inp))
+            .collect();
+        (self.inner.accept_bytes(state, symbols.as_slice()), buffer)
+    }
 }
 
+#[derive(Clone, Debug)]
 pub struct AutomatonDFAAdapter<Wrapped: DFA<InputType=u8>>(pub Wrapped);
 
+impl<Wrapped: DFA<InputType=u8>> AutomatonDFAAdapter<Wrapped>
+        where Wrapped::State: Clone {
+    pub fn accept_bytes(&self, state: &Wrapped::State, inps: &[u8])
+            -> Wrapped::State {
+        self.0.accept_bytes(state, inps)
+    }
+}
+
 impl<Wrapped: DFA<InputType=u8>> Automaton for AutomatonDFAAdapter<Wrapped> 
         where Wrapped::State: Clone {
     type State = Wrapped::State;
@@ -320,3 +1053,24 @@ impl<Wrapped: DFA<InputType=u8>> Automaton for AutomatonDFAAdapter<Wrapped>
 pub trait WeightedStateAutomaton: Automaton {
     fn get_weight(&self, state: &Self::State) -> f64;
 }
+
+impl<'a, T: WeightedStateAutomaton> WeightedStateAutomaton for &'a T {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        (**self).get_weight(state)
+    }
+}
+
+// Shared by the HFST and OpenFST stacks
+impl<G> WeightedStateAutomaton for AutomatonDFAAdapter<EpsilonExpandingBeamSearchAdapter<G>>
+        where G: FollowEpsilonNFA<InputType=u8>, G::State: Eq + Hash + Clone {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let weights = state.iter().filter_map(|&(ref state, ref weight)|
+            if (self.0).inner.aut.is_match(state) {
+                Some(*weight)
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
@@ -0,0 +1,261 @@
+use fst::Automaton;
+
+use adapters::Utf8Buffer;
+use error::{Error, Result};
+
+// A set of chars given as inclusive ranges, or everything but them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CharClass {
+    pub ranges: Vec<(char, char)>,
+    pub negated: bool,
+}
+
+impl CharClass {
+    pub fn new() -> CharClass {
+        CharClass::default()
+    }
+
+    pub fn any() -> CharClass {
+        CharClass { ranges: vec![], negated: true }
+    }
+
+    pub fn single(chr: char) -> CharClass {
+        CharClass::range(chr, chr)
+    }
+
+    pub fn range(from: char, to: char) -> CharClass {
+        CharClass { ranges: vec![(from, to)], negated: false }
+    }
+
+    pub fn push_range(&mut self, from: char, to: char) {
+        self.ranges.push((from, to));
+    }
+
+    pub fn contains(&self, chr: char) -> bool {
+        let inside = self.ranges.iter().any(|&(from, to)| from <= chr && chr <= to);
+        inside != self.negated
+    }
+}
+
+// A class repeated between min and max times, with no max for unbounded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClassItem {
+    pub class: CharClass,
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+impl ClassItem {
+    pub fn once(class: CharClass) -> ClassItem {
+        ClassItem { class: class, min: 1, max: Some(1) }
+    }
+}
+
+// Matches keys made of a sequence of character classes, e.g. structured
+// identifiers like [A-Z][A-Z][0-9]{4}. It's an fst::Automaton itself, so it
+// can be combined with the fuzzy automata using Automaton::intersection and
+// Automaton::union, e.g. to only correct to keys of the right shape.
+#[derive(Clone, Debug)]
+pub struct CharClassAutomaton {
+    items: Vec<ClassItem>,
+}
+
+// Where matching could be: (item, repetitions of it so far) pairs, sorted
+// and without duplicates, along with any partly read char. No positions
+// means nothing can match.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CharClassState {
+    positions: Vec<(u32, u32)>,
+    buffer: Utf8Buffer,
+}
+
+impl CharClassAutomaton {
+    pub fn new(items: Vec<ClassItem>) -> CharClassAutomaton {
+        CharClassAutomaton { items: items }
+    }
+
+    // Parses a template made of literal chars, . for any char and [...]
+    // classes of chars and a-z style ranges, with [^...] for their
+    // complement. Each can be followed by {n}, {n,m}, {n,}, ?, * or +. A
+    // backslash makes the char after it literal.
+    pub fn parse(template: &str) -> Result<CharClassAutomaton> {
+        let mut chars = template.chars().peekable();
+        let mut items = vec![];
+        while let Some(chr) = chars.next() {
+            let class = match chr {
+                '.' => CharClass::any(),
+                '[' => {
+                    let mut class = CharClass::new();
+                    if chars.peek() == Some(&'^') {
+                        chars.next();
+                        class.negated = true;
+                    }
+                    loop {
+                        let from = match chars.next() {
+                            Some(']') => break,
+                            Some('\\') => chars.next(),
+                            from => from,
+                        }.ok_or_else(|| invalid(template, "unclosed ["))?;
+                        let to = if chars.peek() == Some(&'-') {
+                            chars.next();
+                            match chars.next() {
+                                // a trailing - is literal
+                                Some(']') => {
+                                    class.push_range(from, from);
+                                    class.push_range('-', '-');
+                                    break;
+                                }
+                                Some('\\') => chars.next(),
+                                to => to,
+                            }.ok_or_else(|| invalid(template, "unclosed ["))?
+                        } else {
+                            from
+                        };
+                        if to < from {
+                            return Err(invalid(template, "range out of order"));
+                        }
+                        class.push_range(from, to);
+                    }
+                    class
+                }
+                '\\' => CharClass::single(chars.next()
+                    .ok_or_else(|| invalid(template, "trailing \\"))?),
+                '{' | '}' | '?' | '*' | '+' | ']' =>
+                    return Err(invalid(template, "nothing to repeat")),
+                chr => CharClass::single(chr),
+            };
+            let repeat = match chars.peek().cloned() {
+                Some('?') => Some((0, Some(1))),
+                Some('*') => Some((0, None)),
+                Some('+') => Some((1, None)),
+                Some('{') => {
+                    chars.next();
+                    let mut spec = String::new();
+                    while chars.peek() != Some(&'}') {
+                        spec.push(chars.next().ok_or_else(|| invalid(template, "unclosed {"))?);
+                    }
+                    Some(parse_bounds(&spec)
+                         .ok_or_else(|| invalid(template, "invalid repetition"))?)
+                }
+                _ => None,
+            };
+            let (min, max) = match repeat {
+                Some(bounds) => {
+                    // skips the ?, *, + or }
+                    chars.next();
+                    bounds
+                }
+                None => (1, Some(1)),
+            };
+            items.push(ClassItem { class: class, min: min, max: max });
+        }
+        Ok(CharClassAutomaton::new(items))
+    }
+
+    pub fn items(&self) -> &[ClassItem] {
+        &self.items
+    }
+
+    // Adds the positions reachable by skipping items which have been
+    // repeated enough, then sorts and dedups.
+    fn close(&self, positions: &mut Vec<(u32, u32)>) {
+        let mut idx = 0;
+        while idx < positions.len() {
+            let (item, count) = positions[idx];
+            let item = item as usize;
+            if item < self.items.len() && count >= self.items[item].min {
+                let next = ((item + 1) as u32, 0);
+                if !positions.contains(&next) {
+                    positions.push(next);
+                }
+            }
+            idx += 1;
+        }
+        positions.sort();
+        positions.dedup();
+    }
+
+    fn step(&self, positions: &[(u32, u32)], chr: char) -> Vec<(u32, u32)> {
+        let mut next = Vec::with_capacity(positions.len());
+        for &(item, count) in positions {
+            let class_item = match self.items.get(item as usize) {
+                Some(class_item) => class_item,
+                None => continue,
+            };
+            if class_item.max.map_or(false, |max| count >= max) ||
+                    !class_item.class.contains(chr) {
+                continue;
+            }
+            // past the min, an unbounded item's count no longer matters,
+            // which keeps the number of states finite
+            let count = match class_item.max {
+                None => (count + 1).min(class_item.min),
+                Some(_) => count + 1,
+            };
+            next.push((item, count));
+        }
+        self.close(&mut next);
+        next
+    }
+}
+
+fn invalid(template: &str, why: &str) -> Error {
+    Error::InvalidConfig(format!("{} in template {}", why, template))
+}
+
+// The inside of {n}, {n,} or {n,m}.
+fn parse_bounds(spec: &str) -> Option<(u32, Option<u32>)> {
+    let mut parts = spec.splitn(2, ',');
+    let min = parts.next()?.trim().parse().ok()?;
+    let max = match parts.next() {
+        None => Some(min),
+        Some(max) if max.trim().is_empty() => None,
+        Some(max) => Some(max.trim().parse().ok()?),
+    };
+    match max {
+        Some(max) if max < min => None,
+        _ => Some((min, max)),
+    }
+}
+
+impl Automaton for CharClassAutomaton {
+    type State = CharClassState;
+
+    fn start(&self) -> CharClassState {
+        let mut positions = vec![(0, 0)];
+        self.close(&mut positions);
+        CharClassState { positions: positions, buffer: Utf8Buffer::new() }
+    }
+
+    fn is_match(&self, state: &CharClassState) -> bool {
+        let end = (self.items.len() as u32, 0);
+        state.buffer.is_empty() && state.positions.binary_search(&end).is_ok()
+    }
+
+    fn can_match(&self, state: &CharClassState) -> bool {
+        !state.positions.is_empty()
+    }
+
+    fn will_always_match(&self, _state: &CharClassState) -> bool {
+        false
+    }
+
+    fn accept(&self, state: &CharClassState, byte: u8) -> CharClassState {
+        let mut buffer = state.buffer;
+        match buffer.push_utf8(byte) {
+            Some(chr) => CharClassState {
+                positions: self.step(&state.positions, chr),
+                buffer: Utf8Buffer::new(),
+            },
+            // invalid UTF-8, which can never match
+            None if buffer.is_full() => CharClassState {
+                positions: vec![],
+                buffer: buffer,
+            },
+            None => CharClassState {
+                positions: state.positions.clone(),
+                buffer: buffer,
+            },
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+
+use adapters::{WeightedNFA, BeamSearchAdapter, Combine};
+
+// Beam size used when none is given, e.g. by SearchConfig::default or when
+// converting from an unweighted automaton, which doesn't have one.
+pub const DEFAULT_BEAM_SIZE: usize = 32;
+
+// Threshold used when none is given, about two edits for the Levenshtein
+// automata.
+pub const DEFAULT_THRESHOLD: f64 = 2.0;
+
+// Why a SearchConfig can't be used.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    // nothing fits in the beam, so nothing would ever match
+    ZeroBeamSize,
+    // negative or NaN; an infinite threshold prunes by beam size alone
+    InvalidThreshold(f64),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::ZeroBeamSize => write!(f, "beam size is 0"),
+            ConfigError::InvalidThreshold(threshold) =>
+                write!(f, "threshold {} is negative or NaN", threshold),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            ConfigError::ZeroBeamSize => "beam size is 0",
+            ConfigError::InvalidThreshold(_) => "threshold is negative or NaN",
+        }
+    }
+}
+
+// The parameters of a beam search, shared by the mk_* stack constructors.
+#[derive(Copy, Clone, Debug)]
+pub struct SearchConfig {
+    // hypotheses heavier than this are dropped
+    pub threshold: f64,
+    // at most this many hypotheses are kept per step
+    pub beam_size: usize,
+    // whether epsilon arcs are followed, for stacks over automata with them
+    pub expand_epsilons: bool,
+    pub combine: Combine,
+}
+
+impl Default for SearchConfig {
+    fn default() -> SearchConfig {
+        SearchConfig {
+            threshold: DEFAULT_THRESHOLD,
+            beam_size: DEFAULT_BEAM_SIZE,
+            expand_epsilons: true,
+            combine: Combine::Sum,
+        }
+    }
+}
+
+impl SearchConfig {
+    pub fn new(threshold: f64, beam_size: usize) -> SearchConfig {
+        SearchConfig {
+            threshold: threshold,
+            beam_size: beam_size,
+            ..SearchConfig::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.beam_size == 0 {
+            return Err(ConfigError::ZeroBeamSize);
+        }
+        if self.threshold.is_nan() || self.threshold < 0.0 {
+            return Err(ConfigError::InvalidThreshold(self.threshold));
+        }
+        Ok(())
+    }
+
+    // The beam search adapter for aut, if the config is valid.
+    pub fn beam_search<NFA: WeightedNFA>(&self, aut: NFA)
+            -> Result<BeamSearchAdapter<NFA>, ConfigError>
+                where NFA::State: Eq + Hash {
+        self.validate()?;
+        let mut adapter = BeamSearchAdapter::new(aut, self.threshold, self.beam_size);
+        adapter.expand_epsilons = self.expand_epsilons;
+        adapter.combine = self.combine;
+        Ok(adapter)
+    }
+}
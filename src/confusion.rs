@@ -0,0 +1,63 @@
+use config::SearchConfig;
+use error::Result;
+use levenshtein::ocr::OcrRule;
+use translit::{Mapping, TranslitStack, mk_translit, parse_table};
+
+// Confusions OCR commonly makes, as a table for parse_table, with what the
+// OCR read first and what the key has second. Merges and splits go both
+// ways, e.g. rn for m and m for rn.
+pub const COMMON_OCR_CONFUSIONS: &'static str = "\
+rn m 0.5
+m rn 0.5
+cl d 0.5
+d cl 0.5
+li h 0.7
+h li 0.7
+vv w 0.5
+w vv 0.5
+ri n 0.7
+0 O 0.3
+O 0 0.3
+1 l 0.3
+l 1 0.3
+1 I 0.3
+I 1 0.3
+l I 0.3
+I l 0.3
+5 S 0.5
+S 5 0.5
+8 B 0.5
+B 8 0.5
+";
+
+// Multicharacter OCR confusions on their own, without the ordinary edits
+// of OcrLevenshteinNFA, e.g. to intersect or union with a Levenshtein
+// automaton. Keys have to be the query with some of its parts confused, so
+// anything else has to be the same. This is transliteration where the
+// table is of confusions, so it's a TranslitNFA underneath.
+pub type ConfusionStack = TranslitStack;
+
+impl<'a> From<&'a OcrRule> for Mapping {
+    fn from(rule: &'a OcrRule) -> Mapping {
+        Mapping {
+            from: rule.from.clone(),
+            to: rule.to.clone(),
+            weight: rule.cost,
+        }
+    }
+}
+
+pub fn common_ocr_confusions() -> Vec<Mapping> {
+    parse_table(COMMON_OCR_CONFUSIONS).unwrap()
+}
+
+pub fn mk_ocr_confusion(query: &str, confusions: Vec<Mapping>, config: &SearchConfig)
+        -> Result<ConfusionStack> {
+    mk_translit(query, confusions, config)
+}
+
+// The same with the rules of an OcrLevenshteinNFA.
+pub fn mk_ocr_confusion_from_rules(query: &str, rules: &[OcrRule], config: &SearchConfig)
+        -> Result<ConfusionStack> {
+    mk_translit(query, rules.iter().map(Mapping::from).collect(), config)
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use fst::Map;
+
+use config::SearchConfig;
+use error::Result;
+use ext::map::MapExt;
+use ext::raw::SortKey;
+use levenshtein::weighted::mk_levenshtein;
+use rank_suggestions::NoisyChannel;
+use tokenize::{Tokenizer, Alphanumeric};
+
+// A token of a document which isn't in the dictionary, with where it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correction {
+    // byte offsets into the document
+    pub span: Range<usize>,
+    pub original: String,
+    // best first, with their edit weights, or scores when ranking
+    pub suggestions: Vec<(String, f64)>,
+}
+
+// Spell checks whole documents against a map of words, e.g. for OCR post
+// correction, where the positions of corrections are needed.
+pub struct DocumentCorrector<'m> {
+    map: &'m Map,
+    pub search: SearchConfig,
+    pub max_suggestions: usize,
+    // ranks by edit weight and the map's counts instead of edit weight alone
+    pub ranking: Option<NoisyChannel>,
+    pub tokenizer: Box<dyn Tokenizer>,
+}
+
+impl<'m> DocumentCorrector<'m> {
+    pub fn new(map: &'m Map, search: SearchConfig) -> Result<DocumentCorrector<'m>> {
+        search.validate()?;
+        Ok(DocumentCorrector {
+            map: map,
+            search: search,
+            max_suggestions: 5,
+            ranking: None,
+            tokenizer: Box::new(Alphanumeric),
+        })
+    }
+
+    pub fn suggest(&self, token: &str) -> Result<Vec<(String, f64)>> {
+        let aut = mk_levenshtein(token, &self.search)?;
+        Ok(match self.ranking {
+            Some(ranking) => {
+                let stream = self.map.search_weighted_stream(&aut);
+                ranking.rank(stream, Some(self.max_suggestions)).into_iter()
+                    .map(|suggestion| (
+                        String::from_utf8_lossy(&suggestion.key).into_owned(),
+                        suggestion.score))
+                    .collect()
+            }
+            None => {
+                let stream = self.map.search_state_stream(&aut);
+                stream.collect_sorted(Some(self.max_suggestions), SortKey::Weight)
+                    .into_iter()
+                    .map(|(key, _, weight)| (String::from_utf8_lossy(&key).into_owned(), weight))
+                    .collect()
+            }
+        })
+    }
+
+    // Each distinct unknown token is only searched for once, however often
+    // it appears.
+    pub fn correct_text(&self, document: &str) -> Result<Vec<Correction>> {
+        let mut suggestions: HashMap<&str, Vec<(String, f64)>> = HashMap::new();
+        let mut corrections = vec![];
+        for span in self.tokenizer.tokenize(document) {
+            let token = &document[span.clone()];
+            if self.map.contains_key(token) {
+                continue;
+            }
+            if !suggestions.contains_key(token) {
+                suggestions.insert(token, self.suggest(token)?);
+            }
+            corrections.push(Correction {
+                span: span,
+                original: token.to_owned(),
+                suggestions: suggestions[token].clone(),
+            });
+        }
+        Ok(corrections)
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::f64;
+use std::slice;
+
+use adapters::{WeightedNFA, BeamSearchAdapter, AutomatonDFAAdapter, WeightedStateAutomaton,
+               compare_weights};
+use config::SearchConfig;
+use error::Result;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct DictArc {
+    byte: u8,
+    target: u32,
+    weight: f64,
+}
+
+#[derive(Clone, Debug, Default)]
+struct DictNode {
+    // sorted by byte, with up to two arcs per byte, see DictAutomaton::new,
+    // lightest first since the beam stops taking arcs at the first it drops
+    arcs: Vec<DictArc>,
+    // only ever other than 0 at the root, see DictAutomaton::new
+    final_weight: Option<f64>,
+}
+
+// A trie over a weighted word list, e.g. a whitelist where lighter entries
+// are preferred, to use as a small error model or, beam searched, to
+// intersect with another automaton. The weight of an entry is the sum of
+// the weights along its path.
+//
+// Weights are pushed towards the root, so each arc carries the increase of
+// the lightest entry reachable through it, and the beam can drop prefixes
+// which only lead to heavy entries early. An entry which is also a prefix of
+// lighter ones gets a separate final state, reached by its own arc, so
+// continuing past it doesn't pay for it. The root has no arc into it, so an
+// empty key's weight is a final weight instead.
+#[derive(Clone, Debug)]
+pub struct DictAutomaton {
+    nodes: Vec<DictNode>,
+    num_entries: usize,
+}
+
+#[derive(Default)]
+struct RawNode {
+    children: BTreeMap<u8, usize>,
+    weight: Option<f64>,
+}
+
+impl DictAutomaton {
+    // Entries can come in any order. When a key is given more than once the
+    // lightest weight is kept.
+    pub fn new<I, K>(entries: I) -> DictAutomaton
+            where I: IntoIterator<Item=(K, f64)>, K: AsRef<[u8]> {
+        let mut raw = vec![RawNode::default()];
+        let mut num_entries = 0;
+        for (key, weight) in entries {
+            let mut node = 0;
+            for &byte in key.as_ref() {
+                node = match raw[node].children.get(&byte) {
+                    Some(&child) => child,
+                    None => {
+                        let child = raw.len();
+                        raw[node].children.insert(byte, child);
+                        raw.push(RawNode::default());
+                        child
+                    }
+                };
+            }
+            match raw[node].weight {
+                None => {
+                    num_entries += 1;
+                    raw[node].weight = Some(weight);
+                }
+                Some(old) if weight < old => raw[node].weight = Some(weight),
+                Some(_) => {}
+            }
+        }
+
+        // children always come after their parents, so going backwards
+        // visits them first
+        let mut lightest = vec![f64::INFINITY; raw.len()];
+        for idx in (0..raw.len()).rev() {
+            let below = raw[idx].children.values()
+                .map(|&child| lightest[child])
+                .fold(f64::INFINITY, f64::min);
+            lightest[idx] = raw[idx].weight.map_or(below, |weight| weight.min(below));
+        }
+        // weights are relative to 0 at the root, so the weights of whole
+        // paths are the entries' own
+        lightest[0] = 0.0;
+
+        let mut nodes: Vec<DictNode> = raw.iter().enumerate().map(|(idx, node)| DictNode {
+            arcs: vec![],
+            final_weight: match node.weight {
+                Some(weight) if idx == 0 => Some(weight),
+                Some(_) if node.children.is_empty() => Some(0.0),
+                _ => None,
+            },
+        }).collect();
+        for (idx, node) in raw.iter().enumerate() {
+            let mut arcs = Vec::with_capacity(node.children.len());
+            for (&byte, &child) in node.children.iter() {
+                let child_node = &raw[child];
+                match child_node.weight {
+                    Some(weight) if !child_node.children.is_empty() => {
+                        // the entry ending here gets a final state of its own
+                        let leaf = nodes.len() as u32;
+                        nodes.push(DictNode { arcs: vec![], final_weight: Some(0.0) });
+                        arcs.push(DictArc { byte: byte, target: leaf,
+                                            weight: weight - lightest[idx] });
+                    }
+                    _ => {}
+                }
+                arcs.push(DictArc { byte: byte, target: child as u32,
+                                    weight: lightest[child] - lightest[idx] });
+            }
+            arcs.sort_by(|arc1, arc2| arc1.byte.cmp(&arc2.byte)
+                .then(compare_weights(&arc1.weight, &arc2.weight)));
+            nodes[idx].arcs = arcs;
+        }
+        DictAutomaton { nodes: nodes, num_entries: num_entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_entries == 0
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+pub struct DictNextStates<'a> {
+    arcs: slice::Iter<'a, DictArc>,
+    inp: u8,
+}
+
+impl<'a> Iterator for DictNextStates<'a> {
+    type Item = (u32, f64);
+
+    fn next(&mut self) -> Option<(u32, f64)> {
+        match self.arcs.next() {
+            Some(arc) if arc.byte == self.inp => Some((arc.target, arc.weight)),
+            _ => None,
+        }
+    }
+}
+
+impl WeightedNFA for DictAutomaton {
+    type State = u32;
+    type NextStateIter<'a> = DictNextStates<'a>;
+    type InputType = u8;
+
+    fn start(&self) -> u32 {
+        0
+    }
+
+    fn is_match(&self, state: &u32) -> bool {
+        self.nodes[*state as usize].final_weight.is_some()
+    }
+
+    fn final_weight(&self, state: &u32) -> f64 {
+        self.nodes[*state as usize].final_weight.unwrap_or(f64::INFINITY)
+    }
+
+    fn can_match(&self, state: &u32) -> bool {
+        let node = &self.nodes[*state as usize];
+        node.final_weight.is_some() || !node.arcs.is_empty()
+    }
+
+    fn will_always_match(&self, _state: &u32) -> bool {
+        false
+    }
+
+    fn accept<'a>(&'a self, state: &u32, inp: u8) -> DictNextStates<'a> {
+        let arcs = &self.nodes[*state as usize].arcs;
+        let first = arcs.partition_point(|arc| arc.byte < inp);
+        DictNextStates { arcs: arcs[first..].iter(), inp: inp }
+    }
+}
+
+pub type DictStack = AutomatonDFAAdapter<BeamSearchAdapter<DictAutomaton>>;
+
+pub fn mk_dict_stack(dict: DictAutomaton, config: &SearchConfig) -> Result<DictStack> {
+    Ok(AutomatonDFAAdapter(config.beam_search(dict)?))
+}
+
+impl WeightedStateAutomaton for DictStack {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let aut = &(self.0).aut;
+        let weights = state.iter().filter_map(|&(state, weight)|
+            if aut.is_match(&state) {
+                Some(weight + aut.final_weight(&state))
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
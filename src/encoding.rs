@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+#[cfg(feature = "unicode-segmentation")]
+use unicode_segmentation::UnicodeSegmentation;
+use smallvec::SmallVec;
+
+use adapters::Utf8Buffer;
+
+// Turns the bytes an fst is searched with into the symbols a DFA reads,
+// e.g. chars or whole tokens. The buffer holds whatever has been read so
+// far of the next symbol.
+pub trait InputEncoder {
+    type Symbol: Copy;
+    type Buffer: Clone;
+
+    fn empty(&self) -> Self::Buffer;
+
+    // Pushes a byte, returning the symbol it completes, if any.
+    fn push(&self, buffer: &mut Self::Buffer, byte: u8) -> Option<Self::Symbol>;
+
+    // Whether the input may end here, i.e. the buffer doesn't hold an
+    // incomplete symbol.
+    fn can_end(&self, buffer: &Self::Buffer) -> bool;
+
+    // The symbol completed by the input ending here, if any.
+    fn finish(&self, buffer: &Self::Buffer) -> Option<Self::Symbol>;
+}
+
+// UTF-8 encoded chars.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Utf8Encoder;
+
+impl InputEncoder for Utf8Encoder {
+    type Symbol = char;
+    type Buffer = Utf8Buffer;
+
+    fn empty(&self) -> Utf8Buffer {
+        Utf8Buffer::new()
+    }
+
+    fn push(&self, buffer: &mut Utf8Buffer, byte: u8) -> Option<char> {
+        let chr = buffer.push_utf8(byte);
+        if chr.is_some() {
+            *buffer = Utf8Buffer::new();
+        }
+        chr
+    }
+
+    fn can_end(&self, buffer: &Utf8Buffer) -> bool {
+        buffer.is_empty()
+    }
+
+    fn finish(&self, _buffer: &Utf8Buffer) -> Option<char> {
+        None
+    }
+}
+
+// The bytes so far of the token being read.
+pub type TokenBuffer = SmallVec<[u8; 16]>;
+
+// Whole tokens split on the separator, each numbered by its position in
+// tokens (e.g. a query's distinct tokens), with tokens.len() standing for any
+// other token. Tokens are buffered and compared whole, so two different ones
+// never get the same number. Runs of separators count as one and the last
+// token doesn't need one after it.
+#[derive(Clone, Debug)]
+pub struct TokenEncoder {
+    pub separator: u8,
+    pub tokens: Arc<Vec<Vec<u8>>>,
+}
+
+impl TokenEncoder {
+    pub fn new(separator: u8, tokens: Arc<Vec<Vec<u8>>>) -> TokenEncoder {
+        TokenEncoder { separator: separator, tokens: tokens }
+    }
+
+    pub fn number(&self, token: &[u8]) -> u64 {
+        self.tokens.iter()
+            .position(|known| known.as_slice() == token)
+            .unwrap_or(self.tokens.len()) as u64
+    }
+}
+
+impl InputEncoder for TokenEncoder {
+    type Symbol = u64;
+    type Buffer = Option<TokenBuffer>;
+
+    fn empty(&self) -> Option<TokenBuffer> {
+        None
+    }
+
+    fn push(&self, buffer: &mut Option<TokenBuffer>, byte: u8) -> Option<u64> {
+        if byte == self.separator {
+            buffer.take().map(|token| self.number(&token))
+        } else {
+            buffer.get_or_insert_with(TokenBuffer::new).push(byte);
+            None
+        }
+    }
+
+    fn can_end(&self, _buffer: &Option<TokenBuffer>) -> bool {
+        true
+    }
+
+    fn finish(&self, buffer: &Option<TokenBuffer>) -> Option<u64> {
+        buffer.as_ref().map(|token| self.number(token))
+    }
+}
+
+// The first char of each token split on the separator, e.g. to match
+// acronyms. The buffer is the first char so far of the token being read,
+// and whether it's already been given.
+#[derive(Copy, Clone, Debug)]
+pub struct InitialEncoder {
+    pub separator: u8,
+}
+
+impl InputEncoder for InitialEncoder {
+    type Symbol = char;
+    type Buffer = (Utf8Buffer, bool);
+
+    fn empty(&self) -> (Utf8Buffer, bool) {
+        (Utf8Buffer::new(), false)
+    }
+
+    fn push(&self, buffer: &mut (Utf8Buffer, bool), byte: u8) -> Option<char> {
+        if byte == self.separator {
+            *buffer = self.empty();
+            return None;
+        }
+        if buffer.1 {
+            return None;
+        }
+        let chr = buffer.0.push_utf8(byte);
+        if chr.is_some() {
+            *buffer = (Utf8Buffer::new(), true);
+        }
+        chr
+    }
+
+    fn can_end(&self, buffer: &(Utf8Buffer, bool)) -> bool {
+        buffer.0.is_empty()
+    }
+
+    fn finish(&self, _buffer: &(Utf8Buffer, bool)) -> Option<char> {
+        None
+    }
+}
+
+#[cfg(feature = "unicode-segmentation")]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GraphemeBuffer {
+    // the chars so far of the cluster being read
+    cluster: String,
+    partial: Utf8Buffer,
+}
+
+// Extended grapheme clusters, numbered like TokenEncoder's tokens by their
+// position in clusters, with clusters.len() standing for any other cluster.
+// A cluster is only complete once the char after it is read, since that
+// char could still extend it.
+#[cfg(feature = "unicode-segmentation")]
+#[derive(Clone, Debug)]
+pub struct GraphemeEncoder {
+    pub clusters: Arc<Vec<String>>,
+}
+
+#[cfg(feature = "unicode-segmentation")]
+impl GraphemeEncoder {
+    pub fn new(clusters: Arc<Vec<String>>) -> GraphemeEncoder {
+        GraphemeEncoder { clusters: clusters }
+    }
+
+    pub fn number(&self, cluster: &str) -> u64 {
+        self.clusters.iter()
+            .position(|known| known.as_str() == cluster)
+            .unwrap_or(self.clusters.len()) as u64
+    }
+}
+
+#[cfg(feature = "unicode-segmentation")]
+impl InputEncoder for GraphemeEncoder {
+    type Symbol = u64;
+    type Buffer = GraphemeBuffer;
+
+    fn empty(&self) -> GraphemeBuffer {
+        GraphemeBuffer::default()
+    }
+
+    fn push(&self, buffer: &mut GraphemeBuffer, byte: u8) -> Option<u64> {
+        let chr = buffer.partial.push_utf8(byte)?;
+        buffer.partial = Utf8Buffer::new();
+        let mut extended = buffer.cluster.clone();
+        extended.push(chr);
+        if buffer.cluster.is_empty() || extended.graphemes(true).count() == 1 {
+            buffer.cluster = extended;
+            None
+        } else {
+            let done = self.number(&buffer.cluster);
+            buffer.cluster = chr.to_string();
+            Some(done)
+        }
+    }
+
+    fn can_end(&self, buffer: &GraphemeBuffer) -> bool {
+        buffer.partial.is_empty()
+    }
+
+    fn finish(&self, buffer: &GraphemeBuffer) -> Option<u64> {
+        if buffer.cluster.is_empty() {
+            None
+        } else {
+            Some(self.number(&buffer.cluster))
+        }
+    }
+}
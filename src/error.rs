@@ -0,0 +1,129 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+use fst;
+
+use config::ConfigError;
+
+#[cfg(feature = "hfst")]
+use hfst::WeightError;
+
+pub type Result<T> = result::Result<T, Error>;
+
+// Errors from anywhere in the crate, so callers only have to handle one
+// type.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Fst(fst::Error),
+    // a transducer couldn't be read from the file
+    Load(String),
+    // composing a query with an error model failed
+    Compose,
+    // a transducer couldn't be converted, e.g. to an HfstBasicTransducer
+    Convert,
+    Utf8(Utf8Error),
+    // parameters which can't be used, e.g. a query too long for
+    // MyersLevenshtein
+    InvalidConfig(String),
+    // an invalid SearchConfig
+    Search(ConfigError),
+    // for callers which stop a search part way, e.g. at a deadline
+    Cancelled,
+    // nothing could be matched, e.g. the query has a character the error
+    // model doesn't know
+    NoMatch,
+    #[cfg(feature = "hfst")]
+    Weights(WeightError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => err.fmt(f),
+            Error::Fst(ref err) => err.fmt(f),
+            Error::Load(ref path) => write!(f, "couldn't read transducer from {}", path),
+            Error::Compose => write!(f, "couldn't compose query with error model"),
+            Error::Convert => write!(f, "couldn't convert transducer"),
+            Error::Utf8(ref err) => err.fmt(f),
+            Error::InvalidConfig(ref msg) => write!(f, "invalid configuration: {}", msg),
+            Error::Search(ref err) => write!(f, "invalid search configuration: {}", err),
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::NoMatch => write!(f, "nothing matched"),
+            #[cfg(feature = "hfst")]
+            Error::Weights(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::Fst(_) => "fst error",
+            Error::Load(_) => "couldn't read transducer",
+            Error::Compose => "couldn't compose query with error model",
+            Error::Convert => "couldn't convert transducer",
+            Error::Utf8(_) => "invalid UTF-8",
+            Error::InvalidConfig(_) => "invalid configuration",
+            Error::Search(_) => "invalid search configuration",
+            Error::Cancelled => "cancelled",
+            Error::NoMatch => "nothing matched",
+            #[cfg(feature = "hfst")]
+            Error::Weights(_) => "invalid weights for beam search",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            Error::Fst(ref err) => Some(err),
+            Error::Utf8(ref err) => Some(err),
+            Error::Search(ref err) => Some(err),
+            #[cfg(feature = "hfst")]
+            Error::Weights(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<fst::Error> for Error {
+    fn from(err: fst::Error) -> Error {
+        Error::Fst(err)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Error {
+        Error::Search(err)
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(err: Utf8Error) -> Error {
+        Error::Utf8(err)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Error {
+        Error::Utf8(err.utf8_error())
+    }
+}
+
+#[cfg(feature = "hfst")]
+impl From<WeightError> for Error {
+    fn from(err: WeightError) -> Error {
+        Error::Weights(err)
+    }
+}
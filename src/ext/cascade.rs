@@ -0,0 +1,73 @@
+use fst::Automaton;
+
+// Runs a cheap pre-filter automaton ahead of an expensive main automaton.
+// The main automaton is only advanced while the pre-filter can still match,
+// unlike with Automaton::intersection which always advances both.
+pub struct Cascade<P, A> {
+    pub pre: P,
+    pub aut: A,
+}
+
+pub struct CascadeState<P: Automaton, A: Automaton> {
+    pub pre: P::State,
+    // None once the pre-filter has ruled the path out
+    pub aut: Option<A::State>,
+}
+
+impl<P: Automaton, A: Automaton> Clone for CascadeState<P, A>
+        where P::State: Clone, A::State: Clone {
+    fn clone(&self) -> Self {
+        CascadeState { pre: self.pre.clone(), aut: self.aut.clone() }
+    }
+}
+
+impl<P: Automaton, A: Automaton> Cascade<P, A> {
+    pub fn new(pre: P, aut: A) -> Cascade<P, A> {
+        Cascade { pre: pre, aut: aut }
+    }
+}
+
+impl<P: Automaton, A: Automaton> Automaton for Cascade<P, A> {
+    type State = CascadeState<P, A>;
+
+    fn start(&self) -> Self::State {
+        let pre = self.pre.start();
+        let aut = if self.pre.can_match(&pre) {
+            Some(self.aut.start())
+        } else {
+            None
+        };
+        CascadeState { pre: pre, aut: aut }
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        match state.aut {
+            Some(ref aut) => self.pre.is_match(&state.pre) && self.aut.is_match(aut),
+            None => false,
+        }
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        match state.aut {
+            Some(ref aut) => self.pre.can_match(&state.pre) && self.aut.can_match(aut),
+            None => false,
+        }
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        match state.aut {
+            Some(ref aut) => self.pre.will_always_match(&state.pre) &&
+                self.aut.will_always_match(aut),
+            None => false,
+        }
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        let pre = self.pre.accept(&state.pre, byte);
+        let aut = match state.aut {
+            Some(ref aut) if self.pre.can_match(&pre) => Some(self.aut.accept(aut, byte)),
+            _ => None,
+        };
+        CascadeState { pre: pre, aut: aut }
+    }
+}
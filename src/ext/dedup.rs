@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+use fst::Streamer;
+
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::UnicodeNormalization;
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+
+use adapters::compare_weights;
+
+// Collapses runs of the same key in a stream of (key, output, weight) in
+// key order, e.g. the merged weighted streams of several shards or
+// automata, into one item with the lightest weight and that item's output.
+// Ties go to whichever came first.
+pub struct DedupByKeyMinWeight<S> {
+    stream: S,
+    // the key being collapsed
+    pending: Option<(u64, f64)>,
+    pending_key: Vec<u8>,
+    // the last key yielded
+    key: Vec<u8>,
+}
+
+pub fn dedup_by_key_min_weight<S>(stream: S) -> DedupByKeyMinWeight<S>
+        where S: for<'a> Streamer<'a, Item=(&'a [u8], u64, f64)> {
+    DedupByKeyMinWeight {
+        stream: stream,
+        pending: None,
+        pending_key: vec![],
+        key: vec![],
+    }
+}
+
+impl<S> DedupByKeyMinWeight<S> {
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<'a, S> Streamer<'a> for DedupByKeyMinWeight<S>
+        where S: for<'b> Streamer<'b, Item=(&'b [u8], u64, f64)> {
+    type Item = (&'a [u8], u64, f64);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        loop {
+            let done = match self.stream.next() {
+                Some((key, out, weight)) => {
+                    if let Some((_, best_weight)) = self.pending {
+                        if key == &self.pending_key[..] {
+                            if compare_weights(&weight, &best_weight) == Ordering::Less {
+                                self.pending = Some((out, weight));
+                            }
+                            continue;
+                        }
+                    }
+                    let done = self.pending.take();
+                    mem::swap(&mut self.key, &mut self.pending_key);
+                    self.pending_key.clear();
+                    self.pending_key.extend_from_slice(key);
+                    self.pending = Some((out, weight));
+                    done
+                }
+                None => {
+                    mem::swap(&mut self.key, &mut self.pending_key);
+                    self.pending.take()
+                }
+            };
+            return match done {
+                Some((out, weight)) => Some((&self.key, out, weight)),
+                None if self.pending.is_some() => continue,
+                None => None,
+            };
+        }
+    }
+}
+
+// The same for owned results in any order, e.g. those collected from
+// searches run in parallel, leaving them in key order.
+pub fn dedup_vec_by_key_min_weight(results: &mut Vec<(Vec<u8>, u64, f64)>) {
+    // stable, so ties keep the first
+    results.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| compare_weights(&a.2, &b.2)));
+    results.dedup_by(|later, earlier| later.0 == earlier.0);
+}
+
+// A step of making keys which should count as the same equal, e.g. Cafe
+// and Café with StripDiacritics.
+#[derive(Clone)]
+pub enum KeyNormalization {
+    Lowercase,
+    #[cfg(feature = "unicode-normalization")]
+    Nfc,
+    #[cfg(feature = "unicode-normalization")]
+    StripDiacritics,
+    Custom(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+impl fmt::Debug for KeyNormalization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyNormalization::Lowercase => write!(f, "Lowercase"),
+            #[cfg(feature = "unicode-normalization")]
+            KeyNormalization::Nfc => write!(f, "Nfc"),
+            #[cfg(feature = "unicode-normalization")]
+            KeyNormalization::StripDiacritics => write!(f, "StripDiacritics"),
+            KeyNormalization::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+impl KeyNormalization {
+    pub fn apply(&self, key: &str) -> String {
+        match *self {
+            KeyNormalization::Lowercase => key.to_lowercase(),
+            #[cfg(feature = "unicode-normalization")]
+            KeyNormalization::Nfc => key.nfc().collect(),
+            #[cfg(feature = "unicode-normalization")]
+            KeyNormalization::StripDiacritics => {
+                let mut stripped = String::with_capacity(key.len());
+                for chr in key.chars() {
+                    decompose_canonical(chr, |part| {
+                        if !is_combining_mark(part) {
+                            stripped.push(part);
+                        }
+                    });
+                }
+                stripped
+            }
+            KeyNormalization::Custom(ref normalize) => normalize(key),
+        }
+    }
+}
+
+// Applies steps in order. Keys which aren't UTF-8 have their invalid parts
+// replaced first.
+pub fn normalize_key(steps: &[KeyNormalization], key: &[u8]) -> String {
+    let mut normalized = String::from_utf8_lossy(key).into_owned();
+    for step in steps {
+        normalized = step.apply(&normalized);
+    }
+    normalized
+}
+
+// Keeps only the lightest of the results whose keys are the same once
+// normalized, at the place of the first of them, so results already in
+// weight order stay that way. key_weight gives a result's key and weight.
+pub fn dedup_normalized_by<T, F>(results: Vec<T>, steps: &[KeyNormalization], key_weight: F)
+        -> Vec<T> where F: Fn(&T) -> (&[u8], f64) {
+    let mut kept: Vec<T> = Vec::with_capacity(results.len());
+    let mut seen = HashMap::new();
+    for result in results {
+        let normalized = normalize_key(steps, key_weight(&result).0);
+        if let Some(&idx) = seen.get(&normalized) {
+            let (_, weight) = key_weight(&result);
+            let (_, kept_weight) = key_weight(&kept[idx]);
+            if compare_weights(&weight, &kept_weight) == Ordering::Less {
+                kept[idx] = result;
+            }
+            continue;
+        }
+        seen.insert(normalized, kept.len());
+        kept.push(result);
+    }
+    kept
+}
+
+// The same for owned (key, output, weight) results.
+pub fn dedup_normalized(results: Vec<(Vec<u8>, u64, f64)>, steps: &[KeyNormalization])
+        -> Vec<(Vec<u8>, u64, f64)> {
+    dedup_normalized_by(results, steps, |&(ref key, _, weight)| (key, weight))
+}
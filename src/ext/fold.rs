@@ -0,0 +1,101 @@
+use fst::Automaton;
+
+use adapters::WeightedStateAutomaton;
+
+// Maps each byte of a key before the automaton sees it, so e.g. a case
+// sensitive index can be searched case insensitively without rebuilding
+// it. Bytes are folded one at a time, so folds which change the length of
+// UTF-8 sequences, like most non-ASCII case folding, can't be done here.
+pub trait KeyFold {
+    fn fold(&self, byte: u8) -> u8;
+}
+
+impl<'a, F: KeyFold> KeyFold for &'a F {
+    fn fold(&self, byte: u8) -> u8 {
+        (**self).fold(byte)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AsciiLowercase;
+
+impl KeyFold for AsciiLowercase {
+    fn fold(&self, byte: u8) -> u8 {
+        byte.to_ascii_lowercase()
+    }
+}
+
+// An arbitrary table of what each byte becomes.
+#[derive(Clone, Debug)]
+pub struct ByteMap(pub [u8; 256]);
+
+impl ByteMap {
+    // Leaves every byte as it is until set.
+    pub fn new() -> ByteMap {
+        let mut table = [0; 256];
+        for (byte, to) in table.iter_mut().enumerate() {
+            *to = byte as u8;
+        }
+        ByteMap(table)
+    }
+
+    pub fn set(&mut self, from: u8, to: u8) {
+        self.0[from as usize] = to;
+    }
+}
+
+impl Default for ByteMap {
+    fn default() -> ByteMap {
+        ByteMap::new()
+    }
+}
+
+impl KeyFold for ByteMap {
+    fn fold(&self, byte: u8) -> u8 {
+        self.0[byte as usize]
+    }
+}
+
+// Runs aut over keys as folded by fold. Streams still yield the keys as
+// they are in the fst.
+#[derive(Clone, Debug)]
+pub struct Folded<F, A> {
+    pub fold: F,
+    pub aut: A,
+}
+
+impl<F: KeyFold, A: Automaton> Folded<F, A> {
+    pub fn new(fold: F, aut: A) -> Folded<F, A> {
+        Folded { fold: fold, aut: aut }
+    }
+}
+
+impl<F: KeyFold, A: Automaton> Automaton for Folded<F, A> {
+    type State = A::State;
+
+    fn start(&self) -> A::State {
+        self.aut.start()
+    }
+
+    fn is_match(&self, state: &A::State) -> bool {
+        self.aut.is_match(state)
+    }
+
+    fn can_match(&self, state: &A::State) -> bool {
+        self.aut.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &A::State) -> bool {
+        self.aut.will_always_match(state)
+    }
+
+    fn accept(&self, state: &A::State, byte: u8) -> A::State {
+        self.aut.accept(state, self.fold.fold(byte))
+    }
+}
+
+impl<F: KeyFold, A: WeightedStateAutomaton> WeightedStateAutomaton for Folded<F, A> {
+    fn get_weight(&self, state: &A::State) -> f64 {
+        self.aut.get_weight(state)
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::ops::{Bound, Range};
+
+use fst::map::Map;
+use fst::Streamer;
+
+use adapters::{AutomatonDFAAdapter, DFAUtf8Adapter, compare_weights};
+use config::{SearchConfig, DEFAULT_BEAM_SIZE, DEFAULT_THRESHOLD};
+use encoding::Utf8Encoder;
+use error::Result;
+use ext::dedup::{KeyNormalization, dedup_normalized_by};
+use ext::map::MapExt;
+use levenshtein::costs::{CostModel, UnitCost};
+use levenshtein::weighted::WeightedLevenshteinNFA;
+use rank_suggestions::{NoisyChannel, RankedSuggestion};
+
+// Combines a match's edit weight and output into the score results are
+// ranked by, lower first, e.g. NoisyChannel for a map of counts.
+pub trait Ranker {
+    fn score(&self, edit_weight: f64, out: u64) -> f64;
+}
+
+impl<F: Fn(f64, u64) -> f64> Ranker for F {
+    fn score(&self, edit_weight: f64, out: u64) -> f64 {
+        self(edit_weight, out)
+    }
+}
+
+impl Ranker for NoisyChannel {
+    fn score(&self, edit_weight: f64, count: u64) -> f64 {
+        NoisyChannel::score(self, edit_weight, count)
+    }
+}
+
+// Fuzzy search of a map in one expression, e.g.
+//
+//     map.fuzzy_search("query").max_distance(2).limit(20).run()
+//
+// Without a ranker, results are ranked by edit weight, which is also their
+// score. Either way, ties are broken by key.
+pub struct MapFuzzySearchBuilder<'m> {
+    map: &'m Map,
+    query: String,
+    max_distance: u32,
+    beam_size: usize,
+    costs: Option<Box<dyn CostModel>>,
+    // keys outside this range are left out
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    limit: Option<usize>,
+    ranker: Option<Box<dyn Ranker>>,
+    // keys equal once normalized by these are only given once
+    dedup: Option<Vec<KeyNormalization>>,
+}
+
+// The cost model and ranker are trait objects, so are left out.
+impl<'m> fmt::Debug for MapFuzzySearchBuilder<'m> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MapFuzzySearchBuilder")
+            .field("query", &self.query)
+            .field("max_distance", &self.max_distance)
+            .field("beam_size", &self.beam_size)
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("limit", &self.limit)
+            .field("dedup", &self.dedup)
+            .finish()
+    }
+}
+
+impl<'m> MapFuzzySearchBuilder<'m> {
+    pub fn new(map: &'m Map, query: &str) -> MapFuzzySearchBuilder<'m> {
+        MapFuzzySearchBuilder {
+            map: map,
+            query: query.to_owned(),
+            max_distance: DEFAULT_THRESHOLD as u32,
+            beam_size: DEFAULT_BEAM_SIZE,
+            costs: None,
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            limit: None,
+            ranker: None,
+            dedup: None,
+        }
+    }
+
+    // The heaviest edit weight a match can have.
+    pub fn max_distance(mut self, max_distance: u32) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    pub fn beam_size(mut self, beam_size: usize) -> Self {
+        self.beam_size = beam_size;
+        self
+    }
+
+    // Substitutions cost what costs says rather than 1.
+    pub fn weighted<C: CostModel + 'static>(mut self, costs: C) -> Self {
+        self.costs = Some(Box::new(costs));
+        self
+    }
+
+    // Only keys from range.start up to but not including range.end, like
+    // ge and lt together.
+    pub fn range<K: AsRef<[u8]>>(self, range: Range<K>) -> Self {
+        self.ge(range.start).lt(range.end)
+    }
+
+    // The same bounds as fst's StreamBuilder.
+    pub fn ge<K: AsRef<[u8]>>(mut self, start: K) -> Self {
+        self.start = Bound::Included(start.as_ref().to_vec());
+        self
+    }
+
+    pub fn gt<K: AsRef<[u8]>>(mut self, start: K) -> Self {
+        self.start = Bound::Excluded(start.as_ref().to_vec());
+        self
+    }
+
+    pub fn le<K: AsRef<[u8]>>(mut self, end: K) -> Self {
+        self.end = Bound::Included(end.as_ref().to_vec());
+        self
+    }
+
+    pub fn lt<K: AsRef<[u8]>>(mut self, end: K) -> Self {
+        self.end = Bound::Excluded(end.as_ref().to_vec());
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn ranked_by<R: Ranker + 'static>(mut self, ranker: R) -> Self {
+        self.ranker = Some(Box::new(ranker));
+        self
+    }
+
+    // Only keep the best scored of keys which are the same once normalized,
+    // e.g. with Lowercase and StripDiacritics so Cafe and café aren't both
+    // suggested.
+    pub fn dedup_normalized(mut self, steps: Vec<KeyNormalization>) -> Self {
+        self.dedup = Some(steps);
+        self
+    }
+
+    // Fails if the beam size is 0.
+    pub fn run(mut self) -> Result<Vec<RankedSuggestion>> {
+        let costs = self.costs.take().unwrap_or_else(|| Box::new(UnitCost));
+        let nfa = WeightedLevenshteinNFA::with_costs(&self.query, costs);
+        let config = SearchConfig::new(self.max_distance as f64, self.beam_size);
+        let aut = AutomatonDFAAdapter(DFAUtf8Adapter::new(Utf8Encoder, config.beam_search(nfa)?));
+        let mut results = vec![];
+        let mut stream = self.map.search_weighted_stream(aut);
+        stream.set_key_range(self.start.clone(), self.end.clone());
+        while let Some((key, out, edit_weight)) = stream.next() {
+            let score = match self.ranker {
+                Some(ref ranker) => ranker.score(edit_weight, out),
+                None => edit_weight,
+            };
+            results.push(RankedSuggestion {
+                key: key.to_vec(),
+                count: out,
+                edit_weight: edit_weight,
+                score: score,
+            });
+            // only the best limit are wanted, so once there are twice that
+            // the rest can go, as in collect_sorted
+            if let Some(limit) = self.limit {
+                if results.len() >= limit.saturating_mul(2).max(1) {
+                    results = self.best(results, limit);
+                }
+            }
+        }
+        Ok(self.best(results, self.limit.unwrap_or(usize::max_value())))
+    }
+
+    // The limit best of results, without any which dedup_normalized drops.
+    fn best(&self, mut results: Vec<RankedSuggestion>, limit: usize) -> Vec<RankedSuggestion> {
+        results.sort_by(|a, b|
+            compare_weights(&a.score, &b.score).then_with(|| a.key.cmp(&b.key)));
+        if let Some(ref steps) = self.dedup {
+            results = dedup_normalized_by(results, steps, |result| (&result.key, result.score));
+        }
+        results.truncate(limit);
+        results
+    }
+}
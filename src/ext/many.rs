@@ -0,0 +1,58 @@
+use fst::map::Map;
+use fst::Streamer;
+
+use adapters::compare_weights;
+use ext::map::MapExt;
+use levenshtein::multi::MultiLevenshtein;
+
+// How search_many matches its queries.
+#[derive(Copy, Clone, Debug)]
+pub struct ManyConfig {
+    // keys within this many edits of a query are its results
+    pub distance: u32,
+    // queries per pass over the fst, which bounds the size of the trie and
+    // of the states stepped per key
+    pub batch_size: usize,
+    // only keep this many closest results per query
+    pub limit: Option<usize>,
+}
+
+impl ManyConfig {
+    pub fn new(distance: u32) -> ManyConfig {
+        ManyConfig {
+            distance: distance,
+            batch_size: 1024,
+            limit: None,
+        }
+    }
+}
+
+// Matches every query against the map, e.g. for deduplicating a batch of
+// keys against an index, returning the keys within distance of each query
+// as (key, output, distance) closest first, then in key order. The queries
+// are put in one MultiLevenshtein trie per batch, so each batch is one pass
+// over the fst and queries sharing prefixes share the work of stepping
+// them. Repeated queries get the same results.
+pub fn search_many(map: &Map, queries: &[&str], config: &ManyConfig)
+        -> Vec<Vec<(Vec<u8>, u64, f64)>> {
+    let mut results = vec![vec![]; queries.len()];
+    let batch_size = config.batch_size.max(1);
+    for (batch_idx, batch) in queries.chunks(batch_size).enumerate() {
+        let offset = batch_idx * batch_size;
+        let aut = MultiLevenshtein::new(batch, config.distance);
+        let mut stream = map.search_state_stream(&aut);
+        while let Some((key, out, state)) = stream.next() {
+            for (query, dist) in aut.matching_distances(&state) {
+                results[offset + query].push((key.to_vec(), out, dist as f64));
+            }
+        }
+    }
+    for result in results.iter_mut() {
+        // keys come in order and the sort is stable, so ties stay in it
+        result.sort_by(|a, b| compare_weights(&a.2, &b.2));
+        if let Some(limit) = config.limit {
+            result.truncate(limit);
+        }
+    }
+    results
+}
@@ -1,23 +1,128 @@
+use std::io;
+use std::ops::Bound;
+
 use fst::map::Map;
+use fst::set::Set;
 use ext::raw;
-use ext::raw::FstExt;
+use ext::subgraph;
+use ext::subgraph::MatchedSubgraph;
+use ext::raw::{FstExt, SortKey};
+use ext::fuzzy::MapFuzzySearchBuilder;
 use fst::automaton::{Automaton, AlwaysMatch};
 use fst::Streamer;
+use fst::raw::CompiledAddr;
+
+use adapters::WeightedStateAutomaton;
 
 //pub struct Map(raw::Fst);
 
 pub trait MapExt {
     fn search_state_stream<A: Automaton>(&self, aut: A) -> SimpleStateStream<A>;
+
+    fn search_state_stream_ref<'a, A: Automaton>(&'a self, aut: &'a A)
+            -> SimpleStateStream<'a, &'a A> {
+        self.search_state_stream(aut)
+    }
+
+    fn search_weighted_stream<A: WeightedStateAutomaton>(&self, aut: A)
+        -> WeightedStateStream<A>;
+
+    fn search_cascade_stream<P: Automaton, A: Automaton>(&self, pre: P, aut: A)
+        -> CascadeStream<P, A>;
+
+    fn search_prefix_stream<A: Automaton>(&self, aut: A) -> PrefixStateStream<A>;
+
+    fn count_matches<A: Automaton>(&self, aut: A) -> usize;
+
+    fn any_match<A: Automaton>(&self, aut: A) -> bool;
+
+    fn sample_matches<A, R>(&self, aut: A, k: usize, rng: R) -> Vec<(Vec<u8>, u64, f64)>
+        where A: WeightedStateAutomaton, A::State: Clone, R: FnMut() -> f64;
+
+    fn extract_matches<A, W>(&self, aut: A, wtr: W) -> ::fst::Result<MatchedSubgraph>
+        where A: Automaton, A::State: Clone, W: io::Write;
+
+    fn matched_set<A>(&self, aut: A) -> ::fst::Result<(Set, MatchedSubgraph)>
+        where A: Automaton, A::State: Clone;
+
+    fn fuzzy_search(&self, query: &str) -> MapFuzzySearchBuilder;
 }
 
 impl MapExt for Map {
     fn search_state_stream<A: Automaton>(&self, aut: A) -> SimpleStateStream<A> {
         SimpleStateStream(self.as_fst().search_state_stream(aut))
     }
+
+    fn search_weighted_stream<A: WeightedStateAutomaton>(&self, aut: A)
+            -> WeightedStateStream<A> {
+        WeightedStateStream(self.as_fst().search_weighted_stream(aut))
+    }
+
+    fn search_cascade_stream<P: Automaton, A: Automaton>(&self, pre: P, aut: A)
+            -> CascadeStream<P, A> {
+        CascadeStream(self.as_fst().search_cascade_stream(pre, aut))
+    }
+
+    fn search_prefix_stream<A: Automaton>(&self, aut: A) -> PrefixStateStream<A> {
+        PrefixStateStream(self.as_fst().search_prefix_stream(aut))
+    }
+
+    fn count_matches<A: Automaton>(&self, aut: A) -> usize {
+        self.as_fst().count_matches(aut)
+    }
+
+    fn any_match<A: Automaton>(&self, aut: A) -> bool {
+        self.as_fst().any_match(aut)
+    }
+
+    fn sample_matches<A, R>(&self, aut: A, k: usize, rng: R) -> Vec<(Vec<u8>, u64, f64)>
+            where A: WeightedStateAutomaton, A::State: Clone, R: FnMut() -> f64 {
+        self.as_fst().sample_matches(aut, k, rng).into_iter()
+            .map(|(key, out, weight)| (key, out.value(), weight)).collect()
+    }
+
+    fn extract_matches<A, W>(&self, aut: A, wtr: W) -> ::fst::Result<MatchedSubgraph>
+            where A: Automaton, A::State: Clone, W: io::Write {
+        subgraph::extract_matches(self.as_fst(), aut, wtr)
+    }
+
+    fn matched_set<A>(&self, aut: A) -> ::fst::Result<(Set, MatchedSubgraph)>
+            where A: Automaton, A::State: Clone {
+        subgraph::matched_set(self.as_fst(), aut)
+    }
+
+    fn fuzzy_search(&self, query: &str) -> MapFuzzySearchBuilder {
+        MapFuzzySearchBuilder::new(self, query)
+    }
 }
 
 pub struct SimpleStateStream<'m, A=AlwaysMatch>(raw::SimpleStateStream<'m, A>) where A: Automaton;
 
+impl<'m, A: Automaton> SimpleStateStream<'m, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'m, A: WeightedStateAutomaton> SimpleStateStream<'m, A> where A::State: Clone {
+    pub fn collect_sorted(self, limit: Option<usize>, by: SortKey)
+            -> Vec<(Vec<u8>, u64, f64)> {
+        self.0.collect_sorted(limit, by)
+    }
+
+    pub fn into_iter_owned(self, limit: Option<usize>) -> raw::OwnedIter<'m, A> {
+        self.0.into_iter_owned(limit)
+    }
+}
+
 impl<'a, 'm, A: Automaton> Streamer<'a> for SimpleStateStream<'m, A>
         where A::State: 'a + Clone {
     type Item = (&'a [u8], u64, A::State);
@@ -26,3 +131,155 @@ impl<'a, 'm, A: Automaton> Streamer<'a> for SimpleStateStream<'m, A>
         self.0.next().map(|(key, out, state)| (key, out.value(), state))
     }
 }
+
+impl<'m, A: Automaton> SimpleStateStream<'m, A> {
+    // Drops the automaton's states, so the matches can go straight into
+    // MapBuilder::extend_stream or fst's map operations.
+    pub fn into_plain(self) -> PlainStream<'m, A> {
+        PlainStream(self.0.into_plain())
+    }
+}
+
+impl<'m, A: Automaton> SimpleStateStream<'m, A> {
+    pub fn into_traced(self) -> TracedStream<'m, A> {
+        TracedStream(self.0.into_traced())
+    }
+}
+
+pub struct TracedStream<'m, A=AlwaysMatch>(raw::TracedStream<'m, A>) where A: Automaton;
+
+impl<'m, A: Automaton> TracedStream<'m, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'a, 'm, A: Automaton> Streamer<'a> for TracedStream<'m, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], u64, &'a [u64], A::State);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, out, trace, state)| (key, out.value(), trace, state))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<'m, A: WeightedStateAutomaton> SimpleStateStream<'m, A> {
+    pub fn into_async(self) -> raw::AsyncWeightedStream<'m, A> {
+        self.0.into_async()
+    }
+}
+
+pub struct PlainStream<'m, A=AlwaysMatch>(raw::PlainStream<'m, A>) where A: Automaton;
+
+impl<'m, A: Automaton> PlainStream<'m, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'a, 'm, A: Automaton> Streamer<'a> for PlainStream<'m, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], u64);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, out)| (key, out.value()))
+    }
+}
+
+pub struct WeightedStateStream<'m, A>(raw::WeightedStateStream<'m, A>)
+    where A: WeightedStateAutomaton;
+
+impl<'m, A: WeightedStateAutomaton> WeightedStateStream<'m, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+
+    pub fn into_iter_owned(self, limit: Option<usize>) -> raw::OwnedIter<'m, A> {
+        self.0.into_iter_owned(limit)
+    }
+}
+
+impl<'a, 'm, A: WeightedStateAutomaton> Streamer<'a> for WeightedStateStream<'m, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], u64, f64);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, out, weight)| (key, out.value(), weight))
+    }
+}
+
+pub struct CascadeStream<'m, P, A>(raw::CascadeStream<'m, P, A>)
+    where P: Automaton, A: Automaton;
+
+impl<'m, P: Automaton, A: Automaton> CascadeStream<'m, P, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'a, 'm, P: Automaton, A: Automaton> Streamer<'a> for CascadeStream<'m, P, A>
+        where P::State: 'a + Clone, A::State: 'a + Clone {
+    type Item = (&'a [u8], u64, A::State);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, out, state)| (key, out.value(), state))
+    }
+}
+
+pub struct PrefixStateStream<'m, A>(raw::PrefixStateStream<'m, A>) where A: Automaton;
+
+impl<'m, A: Automaton> PrefixStateStream<'m, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'a, 'm, A: Automaton> Streamer<'a> for PrefixStateStream<'m, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], u64, A::State, CompiledAddr);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, out, state, addr)| (key, out.value(), state, addr))
+    }
+}
@@ -1,2 +1,11 @@
 pub mod raw;
 pub mod map;
+pub mod set;
+pub mod cascade;
+pub mod fold;
+pub mod sample;
+pub mod subgraph;
+pub mod many;
+pub mod dedup;
+pub mod fuzzy;
+pub mod sharded;
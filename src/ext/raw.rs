@@ -1,12 +1,30 @@
-use fst::raw::{Fst, Output, Node};
+use std::io;
+use std::ops::Bound;
+#[cfg(feature = "futures")]
+use std::pin::Pin;
+#[cfg(feature = "futures")]
+use std::task::{Context, Poll};
+
+use fst::raw::{Fst, Output, Node, CompiledAddr};
 use fst::Streamer;
+use fst::set::Set;
 use fst::automaton::{Automaton, AlwaysMatch};
+#[cfg(feature = "futures")]
+use futures::Stream;
 
+use adapters::{WeightedStateAutomaton, compare_weights};
+use ext::cascade::Cascade;
+use ext::sample::Reservoir;
+use ext::subgraph;
+use ext::subgraph::MatchedSubgraph;
 
+// A transition out of a node which has been stepped but not visited yet.
 #[derive(Clone, Debug)]
-struct StreamState<'f, S> {
-    node: Node<'f>,
-    trans: usize,
+struct Child<'f, S> {
+    inp: u8,
+    addr: CompiledAddr,
+    // decoded ahead of time when prefetching
+    node: Option<Node<'f>>,
     out: Output,
     aut_state: S,
 }
@@ -15,75 +33,580 @@ pub struct SimpleStateStream<'f, A=AlwaysMatch> where A: Automaton {
     fst: &'f Fst,
     aut: A,
     inp: Vec<u8>,
-    stack: Vec<StreamState<'f, A::State>>,
+    // one batch of children, in reverse order, per node on the current path
+    stack: Vec<Vec<Child<'f, A::State>>>,
+    // emptied batches, kept for their allocations
+    free: Vec<Vec<Child<'f, A::State>>>,
+    prefetch: bool,
+    // inclusive range of output values to yield
+    output_range: Option<(u64, u64)>,
+    prefix_keys: bool,
+    // root of the subtree under the last match
+    subtree: CompiledAddr,
+    // the output so far at each byte of self.inp, only kept when tracing
+    path_outs: Option<Vec<u64>>,
+    // whether the empty key has still to be checked
+    check_root: bool,
+    // bounds of the keys to yield
+    key_range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
 }
 
 impl<'f, A: Automaton> SimpleStateStream<'f, A> {
     fn new(fst: &'f Fst, aut: A) -> Self {
-        let stack = vec![StreamState {
-            node: fst.root(),
-            trans: 0,
-            out: Output::zero(),
-            aut_state: aut.start(),
-        }];
-        SimpleStateStream {
+        let mut stream = SimpleStateStream {
             fst: fst,
             aut: aut,
             inp: Vec::with_capacity(16),
-            stack: stack,
+            stack: vec![],
+            free: vec![],
+            prefetch: false,
+            output_range: None,
+            prefix_keys: false,
+            subtree: fst.root().addr(),
+            path_outs: None,
+            check_root: true,
+            key_range: (Bound::Unbounded, Bound::Unbounded),
+        };
+        let start = stream.aut.start();
+        let root = fst.root();
+        let batch = stream.expand(&root, Output::zero(), &start);
+        stream.stack.push(batch);
+        stream
+    }
+
+    // Decode child nodes as soon as their parent is expanded rather than
+    // when they're visited, so that for an mmapped fst the reads for a
+    // node's children happen together.
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.prefetch = prefetch;
+    }
+
+    // Only yield keys whose output value is within [min, max], e.g. to only
+    // suggest words above some frequency in a map of frequencies.
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.output_range = Some((min, max));
+    }
+
+    fn in_output_range(&self, out: Output) -> bool {
+        match self.output_range {
+            Some((min, max)) => out.value() >= min && out.value() <= max,
+            None => true,
+        }
+    }
+
+    // Only yield keys within start and end, like fst's StreamBuilder's ge,
+    // gt, le and lt. Subtrees wholly before the start aren't searched, and
+    // the stream stops at the first key past the end.
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.key_range = (start, end);
+    }
+
+    // Whether key is at or after the start, or if it's only a prefix so far,
+    // whether some key starting with it could be.
+    fn after_start(&self, key: &[u8], prefix: bool) -> bool {
+        match self.key_range.0 {
+            Bound::Unbounded => true,
+            Bound::Included(ref start) | Bound::Excluded(ref start) if prefix => {
+                let len = key.len().min(start.len());
+                key[..len] >= start[..len]
+            }
+            Bound::Included(ref start) => key >= &start[..],
+            Bound::Excluded(ref start) => key > &start[..],
+        }
+    }
+
+    // Whether key is at or before the end. Keys starting with one that isn't
+    // come after it, so aren't either.
+    fn before_end(&self, key: &[u8]) -> bool {
+        match self.key_range.1 {
+            Bound::Unbounded => true,
+            Bound::Included(ref end) => key <= &end[..],
+            Bound::Excluded(ref end) => key < &end[..],
+        }
+    }
+
+    fn in_key_range(&self, key: &[u8]) -> bool {
+        self.after_start(key, false) && self.before_end(key)
+    }
+
+    // Steps the automaton over all of a node's transitions at once.
+    fn expand(&mut self, node: &Node<'f>, out: Output, aut_state: &A::State)
+            -> Vec<Child<'f, A::State>> {
+        let mut batch = self.free.pop().unwrap_or_else(Vec::new);
+        if !self.aut.can_match(aut_state) {
+            return batch;
+        }
+        for trans in node.transitions() {
+            batch.push(Child {
+                inp: trans.inp,
+                addr: trans.addr,
+                node: if self.prefetch { Some(self.fst.node(trans.addr)) } else { None },
+                out: out.cat(trans.out),
+                aut_state: self.aut.accept(aut_state, trans.inp),
+            });
+        }
+        batch.reverse();
+        batch
+    }
+}
+
+// Where SimpleStateStream::advance_for stopped.
+enum Step<T> {
+    Found(T),
+    Done,
+    // the budget ran out before the next match was found
+    Paused,
+}
+
+impl<'f, A: Automaton> SimpleStateStream<'f, A> where A::State: Clone {
+    // Moves on to the next match, leaving its key in self.inp.
+    fn advance(&mut self) -> Option<(Output, A::State)> {
+        loop {
+            match self.advance_for(usize::max_value()) {
+                Step::Found(found) => return Some(found),
+                Step::Done => return None,
+                Step::Paused => {}
+            }
+        }
+    }
+
+    // The same, giving up after expanding budget nodes, so a caller can
+    // yield between them. Calling it again carries on from where it was.
+    fn advance_for(&mut self, budget: usize) -> Step<(Output, A::State)> {
+        if self.check_root {
+            self.check_root = false;
+            let root = self.fst.root();
+            let start = self.aut.start();
+            if root.is_final() && self.aut.is_match(&start) {
+                if self.prefix_keys {
+                    // every key starts with the empty one
+                    self.free.extend(self.stack.drain(..));
+                }
+                let out = root.final_output();
+                if self.in_output_range(out) && self.in_key_range(&[]) {
+                    return Step::Found((out, start));
+                }
+            }
+        }
+        let mut expanded = 0;
+        loop {
+            if expanded >= budget {
+                return Step::Paused;
+            }
+            let child = match self.stack.last_mut() {
+                Some(batch) => batch.pop(),
+                None => return Step::Done,
+            };
+            let child = match child {
+                Some(child) => child,
+                None => {
+                    let batch = self.stack.pop().unwrap();
+                    self.free.push(batch);
+                    // the root's batch has no byte of its own
+                    if !self.stack.is_empty() {
+                        self.inp.pop().unwrap();
+                        if let Some(ref mut path_outs) = self.path_outs {
+                            path_outs.pop().unwrap();
+                        }
+                    }
+                    continue;
+                }
+            };
+            if let Some((_, max)) = self.output_range {
+                // outputs only grow along a path, so the whole subtree is out
+                if child.out.value() > max {
+                    continue;
+                }
+            }
+            self.inp.push(child.inp);
+            if !self.before_end(&self.inp) {
+                // keys come in order, so nothing after this is in range
+                self.inp.clear();
+                self.free.extend(self.stack.drain(..));
+                return Step::Done;
+            }
+            if !self.after_start(&self.inp, true) {
+                self.inp.pop();
+                continue;
+            }
+            let is_match = self.aut.is_match(&child.aut_state);
+            if let Some(ref mut path_outs) = self.path_outs {
+                path_outs.push(child.out.value());
+            }
+            self.subtree = child.addr;
+            if self.prefix_keys && is_match {
+                // every key in the subtree starts with this one, so there's
+                // no need to go any further
+                let batch = self.free.pop().unwrap_or_else(Vec::new);
+                self.stack.push(batch);
+                if self.in_output_range(child.out) && self.in_key_range(&self.inp) {
+                    return Step::Found((child.out, child.aut_state));
+                }
+                continue;
+            }
+            let node = match child.node {
+                Some(node) => node,
+                None => self.fst.node(child.addr),
+            };
+            let batch = self.expand(&node, child.out, &child.aut_state);
+            self.stack.push(batch);
+            expanded += 1;
+            if node.is_final() && is_match {
+                let out = child.out.cat(node.final_output());
+                if self.in_output_range(out) && self.in_key_range(&self.inp) {
+                    return Step::Found((out, child.aut_state));
+                }
+            }
+        }
+    }
+}
+
+impl<'f, A: Automaton> SimpleStateStream<'f, A> {
+    // Also gives the output each transition along a match's path added,
+    // e.g. to unpack outputs built from fields at fixed positions.
+    pub fn into_traced(mut self) -> TracedStream<'f, A> {
+        self.path_outs = Some(Vec::with_capacity(16));
+        TracedStream { stream: self, trace: vec![] }
+    }
+}
+
+// Yields each match with one output increment per byte of its key, then
+// the node's final output, which together sum to the match's output.
+pub struct TracedStream<'f, A=AlwaysMatch> where A: Automaton {
+    stream: SimpleStateStream<'f, A>,
+    trace: Vec<u64>,
+}
+
+impl<'f, A: Automaton> TracedStream<'f, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.stream.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.stream.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.stream.set_key_range(start, end);
+    }
+}
+
+impl<'f, 'a, A: Automaton> Streamer<'a> for TracedStream<'f, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], Output, &'a [u64], A::State);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        let (out, state) = self.stream.advance()?;
+        self.trace.clear();
+        let mut prev = 0;
+        for &path_out in self.stream.path_outs.as_ref().unwrap() {
+            self.trace.push(path_out - prev);
+            prev = path_out;
+        }
+        self.trace.push(out.value() - prev);
+        Some((&self.stream.inp, out, &self.trace, state))
+    }
+}
+
+// Yields the prefixes of keys which the automaton matches, each with the
+// output so far and the root of the subtree of keys which start with it.
+pub struct PrefixStateStream<'f, A>(SimpleStateStream<'f, A>) where A: Automaton;
+
+impl<'f, A: Automaton> PrefixStateStream<'f, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'f, 'a, A: Automaton> Streamer<'a> for PrefixStateStream<'f, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], Output, A::State, CompiledAddr);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        match self.0.advance() {
+            Some((out, state)) => Some((&self.0.inp, out, state, self.0.subtree)),
+            None => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Weight,
+    Key,
+}
+
+impl<'f, A: WeightedStateAutomaton> SimpleStateStream<'f, A> where A::State: Clone {
+    // Collects up to limit owned matches, lightest first or in key order.
+    pub fn collect_sorted(mut self, limit: Option<usize>, by: SortKey)
+            -> Vec<(Vec<u8>, u64, f64)> {
+        let limit = limit.unwrap_or(usize::max_value());
+        let mut result = vec![];
+        while let Some((out, state)) = self.advance() {
+            let weight = self.aut.get_weight(&state);
+            result.push((self.inp.clone(), out.value(), weight));
+            match by {
+                // keys come in order, so the first ones are the ones wanted
+                SortKey::Key if result.len() >= limit => break,
+                SortKey::Weight if result.len() >= limit.saturating_mul(2) => {
+                    sort_by_weight(&mut result);
+                    result.truncate(limit);
+                }
+                _ => {}
+            }
+        }
+        if by == SortKey::Weight {
+            sort_by_weight(&mut result);
+        }
+        result.truncate(limit);
+        result
+    }
+}
+
+impl<'f, A: WeightedStateAutomaton> SimpleStateStream<'f, A> {
+    // An ordinary Iterator of owned (key, output, weight) matches in key
+    // order, stopping after limit of them if given. Each key is copied.
+    pub fn into_iter_owned(self, limit: Option<usize>) -> OwnedIter<'f, A> {
+        OwnedIter {
+            stream: self,
+            remaining: limit.unwrap_or(usize::max_value()),
         }
     }
 }
 
+pub struct OwnedIter<'f, A> where A: WeightedStateAutomaton {
+    stream: SimpleStateStream<'f, A>,
+    remaining: usize,
+}
+
+impl<'f, A: WeightedStateAutomaton> Iterator for OwnedIter<'f, A> where A::State: Clone {
+    type Item = (Vec<u8>, u64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (out, state) = self.stream.advance()?;
+        self.remaining -= 1;
+        let weight = self.stream.aut.get_weight(&state);
+        Some((self.stream.inp.clone(), out.value(), weight))
+    }
+}
+
+fn sort_by_weight(result: &mut Vec<(Vec<u8>, u64, f64)>) {
+    result.sort_by(|a, b| compare_weights(&a.2, &b.2).then_with(|| a.0.cmp(&b.0)));
+}
+
 impl<'f, 'a, A: Automaton> Streamer<'a> for SimpleStateStream<'f, A>
         where A::State: 'a + Clone {
     type Item = (&'a [u8], Output, A::State);
 
     fn next(&'a mut self) -> Option<Self::Item> {
-        while let Some(state) = self.stack.pop() {
-            if state.trans >= state.node.len()
-                    || !self.aut.can_match(&state.aut_state) {
-                if state.node.addr() != self.fst.root().addr() {
-                    self.inp.pop().unwrap();
+        match self.advance() {
+            Some((out, state)) => Some((&self.inp, out, state)),
+            None => None,
+        }
+    }
+}
+
+impl<'f, A: Automaton> SimpleStateStream<'f, A> {
+    // Drops the automaton's states, e.g. to pipe the matches into a
+    // MapBuilder or one of fst's set operations.
+    pub fn into_plain(self) -> PlainStream<'f, A> {
+        PlainStream(self)
+    }
+}
+
+// Yields the same as fst's own streams do.
+pub struct PlainStream<'f, A=AlwaysMatch>(SimpleStateStream<'f, A>) where A: Automaton;
+
+impl<'f, A: Automaton> PlainStream<'f, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'f, 'a, A: Automaton> Streamer<'a> for PlainStream<'f, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], Output);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        match self.0.advance() {
+            Some((out, _)) => Some((&self.0.inp, out)),
+            None => None,
+        }
+    }
+}
+
+// Gives the best final weight of each match rather than its state, so it
+// doesn't have to be worked out again from the key.
+pub struct WeightedStateStream<'f, A>(SimpleStateStream<'f, A>)
+    where A: WeightedStateAutomaton;
+
+impl<'f, A: WeightedStateAutomaton> WeightedStateStream<'f, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+
+    pub fn into_iter_owned(self, limit: Option<usize>) -> OwnedIter<'f, A> {
+        self.0.into_iter_owned(limit)
+    }
+}
+
+impl<'f, 'a, A: WeightedStateAutomaton> Streamer<'a> for WeightedStateStream<'f, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], Output, f64);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        match self.0.advance() {
+            Some((out, state)) => {
+                let weight = self.0.aut.get_weight(&state);
+                Some((&self.0.inp, out, weight))
+            }
+            None => None,
+        }
+    }
+}
+
+// Yields the main automaton's state for each match of a Cascade.
+pub struct CascadeStream<'f, P, A>(SimpleStateStream<'f, Cascade<P, A>>)
+    where P: Automaton, A: Automaton;
+
+impl<'f, P: Automaton, A: Automaton> CascadeStream<'f, P, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn set_output_range(&mut self, min: u64, max: u64) {
+        self.0.set_output_range(min, max);
+    }
+
+    pub fn set_key_range(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.0.set_key_range(start, end);
+    }
+}
+
+impl<'f, 'a, P: Automaton, A: Automaton> Streamer<'a> for CascadeStream<'f, P, A>
+        where P::State: 'a + Clone, A::State: 'a + Clone {
+    type Item = (&'a [u8], Output, A::State);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        match self.0.advance() {
+            // a match always has a main state
+            Some((out, state)) => Some((&self.0.inp, out, state.aut.unwrap())),
+            None => None,
+        }
+    }
+}
+
+// Counts matches without keeping track of keys or outputs, stopping at the
+// first when first_only is set.
+fn count<A: Automaton>(fst: &Fst, aut: A, first_only: bool) -> usize {
+    let start = aut.start();
+    let mut count = if fst.root().is_final() && aut.is_match(&start) { 1 } else { 0 };
+    if first_only && count > 0 {
+        return count;
+    }
+    let mut stack = vec![(fst.root(), start)];
+    while let Some((node, state)) = stack.pop() {
+        if !aut.can_match(&state) {
+            continue;
+        }
+        for trans in node.transitions() {
+            let child = fst.node(trans.addr);
+            let child_state = aut.accept(&state, trans.inp);
+            if first_only && aut.will_always_match(&child_state) {
+                // every node leads to a final one
+                return 1;
+            }
+            if child.is_final() && aut.is_match(&child_state) {
+                count += 1;
+                if first_only {
+                    return count;
                 }
-                continue;
             }
-            let trans = state.node.transition(state.trans);
-            let out = state.out.cat(trans.out);
-            let next_state = self.aut.accept(&state.aut_state, trans.inp);
-            let is_match = self.aut.is_match(&next_state);
-            let next_node = self.fst.node(trans.addr);
-            self.inp.push(trans.inp);
-            self.stack.push(StreamState {
-                trans: state.trans + 1, .. state
-            });
-            if next_node.is_final() && is_match {
-                let cloned_next_state = next_state.clone();
-                self.stack.push(StreamState {
-                    node: next_node,
-                    trans: 0,
-                    out: out,
-                    aut_state: next_state,
-                });
-                return Some((
-                    &self.inp,
-                    out.cat(next_node.final_output()),
-                    cloned_next_state));
-            } else {
-                self.stack.push(StreamState {
-                    node: next_node,
-                    trans: 0,
-                    out: out,
-                    aut_state: next_state,
-                });
-            }
-        }
-        None
+            stack.push((child, child_state));
+        }
     }
+    count
 }
 
 pub trait FstExt {
     fn search_state_stream<A: Automaton>(&self, aut: A) -> SimpleStateStream<A>;
+
+    // Borrows the automaton so it can be reused across several fsts.
+    fn search_state_stream_ref<'a, A: Automaton>(&'a self, aut: &'a A)
+            -> SimpleStateStream<'a, &'a A> {
+        self.search_state_stream(aut)
+    }
+
+    fn search_weighted_stream<A: WeightedStateAutomaton>(&self, aut: A)
+        -> WeightedStateStream<A>;
+
+    // Prunes with pre before advancing aut, e.g. a length filter in front
+    // of an HFST model.
+    fn search_cascade_stream<P: Automaton, A: Automaton>(&self, pre: P, aut: A)
+            -> CascadeStream<P, A> {
+        CascadeStream(self.search_state_stream(Cascade::new(pre, aut)))
+    }
+
+    // Matches keys which start with something aut matches, giving only the
+    // shortest such prefix. This is the fst side of StartsWith.
+    fn search_prefix_stream<A: Automaton>(&self, aut: A) -> PrefixStateStream<A> {
+        let mut stream = self.search_state_stream(aut);
+        stream.prefix_keys = true;
+        PrefixStateStream(stream)
+    }
+
+    // Like counting a search_state_stream, but without building the keys.
+    fn count_matches<A: Automaton>(&self, aut: A) -> usize;
+
+    fn any_match<A: Automaton>(&self, aut: A) -> bool;
+
+    // Samples k matches in one pass, each with probability proportional to
+    // exp(-weight). rng should give uniform numbers in (0, 1).
+    fn sample_matches<A, R>(&self, aut: A, k: usize, mut rng: R)
+            -> Vec<(Vec<u8>, Output, f64)>
+            where A: WeightedStateAutomaton, A::State: Clone, R: FnMut() -> f64 {
+        let mut reservoir = Reservoir::new(k);
+        let mut stream = self.search_weighted_stream(aut);
+        while let Some((key, out, weight)) = stream.next() {
+            reservoir.offer(weight, rng(), || (key.to_vec(), out, weight));
+        }
+        reservoir.into_vec()
+    }
+
+    // Writes the matched keys out as a new Set, e.g. a per query candidate
+    // index for exact rescoring later.
+    fn extract_matches<A, W>(&self, aut: A, wtr: W) -> ::fst::Result<MatchedSubgraph>
+        where A: Automaton, A::State: Clone, W: io::Write;
+
+    fn matched_set<A>(&self, aut: A) -> ::fst::Result<(Set, MatchedSubgraph)>
+        where A: Automaton, A::State: Clone;
 }
 
 impl FstExt for Fst {
@@ -91,7 +614,77 @@ impl FstExt for Fst {
         SimpleStateStream::new(self, aut)
     }
 
+    fn search_weighted_stream<A: WeightedStateAutomaton>(&self, aut: A)
+            -> WeightedStateStream<A> {
+        WeightedStateStream(SimpleStateStream::new(self, aut))
+    }
+
+    fn count_matches<A: Automaton>(&self, aut: A) -> usize {
+        count(self, aut, false)
+    }
+
+    fn any_match<A: Automaton>(&self, aut: A) -> bool {
+        count(self, aut, true) > 0
+    }
+
+    fn extract_matches<A, W>(&self, aut: A, wtr: W) -> ::fst::Result<MatchedSubgraph>
+            where A: Automaton, A::State: Clone, W: io::Write {
+        subgraph::extract_matches(self, aut, wtr)
+    }
+
+    fn matched_set<A>(&self, aut: A) -> ::fst::Result<(Set, MatchedSubgraph)>
+            where A: Automaton, A::State: Clone {
+        subgraph::matched_set(self, aut)
+    }
+
     /*fn search_openfst<A: Automaton>(&self, aut: A) -> Stream<A> {
         Stream::new(self, aut)
     }*/
 }
+
+// Nodes expanded between yields to the executor when none is given.
+#[cfg(feature = "futures")]
+pub const DEFAULT_YIELD_EVERY: usize = 1024;
+
+#[cfg(feature = "futures")]
+impl<'f, A: WeightedStateAutomaton> SimpleStateStream<'f, A> {
+    // Yields owned (key, output, weight) matches as a futures Stream, so a
+    // long search doesn't hold up the other tasks on an executor's thread.
+    pub fn into_async(self) -> AsyncWeightedStream<'f, A> {
+        AsyncWeightedStream { stream: self, yield_every: DEFAULT_YIELD_EVERY }
+    }
+}
+
+// Returns Pending and wakes itself up again every yield_every expanded
+// nodes, rather than running until the next match, which for a fuzzy
+// search of a big fst can be a long way off.
+#[cfg(feature = "futures")]
+pub struct AsyncWeightedStream<'f, A> where A: WeightedStateAutomaton {
+    stream: SimpleStateStream<'f, A>,
+    pub yield_every: usize,
+}
+
+// Nothing is pinned in place, it's all behind the stream's Vecs.
+#[cfg(feature = "futures")]
+impl<'f, A: WeightedStateAutomaton> Unpin for AsyncWeightedStream<'f, A> {}
+
+#[cfg(feature = "futures")]
+impl<'f, A: WeightedStateAutomaton> Stream for AsyncWeightedStream<'f, A>
+        where A::State: Clone {
+    type Item = (Vec<u8>, u64, f64);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match this.stream.advance_for(this.yield_every.max(1)) {
+            Step::Found((out, state)) => {
+                let weight = this.stream.aut.get_weight(&state);
+                Poll::Ready(Some((this.stream.inp.clone(), out.value(), weight)))
+            }
+            Step::Done => Poll::Ready(None),
+            Step::Paused => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
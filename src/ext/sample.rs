@@ -0,0 +1,69 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+struct Sampled<T> {
+    // log of the Efraimidis-Spirakis key u^(1/w)
+    key: f64,
+    item: T,
+}
+
+impl<T> PartialEq for Sampled<T> {
+    fn eq(&self, other: &Sampled<T>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Sampled<T> {}
+
+impl<T> PartialOrd for Sampled<T> {
+    fn partial_cmp(&self, other: &Sampled<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Sampled<T> {
+    // reversed so BinaryHeap pops the smallest key first
+    fn cmp(&self, other: &Sampled<T>) -> Ordering {
+        other.key.partial_cmp(&self.key).unwrap_or(Ordering::Equal)
+    }
+}
+
+// Keeps k items out of a stream of any length, each sampled with
+// probability proportional to exp(-weight).
+pub struct Reservoir<T> {
+    size: usize,
+    heap: BinaryHeap<Sampled<T>>,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(size: usize) -> Reservoir<T> {
+        Reservoir { size: size, heap: BinaryHeap::with_capacity(size) }
+    }
+
+    // The item's key for the uniform random number u in (0, 1), if it would
+    // be kept, so that items which wouldn't be needn't be built.
+    fn sample_key(&self, weight: f64, u: f64) -> Option<f64> {
+        if self.size == 0 || weight == ::std::f64::INFINITY {
+            return None;
+        }
+        // ln(u^(1/w)) with w = exp(-weight)
+        let key = u.ln() * weight.exp();
+        match self.heap.peek() {
+            Some(least) if self.heap.len() >= self.size && key <= least.key => None,
+            _ => Some(key),
+        }
+    }
+
+    pub fn offer<F: FnOnce() -> T>(&mut self, weight: f64, u: f64, item: F) {
+        if let Some(key) = self.sample_key(weight, u) {
+            if self.heap.len() >= self.size {
+                self.heap.pop();
+            }
+            self.heap.push(Sampled { key: key, item: item() });
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.heap.into_iter().map(|sampled| sampled.item).collect()
+    }
+}
@@ -0,0 +1,185 @@
+use std::io;
+
+use fst::set::Set;
+use ext::raw;
+use ext::subgraph;
+use ext::subgraph::MatchedSubgraph;
+use ext::raw::FstExt;
+use fst::automaton::{Automaton, AlwaysMatch};
+use fst::Streamer;
+use fst::raw::CompiledAddr;
+
+use adapters::WeightedStateAutomaton;
+
+pub trait SetExt {
+    fn search_state_stream<A: Automaton>(&self, aut: A) -> SimpleStateStream<A>;
+
+    fn search_state_stream_ref<'a, A: Automaton>(&'a self, aut: &'a A)
+            -> SimpleStateStream<'a, &'a A> {
+        self.search_state_stream(aut)
+    }
+
+    fn search_weighted_stream<A: WeightedStateAutomaton>(&self, aut: A)
+        -> WeightedStateStream<A>;
+
+    fn search_cascade_stream<P: Automaton, A: Automaton>(&self, pre: P, aut: A)
+        -> CascadeStream<P, A>;
+
+    fn search_prefix_stream<A: Automaton>(&self, aut: A) -> PrefixStateStream<A>;
+
+    fn count_matches<A: Automaton>(&self, aut: A) -> usize;
+
+    fn any_match<A: Automaton>(&self, aut: A) -> bool;
+
+    fn sample_matches<A, R>(&self, aut: A, k: usize, rng: R) -> Vec<(Vec<u8>, f64)>
+        where A: WeightedStateAutomaton, A::State: Clone, R: FnMut() -> f64;
+
+    fn extract_matches<A, W>(&self, aut: A, wtr: W) -> ::fst::Result<MatchedSubgraph>
+        where A: Automaton, A::State: Clone, W: io::Write;
+
+    fn matched_set<A>(&self, aut: A) -> ::fst::Result<(Set, MatchedSubgraph)>
+        where A: Automaton, A::State: Clone;
+}
+
+impl SetExt for Set {
+    fn search_state_stream<A: Automaton>(&self, aut: A) -> SimpleStateStream<A> {
+        SimpleStateStream(self.as_fst().search_state_stream(aut))
+    }
+
+    fn search_weighted_stream<A: WeightedStateAutomaton>(&self, aut: A)
+            -> WeightedStateStream<A> {
+        WeightedStateStream(self.as_fst().search_weighted_stream(aut))
+    }
+
+    fn search_cascade_stream<P: Automaton, A: Automaton>(&self, pre: P, aut: A)
+            -> CascadeStream<P, A> {
+        CascadeStream(self.as_fst().search_cascade_stream(pre, aut))
+    }
+
+    fn search_prefix_stream<A: Automaton>(&self, aut: A) -> PrefixStateStream<A> {
+        PrefixStateStream(self.as_fst().search_prefix_stream(aut))
+    }
+
+    fn count_matches<A: Automaton>(&self, aut: A) -> usize {
+        self.as_fst().count_matches(aut)
+    }
+
+    fn any_match<A: Automaton>(&self, aut: A) -> bool {
+        self.as_fst().any_match(aut)
+    }
+
+    fn sample_matches<A, R>(&self, aut: A, k: usize, rng: R) -> Vec<(Vec<u8>, f64)>
+            where A: WeightedStateAutomaton, A::State: Clone, R: FnMut() -> f64 {
+        self.as_fst().sample_matches(aut, k, rng).into_iter()
+            .map(|(key, _, weight)| (key, weight)).collect()
+    }
+
+    fn extract_matches<A, W>(&self, aut: A, wtr: W) -> ::fst::Result<MatchedSubgraph>
+            where A: Automaton, A::State: Clone, W: io::Write {
+        subgraph::extract_matches(self.as_fst(), aut, wtr)
+    }
+
+    fn matched_set<A>(&self, aut: A) -> ::fst::Result<(Set, MatchedSubgraph)>
+            where A: Automaton, A::State: Clone {
+        subgraph::matched_set(self.as_fst(), aut)
+    }
+}
+
+pub struct SimpleStateStream<'s, A=AlwaysMatch>(raw::SimpleStateStream<'s, A>) where A: Automaton;
+
+impl<'s, A: Automaton> SimpleStateStream<'s, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+}
+
+impl<'a, 's, A: Automaton> Streamer<'a> for SimpleStateStream<'s, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], A::State);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _, state)| (key, state))
+    }
+}
+
+impl<'s, A: Automaton> SimpleStateStream<'s, A> {
+    // Drops the automaton's states, so the matches can go straight into
+    // SetBuilder::extend_stream or fst's set operations.
+    pub fn into_plain(self) -> PlainStream<'s, A> {
+        PlainStream(self.0.into_plain())
+    }
+}
+
+pub struct PlainStream<'s, A=AlwaysMatch>(raw::PlainStream<'s, A>) where A: Automaton;
+
+impl<'s, A: Automaton> PlainStream<'s, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+}
+
+impl<'a, 's, A: Automaton> Streamer<'a> for PlainStream<'s, A>
+        where A::State: 'a + Clone {
+    type Item = &'a [u8];
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _)| key)
+    }
+}
+
+pub struct WeightedStateStream<'s, A>(raw::WeightedStateStream<'s, A>)
+    where A: WeightedStateAutomaton;
+
+impl<'s, A: WeightedStateAutomaton> WeightedStateStream<'s, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+
+    pub fn into_iter_owned(self, limit: Option<usize>) -> raw::OwnedIter<'s, A> {
+        self.0.into_iter_owned(limit)
+    }
+}
+
+impl<'a, 's, A: WeightedStateAutomaton> Streamer<'a> for WeightedStateStream<'s, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], f64);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _, weight)| (key, weight))
+    }
+}
+
+pub struct CascadeStream<'s, P, A>(raw::CascadeStream<'s, P, A>)
+    where P: Automaton, A: Automaton;
+
+impl<'s, P: Automaton, A: Automaton> CascadeStream<'s, P, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+}
+
+impl<'a, 's, P: Automaton, A: Automaton> Streamer<'a> for CascadeStream<'s, P, A>
+        where P::State: 'a + Clone, A::State: 'a + Clone {
+    type Item = (&'a [u8], A::State);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _, state)| (key, state))
+    }
+}
+
+pub struct PrefixStateStream<'s, A>(raw::PrefixStateStream<'s, A>) where A: Automaton;
+
+impl<'s, A: Automaton> PrefixStateStream<'s, A> {
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.0.set_prefetch(prefetch);
+    }
+}
+
+impl<'a, 's, A: Automaton> Streamer<'a> for PrefixStateStream<'s, A>
+        where A::State: 'a + Clone {
+    type Item = (&'a [u8], A::State, CompiledAddr);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _, state, addr)| (key, state, addr))
+    }
+}
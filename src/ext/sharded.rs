@@ -0,0 +1,59 @@
+use fst::map::Map;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use adapters::{WeightedStateAutomaton, compare_weights};
+use ext::dedup::dedup_vec_by_key_min_weight;
+use ext::map::MapExt;
+use ext::raw::SortKey;
+
+// An index split over several maps, e.g. one per alphabetical partition,
+// searched as one. A key in more than one shard is only given once, with
+// its lightest weight and the output from that shard.
+#[derive(Debug)]
+pub struct ShardedIndex {
+    pub shards: Vec<Map>,
+}
+
+impl ShardedIndex {
+    pub fn new(shards: Vec<Map>) -> ShardedIndex {
+        ShardedIndex { shards: shards }
+    }
+
+    // The k lightest matches over all shards, lightest first, then in key
+    // order. Each shard only has to give its own k lightest, since anything
+    // lighter than a match in the global top k is also lighter in its shard.
+    pub fn search<A>(&self, aut: A, k: usize) -> Vec<(Vec<u8>, u64, f64)>
+            where A: WeightedStateAutomaton, A::State: Clone {
+        let per_shard = self.shards.iter()
+            .map(|shard| shard.search_state_stream(&aut).collect_sorted(Some(k), SortKey::Weight))
+            .collect();
+        merge_top_k(per_shard, k)
+    }
+
+    // The same, searching the shards in parallel. Beam searched automata
+    // can't be shared between threads, so make_aut is called to make one
+    // for each shard.
+    #[cfg(feature = "rayon")]
+    pub fn par_search<A, F>(&self, make_aut: F, k: usize) -> Vec<(Vec<u8>, u64, f64)>
+            where A: WeightedStateAutomaton, A::State: Clone, F: Fn() -> A + Sync {
+        let per_shard = self.shards.par_iter()
+            .map(|shard| {
+                let aut = make_aut();
+                shard.search_state_stream(&aut).collect_sorted(Some(k), SortKey::Weight)
+            })
+            .collect();
+        merge_top_k(per_shard, k)
+    }
+}
+
+fn merge_top_k(per_shard: Vec<Vec<(Vec<u8>, u64, f64)>>, k: usize)
+        -> Vec<(Vec<u8>, u64, f64)> {
+    let mut results: Vec<_> = per_shard.into_iter().flat_map(|shard| shard).collect();
+    dedup_vec_by_key_min_weight(&mut results);
+    // ties have to be broken the same way as within each shard for the top
+    // k of each shard to be enough
+    results.sort_by(|a, b| compare_weights(&a.2, &b.2).then_with(|| a.0.cmp(&b.0)));
+    results.truncate(k);
+    results
+}
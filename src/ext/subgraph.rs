@@ -0,0 +1,50 @@
+use std::collections::HashSet;
+use std::io;
+
+use fst::raw::{Fst, CompiledAddr};
+use fst::set::{Set, SetBuilder};
+use fst::{Automaton, Streamer};
+
+use ext::raw::FstExt;
+
+// The part of an fst a search matched.
+#[derive(Clone, Debug, Default)]
+pub struct MatchedSubgraph {
+    // addresses of the nodes on the path of each matched key, including
+    // the root
+    pub nodes: HashSet<CompiledAddr>,
+    pub keys: u64,
+}
+
+fn add_path(fst: &Fst, key: &[u8], nodes: &mut HashSet<CompiledAddr>) {
+    let mut node = fst.root();
+    nodes.insert(node.addr());
+    for &b in key {
+        let i = node.find_input(b).unwrap();
+        node = fst.node(node.transition_addr(i));
+        nodes.insert(node.addr());
+    }
+}
+
+// Writes a Set of the keys aut matches to wtr. The stream yields keys in
+// order so they can go straight into the builder.
+pub fn extract_matches<A, W>(fst: &Fst, aut: A, wtr: W) -> ::fst::Result<MatchedSubgraph>
+        where A: Automaton, A::State: Clone, W: io::Write {
+    let mut subgraph = MatchedSubgraph::default();
+    let mut builder = SetBuilder::new(wtr)?;
+    let mut stream = fst.search_state_stream(aut);
+    while let Some((key, _, _)) = stream.next() {
+        add_path(fst, key, &mut subgraph.nodes);
+        builder.insert(key)?;
+        subgraph.keys += 1;
+    }
+    builder.finish()?;
+    Ok(subgraph)
+}
+
+pub fn matched_set<A>(fst: &Fst, aut: A) -> ::fst::Result<(Set, MatchedSubgraph)>
+        where A: Automaton, A::State: Clone {
+    let mut bytes = vec![];
+    let subgraph = extract_matches(fst, aut, &mut bytes)?;
+    Ok((Set::from_bytes(bytes)?, subgraph))
+}
@@ -0,0 +1,63 @@
+use std::borrow::Cow;
+
+use hfst::TransducerBox;
+use hfst::symbols::UNKNOWN;
+
+// What to do with query characters which aren't in the error model's
+// alphabet. Composing a query containing them usually gives an empty FSA.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnknownSymbols {
+    Keep,
+    Error,
+    Drop,
+    // replace with @_UNKNOWN_SYMBOL_@, which the error model can then
+    // match with its unknown/identity arcs
+    MapToUnknown,
+}
+
+impl TransducerBox {
+    // The distinct characters of query which aren't in the alphabet, in
+    // order of first appearance.
+    pub fn unknown_symbols(&self, query: &str) -> Vec<String> {
+        let mut unknown: Vec<String> = vec![];
+        let mut buf = [0; 4];
+        for chr in query.chars() {
+            let symbol = chr.encode_utf8(&mut buf);
+            if self.symbols().id(symbol).is_none() &&
+                    !unknown.iter().any(|seen| seen == symbol) {
+                unknown.push(symbol.to_owned());
+            }
+        }
+        unknown
+    }
+
+    // Prepares a query for text_to_denoised_fsa according to how unknown
+    // symbols should be treated. Gives the unknown symbols as the error in
+    // the UnknownSymbols::Error case.
+    pub fn check_query<'q>(&self, query: &'q str, unknown: UnknownSymbols)
+            -> Result<Cow<'q, str>, Vec<String>> {
+        let mut buf = [0; 4];
+        let is_known = |chr: char, buf: &mut [u8; 4]|
+            self.symbols().id(chr.encode_utf8(buf)).is_some();
+        if query.chars().all(|chr| is_known(chr, &mut buf)) {
+            return Ok(Cow::Borrowed(query));
+        }
+        match unknown {
+            UnknownSymbols::Keep => Ok(Cow::Borrowed(query)),
+            UnknownSymbols::Error => Err(self.unknown_symbols(query)),
+            UnknownSymbols::Drop => Ok(Cow::Owned(
+                query.chars().filter(|&chr| is_known(chr, &mut buf)).collect())),
+            UnknownSymbols::MapToUnknown => {
+                let mut mapped = String::with_capacity(query.len());
+                for chr in query.chars() {
+                    if is_known(chr, &mut buf) {
+                        mapped.push(chr);
+                    } else {
+                        mapped.push_str(UNKNOWN);
+                    }
+                }
+                Ok(Cow::Owned(mapped))
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::f32;
+use std::fmt;
+use std::slice;
+use std::str::from_utf8;
+
+use adapters::{WeightedNFA, FollowEpsilonNFA};
+use hfst::HfstBasicTransducerBox;
+use hfst::dump::{DISPLAY_MAX_STATES, write_states};
+use hfst::symbols::{SymbolTable, SymbolBuffer, ByteSet, UNKNOWN, IDENTITY, MAX_SYMBOL_LEN,
+                    is_partial_char};
+use hfst::validate::{WeightError, WeightedArc, check_weights, distances_to_final};
+use quantize::Quantization;
+#[cfg(feature = "profiling")]
+use profile::{Timer, Phase};
+
+#[derive(Copy, Clone, Debug)]
+pub struct CompiledArc {
+    pub input: u32,
+    pub target: u64,
+    pub weight: f32,
+}
+
+// A copy of an HfstBasicTransducerBox in flat Rust Vecs, so that stepping
+// never has to cross into C++.
+#[derive(Clone, Debug)]
+pub struct CompiledHfstGraph {
+    symbols: SymbolTable,
+    // id used for all epsilon arcs
+    epsilon: u32,
+    // id used for all unknown and identity arcs
+    wildcard: u32,
+    has_wildcards: bool,
+    // arcs of state s are arcs[offsets[s]..offsets[s + 1]], sorted by input
+    offsets: Vec<u64>,
+    arcs: Vec<CompiledArc>,
+    finals: Vec<f32>,
+    // the bytes the non-epsilon arcs of each state can start with
+    first_bytes: Vec<ByteSet>,
+}
+
+impl<'a> From<&'a HfstBasicTransducerBox> for CompiledHfstGraph {
+    fn from(graph: &'a HfstBasicTransducerBox) -> CompiledHfstGraph {
+        #[cfg(feature = "profiling")]
+        let _timer = Timer::start(Phase::Conversion);
+        let symbols = graph.symbols().clone();
+        // past the end of the symbol table so it can't clash with a real
+        // symbol which has been configured not to be an epsilon
+        let epsilon = symbols.len() as u32;
+        let wildcard = epsilon + 1;
+        // ignored symbols are left out, so their arcs are dropped below
+        let mut ids: HashMap<u32, u32> = graph.symbol_numbers().iter()
+            .enumerate()
+            .filter(|&(id, _)| !graph.is_ignored(id as u32))
+            .map(|(id, &number)| (number, id as u32)).collect();
+        // all of the graph's epsilon symbols (flag diacritics etc.) are
+        // collapsed into the one epsilon
+        for &number in graph.epsilon_numbers() {
+            ids.insert(number, epsilon);
+        }
+        let mut has_wildcards = false;
+        for symbol in [UNKNOWN, IDENTITY].iter() {
+            if let Some(id) = symbols.id(symbol) {
+                if !graph.is_ignored(id) && !graph.is_epsilon(id) {
+                    ids.insert(graph.symbol_numbers()[id as usize], wildcard);
+                    has_wildcards = true;
+                }
+            }
+        }
+
+        let finals = graph.final_weights();
+        let mut offsets = vec![0; finals.len() + 1];
+        let mut arcs: Vec<(u64, CompiledArc)> = graph.raw_arcs().into_iter()
+            .filter_map(|arc| ids.get(&arc.input).map(|&input| {
+                (arc.source, CompiledArc {
+                    input: input,
+                    target: arc.target,
+                    weight: arc.weight,
+                })
+            })).collect();
+        arcs.sort_by_key(|&(source, ref arc)| (source, arc.input));
+        for &(source, _) in arcs.iter() {
+            offsets[source as usize + 1] += 1;
+        }
+        for state in 0..finals.len() {
+            offsets[state + 1] += offsets[state];
+        }
+        let mut first_bytes = vec![ByteSet::new(); finals.len()];
+        for &(source, ref arc) in arcs.iter() {
+            let bytes = &mut first_bytes[source as usize];
+            if arc.input == wildcard {
+                *bytes = ByteSet::full();
+            } else if arc.input != epsilon {
+                bytes.insert_first_byte(symbols.symbol(arc.input));
+            }
+        }
+
+        CompiledHfstGraph {
+            symbols: symbols,
+            epsilon: epsilon,
+            wildcard: wildcard,
+            has_wildcards: has_wildcards,
+            offsets: offsets,
+            arcs: arcs.into_iter().map(|(_, arc)| arc).collect(),
+            finals: finals,
+            first_bytes: first_bytes,
+        }
+    }
+}
+
+impl CompiledHfstGraph {
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.finals.len()
+    }
+
+    pub fn final_weight(&self, stateno: u64) -> Option<f32> {
+        let weight = *self.finals.get(stateno as usize)?;
+        if weight == f32::INFINITY {
+            None
+        } else {
+            Some(weight)
+        }
+    }
+
+    // Empty for states out of range.
+    pub fn state_arcs(&self, stateno: u64) -> &[CompiledArc] {
+        if stateno as usize >= self.num_states() {
+            return &[];
+        }
+        let start = self.offsets[stateno as usize] as usize;
+        let end = self.offsets[stateno as usize + 1] as usize;
+        &self.arcs[start..end]
+    }
+
+    fn weighted_arcs(&self) -> Vec<WeightedArc> {
+        (0..self.num_states()).flat_map(|state| {
+            self.state_arcs(state as u64).iter().map(move |arc| WeightedArc {
+                source: state as u64,
+                target: arc.target,
+                epsilon: arc.input == self.epsilon,
+                weight: arc.weight,
+            })
+        }).collect()
+    }
+
+    pub fn validate_weights(&self) -> Result<(), WeightError> {
+        check_weights(&self.weighted_arcs(), &self.finals)
+    }
+
+    // Moves weight towards the start so that no arc or final weight is
+    // negative, without changing which paths are lightest. Every complete
+    // path gets lighter by the returned amount, which is infinite if nothing
+    // is accepted.
+    pub fn push_weights(&mut self) -> Result<f32, WeightError> {
+        let distances = distances_to_final(&self.weighted_arcs(), &self.finals)?;
+        for state in 0..self.num_states() {
+            let distance = distances[state];
+            if distance == f32::INFINITY {
+                // can't reach a final state, so nothing here matters
+                continue;
+            }
+            let start = self.offsets[state] as usize;
+            let end = self.offsets[state + 1] as usize;
+            for arc in self.arcs[start..end].iter_mut() {
+                // rounding can leave a little below zero
+                arc.weight = (arc.weight + distances[arc.target as usize] - distance)
+                    .max(0.0);
+            }
+            if self.finals[state] != f32::INFINITY {
+                self.finals[state] = (self.finals[state] - distance).max(0.0);
+            }
+        }
+        Ok(distances.first().cloned().unwrap_or(f32::INFINITY))
+    }
+
+    // Rounds every arc and final weight to what quantization can hold, once
+    // rather than on every step like QuantizedNFA. Weights stay f32, which
+    // holds quantized weights exactly while they're below 2^(24 - frac_bits).
+    // Rounding small epsilon weights down to 0 can make free epsilon cycles,
+    // in which case the weights are left as they were.
+    pub fn quantize_weights(&mut self, quantization: Quantization) -> Result<(), WeightError> {
+        let arcs = self.arcs.clone();
+        let finals = self.finals.clone();
+        for arc in self.arcs.iter_mut() {
+            arc.weight = quantization.quantize(arc.weight as f64) as f32;
+        }
+        for weight in self.finals.iter_mut() {
+            *weight = quantization.quantize(*weight as f64) as f32;
+        }
+        let valid = self.validate_weights();
+        if valid.is_err() {
+            self.arcs = arcs;
+            self.finals = finals;
+        }
+        valid
+    }
+
+    // Arcs from stateno with the given symbol id as input.
+    pub fn arcs(&self, stateno: u64, symbol: u32) -> &[CompiledArc] {
+        let arcs = self.state_arcs(stateno);
+        let start = arcs.partition_point(|arc| arc.input < symbol);
+        let end = arcs.partition_point(|arc| arc.input <= symbol);
+        &arcs[start..end]
+    }
+
+    // Like HfstBasicTransducerBox::dump, with all epsilons shown as one
+    // <epsilon> and unknown and identity arcs as <wildcard>.
+    pub fn dump(&self, max_states: usize) -> String {
+        let mut dumped = String::new();
+        let _ = self.write_dump(&mut dumped, max_states);
+        dumped
+    }
+
+    fn write_dump(&self, f: &mut dyn fmt::Write, max_states: usize) -> fmt::Result {
+        write_states(f, self.num_states(), max_states, |stateno| {
+            let arcs = self.state_arcs(stateno as u64).iter().map(|arc| {
+                let symbol = if arc.input == self.epsilon {
+                    "<epsilon>".to_string()
+                } else if arc.input == self.wildcard {
+                    "<wildcard>".to_string()
+                } else {
+                    self.symbols.symbol(arc.input).to_string()
+                };
+                (symbol, arc.target, arc.weight)
+            }).collect();
+            (self.final_weight(stateno as u64), arcs)
+        })
+    }
+}
+
+impl fmt::Display for CompiledHfstGraph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_dump(f, DISPLAY_MAX_STATES)
+    }
+}
+
+pub enum CompiledNextStates<'a> {
+    Empty,
+    Once(Option<((u64, SymbolBuffer), f64)>),
+    Many(slice::Iter<'a, CompiledArc>),
+    // see HfstNextStates::Both
+    Both(Option<((u64, SymbolBuffer), f64)>, slice::Iter<'a, CompiledArc>),
+}
+
+fn compiled_next_state(arc: &CompiledArc) -> ((u64, SymbolBuffer), f64) {
+    ((arc.target, SymbolBuffer::new()), arc.weight as f64)
+}
+
+impl<'a> Iterator for CompiledNextStates<'a> {
+    type Item = ((u64, SymbolBuffer), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            CompiledNextStates::Empty => None,
+            CompiledNextStates::Once(ref mut next) => next.take(),
+            CompiledNextStates::Many(ref mut arcs) => arcs.next().map(compiled_next_state),
+            CompiledNextStates::Both(ref mut buffered, ref mut arcs) =>
+                buffered.take().or_else(|| arcs.next().map(compiled_next_state)),
+        }
+    }
+}
+
+impl FollowEpsilonNFA for CompiledHfstGraph {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        let &(stateno, ref buf) = state;
+        if !buf.is_empty() {
+            return CompiledNextStates::Empty;
+        }
+        CompiledNextStates::Many(self.arcs(stateno, self.epsilon).iter())
+    }
+}
+
+impl WeightedNFA for CompiledHfstGraph {
+    type State = (u64, SymbolBuffer);
+    type NextStateIter<'a> = CompiledNextStates<'a>;
+    type InputType = u8;
+
+    fn start(&self) -> Self::State {
+        (0, SymbolBuffer::new())
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        let &(stateno, ref buf) = state;
+        buf.is_empty() && self.final_weight(stateno).is_some()
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, byte: u8) -> Self::NextStateIter<'a> {
+        let &(stateno, buf) = state;
+        // the hot path, since most bytes streamed from a big fst don't
+        // start any arc
+        if buf.is_empty() && !self.first_bytes.get(stateno as usize)
+                .map_or(false, |bytes| bytes.contains(byte)) {
+            return CompiledNextStates::Empty;
+        }
+        let mut new_buf = buf;
+        new_buf.push(byte);
+        // see HfstBasicTransducerBox::symbol_arcs
+        let symbol = from_utf8(new_buf.as_slice()).ok()
+            .and_then(|symbol| match self.symbols.id(symbol) {
+                Some(id) => Some(id),
+                None if symbol.chars().count() == 1 => Some(self.wildcard),
+                None => None,
+            });
+        let arcs = symbol.map(|symbol| self.arcs(stateno, symbol))
+            .filter(|arcs| !arcs.is_empty());
+        // see HfstBasicTransducerBox::is_symbol_prefix
+        let bytes = new_buf.as_slice();
+        let buffered = if bytes.len() < MAX_SYMBOL_LEN && (
+                self.symbols.is_proper_prefix(bytes) ||
+                (self.has_wildcards && is_partial_char(bytes))) {
+            Some(((stateno, new_buf), 0.0))
+        } else {
+            None
+        };
+        // see HfstBasicTransducerBox::accept
+        match (buffered, arcs) {
+            (Some(buffered), Some(arcs)) => CompiledNextStates::Both(Some(buffered), arcs.iter()),
+            (None, Some(arcs)) => CompiledNextStates::Many(arcs.iter()),
+            (buffered, None) => CompiledNextStates::Once(buffered),
+        }
+    }
+}
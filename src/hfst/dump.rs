@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::f32;
+use std::fmt;
+
+use hfst::HfstBasicTransducerBox;
+
+// How many states Display shows before eliding the rest.
+pub const DISPLAY_MAX_STATES: usize = 100;
+
+// One arc as it's dumped: symbol, target and weight.
+pub type DumpArc = (String, u64, f32);
+
+// Writes out states one per line, each followed by its arcs indented, e.g.
+//
+//   0 (final 1.5)
+//     a -> 1 / 0.5
+//   1
+//
+// stopping after max_states states.
+pub fn write_states<F>(f: &mut dyn fmt::Write, num_states: usize, max_states: usize,
+                       mut state: F) -> fmt::Result
+        where F: FnMut(usize) -> (Option<f32>, Vec<DumpArc>) {
+    for stateno in 0..num_states.min(max_states) {
+        let (final_weight, arcs) = state(stateno);
+        match final_weight {
+            Some(weight) => writeln!(f, "{} (final {})", stateno, weight)?,
+            None => writeln!(f, "{}", stateno)?,
+        }
+        for (symbol, target, weight) in arcs {
+            writeln!(f, "  {} -> {} / {}", symbol, target, weight)?;
+        }
+    }
+    if num_states > max_states {
+        writeln!(f, "... {} more states", num_states - max_states)?;
+    }
+    Ok(())
+}
+
+impl HfstBasicTransducerBox {
+    // The states, final weights and arcs of the first max_states states,
+    // for looking at small compositions without going through ATT files.
+    pub fn dump(&self, max_states: usize) -> String {
+        let mut dumped = String::new();
+        let _ = self.write_dump(&mut dumped, max_states);
+        dumped
+    }
+
+    fn write_dump(&self, f: &mut dyn fmt::Write, max_states: usize) -> fmt::Result {
+        let names: HashMap<u32, &str> = self.symbol_numbers().iter()
+            .zip(self.symbols().symbols())
+            .map(|(&number, symbol)| (number, symbol.as_str()))
+            .collect();
+        let finals = self.final_weights();
+        let mut arcs = self.raw_arcs().into_iter().peekable();
+        write_states(f, finals.len(), max_states, |stateno| {
+            let mut state_arcs = vec![];
+            while let Some(arc) = arcs.peek().cloned() {
+                if arc.source as usize > stateno {
+                    break;
+                }
+                arcs.next();
+                let symbol = names.get(&arc.input).map_or_else(
+                    || format!("#{}", arc.input), |name| name.to_string());
+                state_arcs.push((symbol, arc.target, arc.weight));
+            }
+            let weight = finals[stateno];
+            (if weight == f32::INFINITY { None } else { Some(weight) }, state_arcs)
+        })
+    }
+}
+
+impl fmt::Display for HfstBasicTransducerBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_dump(f, DISPLAY_MAX_STATES)
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::thread::{self, JoinHandle};
+
+use fst::Map;
+
+use config::SearchConfig;
+use error::{Error, Result};
+use hfst::{TransducerBox, CompiledHfstGraph, Suggestion, Direction, mk_stack};
+
+struct Job {
+    query: String,
+    max_suggestions: usize,
+    direction: Direction,
+    determinize: bool,
+    reply: Sender<Result<Vec<Suggestion>>>,
+}
+
+// A composed query on its way to a search worker.
+struct Search {
+    compiled: CompiledHfstGraph,
+    max_suggestions: usize,
+    reply: Sender<Result<Vec<Suggestion>>>,
+}
+
+fn compose(model: &TransducerBox, job: &Job) -> Result<CompiledHfstGraph> {
+    let graph = model.text_to_denoised_fsa(&job.query, job.direction, job.determinize, false)?;
+    Ok(CompiledHfstGraph::from(&graph))
+}
+
+// Answers queries against one error model and map, e.g. for a server. HFST
+// isn't thread safe, so the model is loaded on and only used by one thread,
+// which composes each query and compiles its graph. The compiled graphs are
+// plain Rust, so they're searched on a pool of worker threads.
+pub struct QueryExecutor {
+    jobs: Option<Sender<Job>>,
+    composer: Option<JoinHandle<()>>,
+    workers: Vec<JoinHandle<()>>,
+    pub max_suggestions: usize,
+    pub direction: Direction,
+    pub determinize: bool,
+}
+
+impl QueryExecutor {
+    // load is run on the composing thread, e.g. TransducerBox::from_file
+    // with the model's path, and its error returned if it fails.
+    pub fn new<F>(load: F, map: Arc<Map>, search: SearchConfig, num_workers: usize)
+            -> Result<QueryExecutor>
+                where F: FnOnce() -> Result<TransducerBox> + Send + 'static {
+        search.validate()?;
+        if num_workers == 0 {
+            return Err(Error::InvalidConfig("no workers".to_owned()));
+        }
+        let (jobs, job_queue) = mpsc::channel::<Job>();
+        let (searches, search_queue) = mpsc::channel::<Search>();
+        let (loaded_tx, loaded) = mpsc::channel();
+        let composer = thread::spawn(move || {
+            let model = match load() {
+                Ok(model) => {
+                    let _ = loaded_tx.send(Ok(()));
+                    model
+                }
+                Err(err) => {
+                    let _ = loaded_tx.send(Err(err));
+                    return;
+                }
+            };
+            // ends once the executor is dropped, which then ends the workers
+            for job in job_queue {
+                match compose(&model, &job) {
+                    Ok(compiled) => {
+                        let search = Search {
+                            compiled: compiled,
+                            max_suggestions: job.max_suggestions,
+                            reply: job.reply,
+                        };
+                        if searches.send(search).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => {
+                        // the submitter may have stopped waiting
+                        let _ = job.reply.send(Err(err));
+                    }
+                }
+            }
+        });
+        loaded.recv().unwrap_or(Err(Error::Cancelled))?;
+        let search_queue = Arc::new(Mutex::new(search_queue));
+        let search = Arc::new(search);
+        let workers = (0..num_workers).map(|_| {
+            let map = map.clone();
+            let search = search.clone();
+            let search_queue = search_queue.clone();
+            thread::spawn(move || loop {
+                // the queue's lock is only held while waiting, not working,
+                // and the receiver is still fine if another worker panicked
+                // holding it
+                let queue = search_queue.lock().unwrap_or_else(|err| err.into_inner());
+                let Search { compiled, max_suggestions, reply } = match queue.recv() {
+                    Ok(job) => job,
+                    // the composer has stopped
+                    Err(_) => return,
+                };
+                drop(queue);
+                let suggestions = mk_stack(compiled, &search)
+                    .map(|aut| aut.suggest(&map, max_suggestions));
+                let _ = reply.send(suggestions);
+            })
+        }).collect();
+        Ok(QueryExecutor {
+            jobs: Some(jobs),
+            composer: Some(composer),
+            workers: workers,
+            max_suggestions: 5,
+            direction: Direction::Down,
+            determinize: false,
+        })
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Queues a query, returning where its suggestions, best first, will be
+    // sent.
+    pub fn submit(&self, query: &str) -> Receiver<Result<Vec<Suggestion>>> {
+        let (reply, suggestions) = mpsc::channel();
+        let job = Job {
+            query: query.to_owned(),
+            max_suggestions: self.max_suggestions,
+            direction: self.direction,
+            determinize: self.determinize,
+            reply: reply,
+        };
+        if let Err(mpsc::SendError(job)) = self.jobs.as_ref().unwrap().send(job) {
+            // the composer has died, e.g. by panicking
+            let _ = job.reply.send(Err(Error::Cancelled));
+        }
+        suggestions
+    }
+
+    // Submits the query and waits for it.
+    pub fn suggest(&self, query: &str) -> Result<Vec<Suggestion>> {
+        self.submit(query).recv().unwrap_or(Err(Error::Cancelled))
+    }
+}
+
+// Lets queued queries finish, then stops the composer and workers.
+impl Drop for QueryExecutor {
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(composer) = self.composer.take() {
+            let _ = composer.join();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,1164 @@
+cpp!({
+    #include <algorithm>
+    #include <cinttypes>
+    #include <limits>
+
+    #include "hfst/HfstTransducer.h"
+    #include "hfst/HfstInputStream.h"
+    #include "hfst/HfstOutputStream.h"
+    /*#include "hfst/implementations/HfstTransitionGraph.h"*/
+    #include "hfst/implementations/HfstBasicTransducer.h"
+
+    using namespace hfst;
+    using hfst::HfstTransducer;
+    using hfst::HfstInputStream;
+    using hfst::HfstOutputStream;
+    using hfst::implementations::HfstState;
+    using hfst::implementations::HfstBasicTransducer;
+    using hfst::implementations::HfstBasicTransition;
+    using hfst::implementations::HfstBasicTransitions;
+    using hfst::implementations::HfstTropicalTransducerTransitionData;
+
+    #include <execinfo.h>
+});
+
+use std::os::raw::c_void;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::iter::Peekable;
+use std::ptr;
+use std::slice;
+use error::{Error, Result};
+use hfst::normalize::{QueryNormalizer, NormalizedQuery};
+use hfst::symbols::{SymbolTable, SymbolBuffer, ByteSet, EPSILON, UNKNOWN, IDENTITY, MAX_SYMBOL_LEN,
+                    is_flag_diacritic, is_partial_char};
+#[cfg(feature = "profiling")]
+use profile::{Timer, Phase};
+
+// Copies out and frees a std::string allocated on the C++ side.
+unsafe fn take_string(string: *mut c_void) -> Vec<u8> {
+    let mut data: *const u8 = ptr::null();
+    let mut len: u64 = 0;
+    cpp!([string as "std::string*", mut data as "const char*", mut len as "uint64_t"] {
+        data = string->data();
+        len = string->size();
+    });
+    let bytes = slice::from_raw_parts(data, len as usize).to_vec();
+    cpp!([string as "std::string*"] {
+        delete string;
+    });
+    bytes
+}
+
+// A fresh path for a temporary .hfst file, since HFST's streams can only
+// deal with named files.
+fn spool_path() -> PathBuf {
+    static SPOOLED: AtomicUsize = AtomicUsize::new(0);
+    env::temp_dir().join(format!(
+        "fst-extra-aut-{}-{}.hfst",
+        process::id(), SPOOLED.fetch_add(1, Ordering::SeqCst)))
+}
+
+fn path_str(path: &PathBuf) -> io::Result<&str> {
+    path.to_str().ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput, "temporary path isn't UTF-8"))
+}
+
+// HFST backends a TransducerBox can be converted to. The optimized lookup
+// formats are left out since they can't be composed with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Implementation {
+    TropicalOpenFst,
+    LogOpenFst,
+    Foma,
+    Sfst,
+}
+
+// Which end of the transducer push_weights moves weights towards.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WeightPush {
+    ToInitial,
+    ToFinal,
+}
+
+// Which way a transducer is applied to a query. Down reads the query off
+// its input side, as for error models. Up reads it off the output side,
+// as for analysers written to generate surface forms, and is the same as
+// applying the inverted transducer down.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Down,
+    Up,
+}
+
+pub struct TransducerBox {
+    transducer: *mut c_void,
+    symbols: SymbolTable,
+    normalizer: Option<QueryNormalizer>,
+}
+
+fn c_path(filename: &str) -> io::Result<CString> {
+    CString::new(filename).map_err(|_| io::Error::new(
+        io::ErrorKind::InvalidInput, "path has a NUL in it"))
+}
+
+// Splits a std::string of NUL terminated symbols.
+fn split_symbols(joined: Vec<u8>) -> Vec<String> {
+    joined.split(|&byte| byte == 0)
+        .filter(|symbol| !symbol.is_empty())
+        .map(|symbol| String::from_utf8_lossy(symbol).into_owned())
+        .collect()
+}
+
+impl TransducerBox {
+    fn new(transducer: *mut c_void) -> TransducerBox {
+        let mut transducer = TransducerBox {
+            transducer: transducer,
+            symbols: SymbolTable::new(),
+            normalizer: None,
+        };
+        transducer.symbols = transducer.alphabet().into_iter().collect();
+        transducer
+    }
+
+    pub fn alphabet(&self) -> Vec<String> {
+        let transducer = self.transducer;
+        let joined;
+        unsafe {
+            joined = take_string(cpp!([transducer as "HfstTransducer*"] -> *mut c_void as "std::string*" {
+                std::string *joined = new std::string();
+                StringSet alphabet = transducer->get_alphabet();
+                for (StringSet::const_iterator it = alphabet.begin(); it != alphabet.end(); it++) {
+                    joined->append(*it);
+                    joined->push_back('\0');
+                }
+                return joined;
+            }));
+        }
+        split_symbols(joined)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    // Sets how queries are normalized by text_to_denoised_fsa.
+    pub fn set_normalizer(&mut self, normalizer: Option<QueryNormalizer>) {
+        self.normalizer = normalizer;
+    }
+
+    pub fn from_file(filename: &str) -> Result<TransducerBox> {
+        let filename_cp = c_path(filename)?;
+        let filename_ptr = filename_cp.as_ptr();
+        let transducer = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([filename_ptr as "const char*"] -> *mut c_void as "HfstTransducer*" {
+                try {
+                    HfstInputStream ins(filename_ptr);
+                    return new HfstTransducer(ins);
+                } catch (...) {
+                    return NULL;
+                }
+            })
+        };
+        if transducer.is_null() {
+            return Err(Error::Load(filename.to_owned()));
+        }
+        Ok(TransducerBox::new(transducer))
+    }
+
+    // The reader is spooled to a temporary file first.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<TransducerBox> {
+        let path = spool_path();
+        let result = OpenOptions::new().write(true).create_new(true).open(&path)
+            .and_then(|mut file| io::copy(&mut reader, &mut file))
+            .and_then(|_| path_str(&path))
+            .map_err(Error::from)
+            .and_then(TransducerBox::from_file);
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    pub fn write_to_file(&self, filename: &str) -> Result<()> {
+        let filename_cp = c_path(filename)?;
+        let filename_ptr = filename_cp.as_ptr();
+        let transducer = self.transducer;
+        let written = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([filename_ptr as "const char*", transducer as "HfstTransducer*"] -> bool as "bool" {
+                try {
+                    HfstOutputStream out(filename_ptr, transducer->get_type());
+                    out << *transducer;
+                    out.close();
+                    return true;
+                } catch (...) {
+                    return false;
+                }
+            })
+        };
+        if written {
+            Ok(())
+        } else {
+            Err(Error::Io(io::Error::new(io::ErrorKind::Other, "couldn't write transducer")))
+        }
+    }
+
+    // Goes through a temporary file like from_reader.
+    pub fn write_to_bytes(&self) -> Result<Vec<u8>> {
+        let path = spool_path();
+        let result = path_str(&path).map_err(Error::from)
+            .and_then(|filename| self.write_to_file(filename))
+            .and_then(|_| fs::read(&path).map_err(Error::from));
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    // The transducer itself as a graph, e.g. to compose with lazily.
+    pub fn to_basic(&self) -> Result<HfstBasicTransducerBox> {
+        #[cfg(feature = "profiling")]
+        let _timer = Timer::start(Phase::Conversion);
+        let transducer = self.transducer;
+        let graph = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([transducer as "HfstTransducer*"] -> *mut c_void as "HfstBasicTransducer*" {
+                try {
+                    return new HfstBasicTransducer(*transducer);
+                } catch (...) {
+                    return NULL;
+                }
+            })
+        };
+        if graph.is_null() {
+            Err(Error::Convert)
+        } else {
+            Ok(HfstBasicTransducerBox::new(graph))
+        }
+    }
+
+    pub fn convert(&mut self, implementation: Implementation) -> Result<()> {
+        let implementation = match implementation {
+            Implementation::TropicalOpenFst => 0,
+            Implementation::LogOpenFst => 1,
+            Implementation::Foma => 2,
+            Implementation::Sfst => 3,
+        };
+        let transducer = self.transducer;
+        let done = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([transducer as "HfstTransducer*", implementation as "uint32_t"] -> bool as "bool" {
+                ImplementationType types[] = {
+                    TROPICAL_OPENFST_TYPE, LOG_OPENFST_TYPE, FOMA_TYPE, SFST_TYPE
+                };
+                try {
+                    transducer->convert(types[implementation]);
+                    return true;
+                } catch (...) {
+                    return false;
+                }
+            })
+        };
+        transformed(done)
+    }
+
+    pub fn push_weights(&mut self, push: WeightPush) -> Result<()> {
+        let to_final = push == WeightPush::ToFinal;
+        let transducer = self.transducer;
+        let done = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([transducer as "HfstTransducer*", to_final as "bool"] -> bool as "bool" {
+                try {
+                    transducer->push_weights(to_final ? TO_FINAL_STATE : TO_INITIAL_STATE);
+                    return true;
+                } catch (...) {
+                    return false;
+                }
+            })
+        };
+        transformed(done)
+    }
+
+    pub fn remove_epsilons(&mut self) -> Result<()> {
+        let transducer = self.transducer;
+        let done = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([transducer as "HfstTransducer*"] -> bool as "bool" {
+                try {
+                    transducer->remove_epsilons();
+                    return true;
+                } catch (...) {
+                    return false;
+                }
+            })
+        };
+        transformed(done)
+    }
+
+    pub fn minimize(&mut self) -> Result<()> {
+        let transducer = self.transducer;
+        let done = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([transducer as "HfstTransducer*"] -> bool as "bool" {
+                try {
+                    transducer->minimize();
+                    return true;
+                } catch (...) {
+                    return false;
+                }
+            })
+        };
+        transformed(done)
+    }
+
+    // Swaps the input and output sides, e.g. to apply a transducer up for
+    // many queries without it being copied for each of them.
+    pub fn invert(&mut self) -> Result<()> {
+        let transducer = self.transducer;
+        let done = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([transducer as "HfstTransducer*"] -> bool as "bool" {
+                try {
+                    transducer->invert();
+                    return true;
+                } catch (...) {
+                    return false;
+                }
+            })
+        };
+        transformed(done)
+    }
+
+    // Rebuilds the symbol table and has HFST number every symbol now rather
+    // than on the first query.
+    pub fn cache_symbols(&mut self) {
+        self.symbols = self.alphabet().into_iter().collect();
+        for symbol in self.symbols.symbols() {
+            symbol_number(symbol);
+        }
+    }
+
+    // Applying the model up copies it for each query, so for many queries
+    // it's quicker to invert it once and apply it down.
+    pub fn text_to_denoised_fsa(&self, query: &str, direction: Direction,
+                                determinize: bool, trace: bool)
+            -> Result<HfstBasicTransducerBox> {
+        self.compose_query(query, direction, ptr::null_mut(), Direction::Down,
+                           determinize, trace)
+    }
+
+    // Like text_to_denoised_fsa, but the corrections are then run through
+    // the analyser, so the graph is of their analyses, e.g. lemmas and tags.
+    // The model is applied down and the analyser in direction.
+    pub fn text_to_analysed_fsa(&self, query: &str, analyser: &TransducerBox,
+                                direction: Direction, determinize: bool, trace: bool)
+            -> Result<HfstBasicTransducerBox> {
+        self.compose_query(query, Direction::Down, analyser.transducer, direction,
+                           determinize, trace)
+    }
+
+    // The analyser is skipped when it's null.
+    fn compose_query(&self, query: &str, direction: Direction, analyser: *mut c_void,
+                     analyser_direction: Direction, determinize: bool, trace: bool)
+            -> Result<HfstBasicTransducerBox> {
+        #[cfg(feature = "profiling")]
+        let _timer = Timer::start(Phase::Composition);
+        let normalized = self.normalizer.as_ref()
+            .map(|normalizer| normalizer.normalize(query));
+        let query = normalized.as_ref()
+            .map_or(query, |normalized| normalized.normalized.as_str());
+        // passed with its length rather than as a C string, so a NUL in the
+        // query can't cut it short
+        let query_ptr = query.as_ptr();
+        let query_len = query.len() as u64;
+        let err_model = self.transducer;
+        let model_up = direction == Direction::Up;
+        let analyser_up = analyser_direction == Direction::Up;
+        let graph;
+        unsafe {
+            let _hfst = hfst_lock();
+            graph = cpp!([
+                    query_ptr as "const char*",
+                    query_len as "uint64_t",
+                    err_model as "HfstTransducer*",
+                    model_up as "bool",
+                    analyser as "HfstTransducer*",
+                    analyser_up as "bool",
+                    determinize as "bool",
+                    trace as "bool"] -> *mut c_void as "HfstBasicTransducer*" {
+                try {
+                    // 1. Create automaton for query
+                    if (trace) {
+                        fprintf(stderr, "1. Create automaton for query\n");
+                        fflush(stderr);
+                    }
+                    std::string query_str(query_ptr, query_len);
+                    HfstTokenizer tok;
+                    tok.add_multichar_symbol("@_UNKNOWN_SYMBOL_@");
+                    // composing needs both sides to be of the same type, so
+                    // the query follows the model, whatever it's converted to
+                    ImplementationType t = err_model->get_type();
+                    HfstTransducer query_fsa(query_str, tok, t);
+                    // 2. Compose with error model
+                    if (trace) {
+                        fprintf(stderr, "2. Compose with error model\n");
+                        fflush(stderr);
+                    }
+                    if (model_up) {
+                        HfstTransducer inverted(*err_model);
+                        inverted.invert();
+                        query_fsa.compose(inverted);
+                    } else {
+                        query_fsa.compose(*err_model);
+                    }
+                    // 3. Project output side
+                    if (trace) {
+                        fprintf(stderr, "3. Project output side\n");
+                        fflush(stderr);
+                    }
+                    query_fsa.output_project();
+                    if (analyser) {
+                        // 3b. Analyse the corrections
+                        if (trace) {
+                            fprintf(stderr, "3b. Analyse the corrections\n");
+                            fflush(stderr);
+                        }
+                        if (analyser_up || analyser->get_type() != t) {
+                            HfstTransducer copied(*analyser);
+                            copied.convert(t);
+                            if (analyser_up) {
+                                copied.invert();
+                            }
+                            query_fsa.compose(copied);
+                        } else {
+                            query_fsa.compose(*analyser);
+                        }
+                        query_fsa.output_project();
+                    }
+                    // 4. Use n best to remove low weight outputs (could use weighted version instead...)
+                    //printf("4. Use n best to remove low weight outputs (could use weighted version instead...)\n");
+                    //fflush(stdout);
+
+                    // query_fsa.n_best(n_best);
+
+                    if (determinize) {
+                        // 5. (determinize?)
+                        if (trace) {
+                            fprintf(stderr, "5. (determinize?)\n");
+                            fflush(stderr);
+                        }
+                        query_fsa.determinize();
+                    }
+                    // 6. Convert to HfstBasicTransducer
+                    if (trace) {
+                        fprintf(stderr, "6. Convert to HfstBasicTransducer\n");
+                        fflush(stderr);
+                    }
+                    HfstBasicTransducer *hbt = new HfstBasicTransducer(query_fsa);
+                    return hbt;
+                } catch (HfstException e) {
+                    fprintf(stderr, "Exception: %s\n", e().c_str());
+                    fflush(stderr);
+
+                    return NULL;
+                }
+            });
+        }
+        if graph.is_null() {
+            return Err(Error::Compose);
+        }
+        let mut graph = HfstBasicTransducerBox::new(graph);
+        graph.query = normalized;
+        Ok(graph)
+    }
+}
+
+// Copies the transducer with HFST's copy constructor, e.g. to give each
+// thread its own for composing a batch of queries in parallel, without
+// reading the file again.
+impl Clone for TransducerBox {
+    fn clone(&self) -> TransducerBox {
+        let transducer = self.transducer;
+        let copy = unsafe {
+            let _hfst = hfst_lock();
+            cpp!([transducer as "HfstTransducer*"] -> *mut c_void as "HfstTransducer*" {
+                return new HfstTransducer(*transducer);
+            })
+        };
+        TransducerBox {
+            transducer: copy,
+            symbols: self.symbols.clone(),
+            normalizer: self.normalizer.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for TransducerBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TransducerBox")
+            .field("symbols", &self.symbols)
+            .field("normalizer", &self.normalizer)
+            .finish()
+    }
+}
+
+impl Drop for TransducerBox {
+    fn drop(&mut self) {
+        let fst = self.transducer;
+        unsafe {
+            cpp!([fst as "HfstTransducer*"] {
+                delete fst;
+            });
+        }
+    }
+}
+
+pub struct HfstBasicTransducerBox {
+    graph: *mut c_void,
+    symbols: SymbolTable,
+    // HFST's own number for each symbol in `symbols`
+    numbers: Vec<u32>,
+    // ids of the symbols followed by follow_epsilon
+    epsilon_ids: Vec<u32>,
+    // whether arcs with each symbol should be skipped entirely
+    ignored: Vec<bool>,
+    // numbers of the epsilon_ids which aren't ignored
+    epsilons: Vec<u32>,
+    // numbers of the unknown and identity symbols which aren't ignored
+    wildcards: Vec<u32>,
+    // the query this graph was made from, if it was normalized
+    query: Option<NormalizedQuery>,
+    // the bytes the arcs of each state can start with
+    first_bytes: Vec<ByteSet>,
+}
+
+// HFST numbers symbols through one table for the whole process, which
+// loading, copying, converting and composing transducers can all add to, so
+// those are made one at a time whichever thread they're on. Nothing else
+// about HFST is known to be thread safe, so the boxes themselves aren't Send.
+static HFST_LOCK: Mutex<()> = Mutex::new(());
+
+fn hfst_lock() -> MutexGuard<'static, ()> {
+    // only HFST is guarded, so a panic while holding the lock leaves nothing
+    // half updated on this side
+    HFST_LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// HFST's in place operations throw on failure.
+fn transformed(done: bool) -> Result<()> {
+    if done {
+        Ok(())
+    } else {
+        Err(Error::Convert)
+    }
+}
+
+fn symbol_number(symbol: &str) -> u32 {
+    let symbol_ptr = symbol.as_ptr();
+    let symbol_len = symbol.len() as u64;
+    unsafe {
+        let _hfst = hfst_lock();
+        cpp!([symbol_ptr as "const char*", symbol_len as "uint64_t"] -> u32 as "uint32_t" {
+            return HfstTropicalTransducerTransitionData::get_number(
+                std::string(symbol_ptr, symbol_len));
+        })
+    }
+}
+
+impl HfstBasicTransducerBox {
+    fn new(graph: *mut c_void) -> HfstBasicTransducerBox {
+        let mut graph = HfstBasicTransducerBox {
+            graph: graph,
+            symbols: SymbolTable::new(),
+            numbers: vec![],
+            epsilon_ids: vec![],
+            ignored: vec![],
+            epsilons: vec![],
+            wildcards: vec![],
+            query: None,
+            first_bytes: vec![],
+        };
+        graph.sort_transitions();
+        graph.symbols = graph.alphabet().into_iter().collect();
+        graph.numbers = graph.symbols.symbols().iter()
+            .map(|symbol| symbol_number(symbol)).collect();
+        graph.ignored = vec![false; graph.symbols.len()];
+        // flag diacritics are treated as epsilons, as in HFST's lookup
+        let epsilon_symbols: Vec<String> = graph.symbols.symbols().iter()
+            .filter(|symbol| *symbol == EPSILON || is_flag_diacritic(symbol))
+            .cloned().collect();
+        graph.set_epsilon_symbols(epsilon_symbols);
+        graph.first_bytes = graph.collect_first_bytes();
+        graph
+    }
+
+    // Sorts the transitions of every state by input number, which HfstArcs
+    // and HfstPairArcs rely on to binary search.
+    fn sort_transitions(&mut self) {
+        let graph = self.graph;
+        unsafe {
+            cpp!([graph as "HfstBasicTransducer*"] {
+                for (HfstState s = 0; s <= graph->get_max_state(); s++) {
+                    // HFST only hands out const transitions, but they belong
+                    // to the graph, which isn't const
+                    HfstBasicTransitions &transitions =
+                        const_cast<HfstBasicTransitions &>((*graph)[s]);
+                    std::stable_sort(transitions.begin(), transitions.end(),
+                                     transition_input_less);
+                }
+            });
+        }
+    }
+
+    // Goes over every arc, whatever is later configured as epsilon or
+    // ignored, so stays a superset of what can really be matched.
+    fn collect_first_bytes(&self) -> Vec<ByteSet> {
+        let names: HashMap<u32, &str> = self.numbers.iter()
+            .zip(self.symbols.symbols())
+            .map(|(&number, symbol)| (number, symbol.as_str()))
+            .collect();
+        let mut first_bytes = vec![ByteSet::new(); self.num_states() as usize];
+        for arc in self.raw_arcs() {
+            let bytes = &mut first_bytes[arc.source as usize];
+            match names.get(&arc.input) {
+                Some(symbol) => bytes.insert_first_byte(symbol),
+                None => *bytes = ByteSet::full(),
+            }
+        }
+        first_bytes
+    }
+
+    // Empty for states out of range.
+    pub fn first_bytes(&self, stateno: u64) -> ByteSet {
+        self.first_bytes.get(stateno as usize).cloned().unwrap_or_default()
+    }
+
+    fn resolve_epsilons(&mut self) {
+        self.epsilons = self.epsilon_ids.iter()
+            .filter(|&&id| !self.ignored[id as usize])
+            .map(|&id| self.numbers[id as usize]).collect();
+        self.wildcards = [UNKNOWN, IDENTITY].iter()
+            .filter_map(|symbol| self.symbols.id(symbol))
+            .filter(|&id| !self.ignored[id as usize])
+            .map(|id| self.numbers[id as usize]).collect();
+    }
+
+    // Sets which symbols are followed by follow_epsilon, e.g. to add <eps>
+    // for models which use it. Defaults to HFST's epsilon symbol and all
+    // flag diacritics. Symbols not in the alphabet are skipped.
+    pub fn set_epsilon_symbols<I, S>(&mut self, symbols: I)
+            where I: IntoIterator<Item=S>, S: AsRef<str> {
+        self.epsilon_ids = symbols.into_iter()
+            .filter_map(|symbol| self.symbols.id(symbol.as_ref())).collect();
+        self.resolve_epsilons();
+    }
+
+    // Adds to the symbols followed by follow_epsilon, keeping the others.
+    pub fn add_epsilon_symbols<I, S>(&mut self, symbols: I)
+            where I: IntoIterator<Item=S>, S: AsRef<str> {
+        for symbol in symbols {
+            if let Some(id) = self.symbols.id(symbol.as_ref()) {
+                if !self.epsilon_ids.contains(&id) {
+                    self.epsilon_ids.push(id);
+                }
+            }
+        }
+        self.resolve_epsilons();
+    }
+
+    // Sets symbols whose arcs are never taken, neither as input nor as
+    // epsilons.
+    pub fn set_ignored_symbols<I, S>(&mut self, symbols: I)
+            where I: IntoIterator<Item=S>, S: AsRef<str> {
+        self.ignored = vec![false; self.symbols.len()];
+        for symbol in symbols {
+            if let Some(id) = self.symbols.id(symbol.as_ref()) {
+                self.ignored[id as usize] = true;
+            }
+        }
+        self.resolve_epsilons();
+    }
+
+    pub fn is_epsilon(&self, id: u32) -> bool {
+        !self.ignored[id as usize] && self.epsilon_ids.contains(&id)
+    }
+
+    pub fn is_ignored(&self, id: u32) -> bool {
+        self.ignored[id as usize]
+    }
+
+    pub fn alphabet(&self) -> Vec<String> {
+        let graph = self.graph;
+        let joined;
+        unsafe {
+            joined = take_string(cpp!([graph as "HfstBasicTransducer*"] -> *mut c_void as "std::string*" {
+                std::string *joined = new std::string();
+                const auto &alphabet = graph->get_alphabet();
+                for (auto it = alphabet.begin(); it != alphabet.end(); it++) {
+                    joined->append(*it);
+                    joined->push_back('\0');
+                }
+                return joined;
+            }));
+        }
+        split_symbols(joined)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    // Used to map results back to the query as it was before normalization.
+    pub fn query(&self) -> Option<&NormalizedQuery> {
+        self.query.as_ref()
+    }
+
+    // HFST's number for each symbol id in symbols()
+    pub fn symbol_numbers(&self) -> &[u32] {
+        self.numbers.as_slice()
+    }
+
+    pub fn epsilon_numbers(&self) -> &[u32] {
+        self.epsilons.as_slice()
+    }
+
+    pub fn num_states(&self) -> u64 {
+        let graph = self.graph;
+        unsafe {
+            cpp!([graph as "HfstBasicTransducer*"] -> u64 as "uint64_t" {
+                return graph->get_max_state() + 1;
+            })
+        }
+    }
+
+    pub fn num_arcs(&self) -> u64 {
+        let graph = self.graph;
+        unsafe {
+            cpp!([graph as "HfstBasicTransducer*"] -> u64 as "uint64_t" {
+                uint64_t num_arcs = 0;
+                for (HfstState s = 0; s <= graph->get_max_state(); s++) {
+                    num_arcs += (*graph)[s].size();
+                }
+                return num_arcs;
+            })
+        }
+    }
+
+    // Every arc in the graph, ordered by source state, in one pass.
+    pub fn raw_arcs(&self) -> Vec<RawArc> {
+        let graph = self.graph;
+        let num_arcs = self.num_arcs() as usize;
+        let mut arcs: Vec<RawArc> = Vec::with_capacity(num_arcs);
+        let arcs_ptr = arcs.as_mut_ptr();
+        unsafe {
+            cpp!([graph as "HfstBasicTransducer*", arcs_ptr as "struct RawArc*"] {
+                uint64_t i = 0;
+                for (HfstState s = 0; s <= graph->get_max_state(); s++) {
+                    const HfstBasicTransitions &transitions = (*graph)[s];
+                    for (HfstBasicTransitions::const_iterator it = transitions.begin();
+                         it != transitions.end();
+                         it++) {
+                        arcs_ptr[i++] = ((struct RawArc) {
+                            s,
+                            it->get_target_state(),
+                            it->get_transition_data().get_input_number(),
+                            it->get_weight()
+                        });
+                    }
+                }
+            });
+            arcs.set_len(num_arcs);
+        }
+        arcs
+    }
+
+    // The final weight of every state, infinite for non-final states.
+    pub fn final_weights(&self) -> Vec<f32> {
+        let graph = self.graph;
+        let num_states = self.num_states() as usize;
+        let mut weights: Vec<f32> = Vec::with_capacity(num_states);
+        let weights_ptr = weights.as_mut_ptr();
+        unsafe {
+            cpp!([graph as "HfstBasicTransducer*", weights_ptr as "float*"] {
+                for (HfstState s = 0; s <= graph->get_max_state(); s++) {
+                    weights_ptr[s] = graph->is_final_state(s) ?
+                        graph->get_final_weight(s) :
+                        std::numeric_limits<float>::infinity();
+                }
+            });
+            weights.set_len(num_states);
+        }
+        weights
+    }
+
+    // self has to be mut since C++ method not marked `const`.
+    // It should be!
+    pub fn write_in_att_format(&mut self, filename: &str) -> Result<()> {
+        let mut graph = self.graph;
+        let filename_cp = c_path(filename)?;
+        let filename_ptr = filename_cp.as_ptr();
+        let written = unsafe {
+            cpp!([filename_ptr as "const char*", mut graph as "HfstBasicTransducer*"] -> bool as "bool" {
+                try {
+                    FILE *fp = fopen(filename_ptr, "w");
+                    if (fp == NULL) {
+                        return false;
+                    }
+                    graph->write_in_att_format(fp);
+                    fclose(fp);
+                    return true;
+                } catch (...) {
+                    return false;
+                }
+            })
+        };
+        if written {
+            Ok(())
+        } else {
+            Err(Error::Io(io::Error::new(io::ErrorKind::Other, "couldn't write graph")))
+        }
+    }
+
+    pub fn is_final_state(&self, stateno: u64) -> bool {
+        let graph = self.graph;
+        unsafe {
+            return cpp!([graph as "HfstBasicTransducer*", stateno as "uint64_t"] -> bool as "bool" {
+                if (stateno > graph->get_max_state()) {
+                    return false;
+                }
+                return (*graph).is_final_state(stateno);
+            });
+        }
+    }
+
+    // Arcs from stateno with any of the given (HFST) symbol numbers as input.
+    pub fn arcs<'a>(&'a self, stateno: u64, numbers: &'a [u32]) -> HfstArcs<'a> {
+        HfstArcs {
+            graph: self,
+            stateno: stateno,
+            numbers: numbers,
+            which: 0,
+            arc: UNPOSITIONED,
+        }
+    }
+
+    // Arcs from stateno with the given symbol as input, or None if the
+    // symbol can't be matched at all. As in HFST, a single character which
+    // isn't in the alphabet is matched by the unknown and identity arcs.
+    pub fn symbol_arcs<'a>(&'a self, stateno: u64, symbol: &[u8])
+            -> Option<HfstArcs<'a>> {
+        let symbol = match ::std::str::from_utf8(symbol) {
+            Ok(symbol) => symbol,
+            Err(_) => return None,
+        };
+        match self.symbols.id(symbol) {
+            Some(id) if self.ignored[id as usize] => None,
+            Some(id) => {
+                let id = id as usize;
+                Some(self.arcs(stateno, &self.numbers[id..id + 1]))
+            }
+            None if !self.wildcards.is_empty() &&
+                    symbol.chars().count() == 1 =>
+                Some(self.arcs(stateno, self.wildcards.as_slice())),
+            None => None,
+        }
+    }
+
+    // Whether bytes could still be extended into a symbol symbol_arcs can
+    // match, so are worth buffering.
+    pub fn is_symbol_prefix(&self, bytes: &[u8]) -> bool {
+        bytes.len() < MAX_SYMBOL_LEN && (
+            self.symbols.is_proper_prefix(bytes) ||
+            (!self.wildcards.is_empty() && is_partial_char(bytes)))
+    }
+
+    pub fn epsilon_arcs<'a>(&'a self, stateno: u64) -> HfstArcs<'a> {
+        self.arcs(stateno, self.epsilons.as_slice())
+    }
+
+    // Arcs from stateno with any of the inputs as input and any of the
+    // outputs as output, for graphs which are transducers.
+    pub fn pair_arcs<'a>(&'a self, stateno: u64, inputs: &'a [u32], outputs: &'a [u32])
+            -> HfstPairArcs<'a> {
+        HfstPairArcs {
+            graph: self,
+            stateno: stateno,
+            inputs: inputs,
+            outputs: outputs,
+            which: 0,
+            arc: UNPOSITIONED,
+        }
+    }
+}
+
+// Copies the graph with HFST's copy constructor.
+impl Clone for HfstBasicTransducerBox {
+    fn clone(&self) -> HfstBasicTransducerBox {
+        let graph = self.graph;
+        let copy = unsafe {
+            cpp!([graph as "HfstBasicTransducer*"] -> *mut c_void as "HfstBasicTransducer*" {
+                return new HfstBasicTransducer(*graph);
+            })
+        };
+        HfstBasicTransducerBox {
+            graph: copy,
+            symbols: self.symbols.clone(),
+            numbers: self.numbers.clone(),
+            epsilon_ids: self.epsilon_ids.clone(),
+            ignored: self.ignored.clone(),
+            epsilons: self.epsilons.clone(),
+            wildcards: self.wildcards.clone(),
+            query: self.query.clone(),
+            first_bytes: self.first_bytes.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for HfstBasicTransducerBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HfstBasicTransducerBox")
+            .field("num_states", &self.num_states())
+            .field("symbols", &self.symbols)
+            .field("query", &self.query)
+            .finish()
+    }
+}
+
+impl Drop for HfstBasicTransducerBox {
+    fn drop(&mut self) {
+        let graph = self.graph;
+        unsafe {
+            cpp!([graph as "HfstBasicTransducer*"] {
+                delete graph;
+            });
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct NextStates {
+    state: u64,
+    weight: f32
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct RawArc {
+    pub source: u64,
+    pub target: u64,
+    // HFST's symbol number
+    pub input: u32,
+    pub weight: f32,
+}
+
+cpp!({
+    struct NextStates {
+        uint64_t state;
+        float weight;
+    };
+    struct RawArc {
+        uint64_t source;
+        uint64_t target;
+        uint32_t input;
+        float weight;
+    };
+
+    static bool input_less(const HfstBasicTransition &transition, uint32_t number) {
+        return transition.get_transition_data().get_input_number() < number;
+    }
+
+    static bool transition_input_less(const HfstBasicTransition &a,
+                                      const HfstBasicTransition &b) {
+        return a.get_transition_data().get_input_number() <
+            b.get_transition_data().get_input_number();
+    }
+
+    // Transitions are sorted by input number when a graph is converted, so
+    // those with the given inputs can be found by binary search. which is
+    // the input being looked for and arc is the next transition to look at,
+    // or UINT64_MAX before the search for which has been done. Returns NULL
+    // once there are no more.
+    static const HfstBasicTransition *next_with_input(
+            const HfstBasicTransitions &transitions,
+            const uint32_t *numbers, uint64_t numbers_len,
+            uint64_t &which, uint64_t &arc) {
+        while (which < numbers_len) {
+            if (arc == UINT64_MAX) {
+                arc = std::lower_bound(transitions.begin(), transitions.end(),
+                                       numbers[which], input_less) - transitions.begin();
+            }
+            if (arc < transitions.size() &&
+                    transitions[arc].get_transition_data().get_input_number() ==
+                        numbers[which]) {
+                return &transitions[arc++];
+            }
+            which++;
+            arc = UINT64_MAX;
+        }
+        return NULL;
+    }
+});
+
+// Walks the arcs of a state in place, yielding those with the given input
+// symbols, so nothing has to be copied out of the graph up front.
+// Arc positions before the binary search for the next input symbol.
+const UNPOSITIONED: u64 = !0;
+
+pub struct HfstArcs<'a> {
+    graph: &'a HfstBasicTransducerBox,
+    stateno: u64,
+    numbers: &'a [u32],
+    // index into numbers of the input being looked for
+    which: u64,
+    arc: u64,
+}
+
+impl<'a> Iterator for HfstArcs<'a> {
+    type Item = ((u64, SymbolBuffer), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let graph = self.graph.graph;
+        let stateno = self.stateno;
+        let numbers = self.numbers.as_ptr();
+        let numbers_len = self.numbers.len() as u64;
+        let mut which = self.which;
+        let mut arc = self.arc;
+        let mut found = false;
+        let next_state;
+        unsafe {
+            next_state = cpp!(
+                    [graph as "HfstBasicTransducer*",
+                     stateno as "uint64_t",
+                     numbers as "const uint32_t*",
+                     numbers_len as "uint64_t",
+                     mut which as "uint64_t",
+                     mut arc as "uint64_t",
+                     mut found as "bool"] ->
+                        NextStates as "struct NextStates" {
+                // a stale state from another graph has no arcs, rather than
+                // indexing past the end
+                if (stateno > graph->get_max_state()) {
+                    return ((struct NextStates) { 0, 0.0 });
+                }
+                const HfstBasicTransition *transition = next_with_input(
+                    (*graph)[stateno], numbers, numbers_len, which, arc);
+                if (transition == NULL) {
+                    return ((struct NextStates) { 0, 0.0 });
+                }
+                found = true;
+                return ((struct NextStates) {
+                    (uint64_t) transition->get_target_state(),
+                    transition->get_weight()
+                });
+            });
+        }
+        self.which = which;
+        self.arc = arc;
+        if found {
+            Some(((next_state.state, SymbolBuffer::new()), next_state.weight as f64))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct HfstPairArcs<'a> {
+    graph: &'a HfstBasicTransducerBox,
+    stateno: u64,
+    inputs: &'a [u32],
+    outputs: &'a [u32],
+    // as in HfstArcs
+    which: u64,
+    arc: u64,
+}
+
+impl<'a> Iterator for HfstPairArcs<'a> {
+    // target state and weight
+    type Item = (u64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let graph = self.graph.graph;
+        let stateno = self.stateno;
+        let inputs = self.inputs.as_ptr();
+        let inputs_len = self.inputs.len() as u64;
+        let outputs = self.outputs.as_ptr();
+        let outputs_len = self.outputs.len() as u64;
+        let mut which = self.which;
+        let mut arc = self.arc;
+        let mut found = false;
+        let next_state;
+        unsafe {
+            next_state = cpp!(
+                    [graph as "HfstBasicTransducer*",
+                     stateno as "uint64_t",
+                     inputs as "const uint32_t*",
+                     inputs_len as "uint64_t",
+                     outputs as "const uint32_t*",
+                     outputs_len as "uint64_t",
+                     mut which as "uint64_t",
+                     mut arc as "uint64_t",
+                     mut found as "bool"] ->
+                        NextStates as "struct NextStates" {
+                // a stale state from another graph has no arcs, rather than
+                // indexing past the end
+                if (stateno > graph->get_max_state()) {
+                    return ((struct NextStates) { 0, 0.0 });
+                }
+                const HfstBasicTransitions &transitions = (*graph)[stateno];
+                const HfstBasicTransition *transition;
+                while ((transition = next_with_input(
+                        transitions, inputs, inputs_len, which, arc)) != NULL) {
+                    unsigned int output =
+                        transition->get_transition_data().get_output_number();
+                    for (uint64_t i = 0; i < outputs_len; i++) {
+                        if (output == outputs[i]) {
+                            found = true;
+                            return ((struct NextStates) {
+                                (uint64_t) transition->get_target_state(),
+                                transition->get_weight()
+                            });
+                        }
+                    }
+                }
+                return ((struct NextStates) { 0, 0.0 });
+            });
+        }
+        self.which = which;
+        self.arc = arc;
+        if found {
+            Some((next_state.state, next_state.weight as f64))
+        } else {
+            None
+        }
+    }
+}
+
+pub enum HfstNextStates<'a> {
+    Empty,
+    Once(Option<((u64, SymbolBuffer), f64)>),
+    Many(Peekable<HfstArcs<'a>>),
+    // the bytes so far still buffered, for when they're a symbol and also
+    // the start of a longer one, then the arcs of the shorter one
+    Both(Option<((u64, SymbolBuffer), f64)>, Peekable<HfstArcs<'a>>),
+}
+
+impl<'a> Iterator for HfstNextStates<'a> {
+    type Item = ((u64, SymbolBuffer), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            HfstNextStates::Empty => None,
+            HfstNextStates::Once(ref mut next) => next.take(),
+            HfstNextStates::Many(ref mut arcs) => arcs.next(),
+            HfstNextStates::Both(ref mut buffered, ref mut arcs) =>
+                buffered.take().or_else(|| arcs.next()),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use adapters::{WeightedNFA, FollowEpsilonNFA};
+use config::SearchConfig;
+use error::{Error, Result};
+use hfst::{HfstBasicTransducerBox, HfstPairArcs, AutStack, SymbolBuffer, mk_stack};
+
+// Composes a query with an error model on the fly as the index is streamed,
+// rather than building the composition up front with text_to_denoised_fsa.
+// The fst node is the third part of the state, kept by the stream, so paths
+// the index doesn't have are never expanded.
+#[derive(Clone, Debug)]
+pub struct LazyComposition<'m> {
+    model: &'m HfstBasicTransducerBox,
+    // the model's number for each symbol of the query
+    query: Vec<u32>,
+}
+
+impl<'m> LazyComposition<'m> {
+    // NoMatch if the query has a character the model doesn't know, since
+    // unknown and identity arcs aren't supported here.
+    pub fn new(model: &'m HfstBasicTransducerBox, query: &str) -> Result<LazyComposition<'m>> {
+        let mut numbers = Vec::with_capacity(query.len());
+        let mut buf = [0; 4];
+        for c in query.chars() {
+            let id = model.symbols().id(c.encode_utf8(&mut buf)).ok_or(Error::NoMatch)?;
+            numbers.push(model.symbol_numbers()[id as usize]);
+        }
+        Ok(LazyComposition { model: model, query: numbers })
+    }
+
+    // Model arcs which either consume the next query symbol or consume
+    // nothing, and output one of outputs.
+    fn next_states<'a>(&'a self, stateno: u64, pos: usize, outputs: &'a [u32])
+            -> LazyNextStates<'a> {
+        let consume = if pos < self.query.len() {
+            Some(self.model.pair_arcs(stateno, &self.query[pos..pos + 1], outputs))
+        } else {
+            None
+        };
+        LazyNextStates::Arcs {
+            buffered: None,
+            consume: consume,
+            keep: self.model.pair_arcs(stateno, self.model.epsilon_numbers(), outputs),
+            pos: pos,
+        }
+    }
+}
+
+pub enum LazyNextStates<'m> {
+    Empty,
+    Once(Option<((u64, usize, SymbolBuffer), f64)>),
+    Arcs {
+        // see HfstNextStates::Both
+        buffered: Option<((u64, usize, SymbolBuffer), f64)>,
+        consume: Option<HfstPairArcs<'m>>,
+        keep: HfstPairArcs<'m>,
+        pos: usize,
+    },
+}
+
+impl<'m> Iterator for LazyNextStates<'m> {
+    type Item = ((u64, usize, SymbolBuffer), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            LazyNextStates::Empty => None,
+            LazyNextStates::Once(ref mut next) => next.take(),
+            LazyNextStates::Arcs { ref mut buffered, ref mut consume, ref mut keep, pos } => {
+                if let Some(buffered) = buffered.take() {
+                    return Some(buffered);
+                }
+                if let Some((target, weight)) = consume.as_mut().and_then(Iterator::next) {
+                    return Some(((target, pos + 1, SymbolBuffer::new()), weight));
+                }
+                *consume = None;
+                keep.next().map(|(target, weight)|
+                    ((target, pos, SymbolBuffer::new()), weight))
+            }
+        }
+    }
+}
+
+impl<'m> WeightedNFA for LazyComposition<'m> {
+    // model state, position in the query and the partial symbol so far
+    type State = (u64, usize, SymbolBuffer);
+    type NextStateIter<'a> = LazyNextStates<'a> where Self: 'a;
+    type InputType = u8;
+
+    fn start(&self) -> Self::State {
+        (0, 0, SymbolBuffer::new())
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        let &(stateno, pos, ref buf) = state;
+        buf.is_empty() && pos == self.query.len() && self.model.is_final_state(stateno)
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, byte: u8) -> Self::NextStateIter<'a> {
+        let &(stateno, pos, buf) = state;
+        let mut new_buf = buf;
+        new_buf.push(byte);
+        let symbol = ::std::str::from_utf8(new_buf.as_slice()).ok()
+            .and_then(|symbol| self.model.symbols().id(symbol));
+        let buffered = if self.model.is_symbol_prefix(new_buf.as_slice()) {
+            Some(((stateno, pos, new_buf), 0.0))
+        } else {
+            None
+        };
+        match symbol {
+            Some(id) => {
+                let id = id as usize;
+                let mut next = self.next_states(
+                    stateno, pos, &self.model.symbol_numbers()[id..id + 1]);
+                // see HfstBasicTransducerBox::accept
+                if let LazyNextStates::Arcs { buffered: ref mut pending, .. } = next {
+                    *pending = buffered;
+                }
+                next
+            }
+            None => LazyNextStates::Once(buffered),
+        }
+    }
+}
+
+impl<'m> FollowEpsilonNFA for LazyComposition<'m> {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        let &(stateno, pos, ref buf) = state;
+        if !buf.is_empty() {
+            return LazyNextStates::Empty;
+        }
+        self.next_states(stateno, pos, self.model.epsilon_numbers())
+    }
+}
+
+pub fn mk_lazy_stack<'m>(model: &'m HfstBasicTransducerBox, query: &str,
+                         config: &SearchConfig)
+        -> Result<AutStack<LazyComposition<'m>>> {
+    mk_stack(LazyComposition::new(model, query)?, config)
+}
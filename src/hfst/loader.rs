@@ -0,0 +1,89 @@
+use std::io::Read;
+
+use error::Result;
+use hfst::ffi::{TransducerBox, Implementation, WeightPush};
+use hfst::normalize::QueryNormalizer;
+
+// Options applied to an error model as it's loaded. They're applied in the
+// order convert, remove epsilons, push weights, minimize, so that minimizing
+// sees the pushed weights.
+#[derive(Clone, Debug, Default)]
+pub struct TransducerLoader {
+    pub implementation: Option<Implementation>,
+    pub push_weights: Option<WeightPush>,
+    pub remove_epsilons: bool,
+    pub minimize: bool,
+    pub cache_symbols: bool,
+    pub normalizer: Option<QueryNormalizer>,
+}
+
+impl TransducerLoader {
+    pub fn new() -> TransducerLoader {
+        TransducerLoader::default()
+    }
+
+    pub fn convert_to(mut self, implementation: Implementation) -> TransducerLoader {
+        self.implementation = Some(implementation);
+        self
+    }
+
+    pub fn push_weights(mut self, push: WeightPush) -> TransducerLoader {
+        self.push_weights = Some(push);
+        self
+    }
+
+    pub fn remove_epsilons(mut self) -> TransducerLoader {
+        self.remove_epsilons = true;
+        self
+    }
+
+    pub fn minimize(mut self) -> TransducerLoader {
+        self.minimize = true;
+        self
+    }
+
+    pub fn cache_symbols(mut self) -> TransducerLoader {
+        self.cache_symbols = true;
+        self
+    }
+
+    pub fn normalizer(mut self, normalizer: QueryNormalizer) -> TransducerLoader {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    pub fn load(&self, filename: &str) -> Result<TransducerBox> {
+        let mut transducer = TransducerBox::from_file(filename)?;
+        self.apply(&mut transducer)?;
+        Ok(transducer)
+    }
+
+    pub fn load_reader<R: Read>(&self, reader: R) -> Result<TransducerBox> {
+        let mut transducer = TransducerBox::from_reader(reader)?;
+        self.apply(&mut transducer)?;
+        Ok(transducer)
+    }
+
+    // For a transducer which has already been loaded.
+    pub fn apply(&self, transducer: &mut TransducerBox) -> Result<()> {
+        if let Some(implementation) = self.implementation {
+            transducer.convert(implementation)?;
+        }
+        if self.remove_epsilons {
+            transducer.remove_epsilons()?;
+        }
+        if let Some(push) = self.push_weights {
+            transducer.push_weights(push)?;
+        }
+        if self.minimize {
+            transducer.minimize()?;
+        }
+        if self.cache_symbols {
+            transducer.cache_symbols();
+        }
+        if self.normalizer.is_some() {
+            transducer.set_normalizer(self.normalizer.clone());
+        }
+        Ok(())
+    }
+}
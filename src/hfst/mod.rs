@@ -0,0 +1,112 @@
+// The trait impls live here rather than in ffi.rs because the cpp build
+// script has to parse that file, and its parser predates generic associated
+// types.
+mod ffi;
+pub mod symbols;
+pub mod compiled;
+pub mod stats;
+pub mod alphabet;
+pub mod normalize;
+pub mod validate;
+pub mod lazy;
+pub mod suggest;
+pub mod loader;
+pub mod dump;
+pub mod executor;
+pub mod morph;
+
+pub use self::ffi::{TransducerBox, HfstBasicTransducerBox, HfstArcs,
+                    HfstNextStates, HfstPairArcs, RawArc, Implementation, WeightPush,
+                    Direction};
+pub use self::loader::TransducerLoader;
+pub use self::symbols::{SymbolTable, SymbolBuffer, ByteSet};
+pub use self::compiled::CompiledHfstGraph;
+pub use self::stats::{GraphStats, WeightDistribution};
+pub use self::alphabet::UnknownSymbols;
+pub use self::normalize::{Normalization, QueryNormalizer, NormalizedQuery};
+pub use self::validate::WeightError;
+pub use self::suggest::Suggestion;
+pub use self::executor::QueryExecutor;
+pub use self::morph::LemmaMatcher;
+
+use std::hash::Hash;
+
+use fst::Automaton;
+use adapters::{WeightedNFA, AutomatonDFAAdapter, EpsilonExpandingBeamSearchAdapter, compare_weights, FollowEpsilonNFA};
+use config::SearchConfig;
+use error::Result;
+
+impl FollowEpsilonNFA for HfstBasicTransducerBox {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        let &(stateno, ref buf) = state;
+        if !buf.is_empty() {
+            return HfstNextStates::Empty;
+        }
+        HfstNextStates::Many(self.epsilon_arcs(stateno).peekable())
+    }
+}
+
+impl WeightedNFA for HfstBasicTransducerBox {
+    type State = (u64, SymbolBuffer);
+    type NextStateIter<'a> = HfstNextStates<'a>;
+    type InputType = u8;
+
+    fn start(&self) -> Self::State {
+        return (0, SymbolBuffer::new());
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        let &(stateno, ref buf) = state;
+        if !buf.is_empty() {
+            return false;
+        }
+        self.is_final_state(stateno)
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, byte: u8) -> Self::NextStateIter<'a> {
+        let &(stateno, buf) = state;
+        if buf.is_empty() && !self.first_bytes(stateno).contains(byte) {
+            return HfstNextStates::Empty;
+        }
+        let mut new_buf = buf;
+        new_buf.push(byte);
+        // Only keep buffering while the bytes could still become a symbol,
+        // otherwise a dead end would ride along in the beam for free.
+        let buffered = if self.is_symbol_prefix(new_buf.as_slice()) {
+            Some(((stateno, new_buf), 0.0))
+        } else {
+            None
+        };
+        let arcs = self.symbol_arcs(stateno, new_buf.as_slice())
+            .map(Iterator::peekable)
+            .and_then(|mut arcs| if arcs.peek().is_some() { Some(arcs) } else { None });
+        // a symbol which starts a longer one, like s and sh, can go either way
+        match (buffered, arcs) {
+            (Some(buffered), Some(arcs)) => HfstNextStates::Both(Some(buffered), arcs),
+            (None, Some(arcs)) => HfstNextStates::Many(arcs),
+            (buffered, None) => HfstNextStates::Once(buffered),
+        }
+    }
+}
+
+// The graph can be either an HfstBasicTransducerBox or a CompiledHfstGraph
+pub type AutStack<G=HfstBasicTransducerBox> = AutomatonDFAAdapter<
+    EpsilonExpandingBeamSearchAdapter<G>>;
+
+pub fn mk_stack<G>(aut: G, config: &SearchConfig) -> Result<AutStack<G>>
+        where G: FollowEpsilonNFA<InputType=u8>, G::State: Eq + Hash + Clone {
+    Ok(AutomatonDFAAdapter(EpsilonExpandingBeamSearchAdapter::new(config.beam_search(aut)?)))
+}
+
+pub fn get_weights<G>(aut: &AutStack<G>, result: &[u8]) -> f64
+        where G: FollowEpsilonNFA<InputType=u8>, G::State: Eq + Hash + Clone {
+    let state = aut.accept_bytes(&aut.start(), result);
+    let weights = state.iter().filter_map(|&(ref state, ref weight)|
+        if (aut.0).inner().aut.is_match(state) {
+            Some(*weight)
+        } else {
+            None
+        }
+    );
+    weights.min_by(compare_weights).unwrap()
+}
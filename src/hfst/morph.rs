@@ -0,0 +1,62 @@
+use config::SearchConfig;
+use error::Result;
+use hfst::{TransducerBox, HfstBasicTransducerBox, Direction, AutStack, mk_stack};
+
+// Whether an analyser's output symbol is a tag rather than part of a lemma,
+// going by the usual ways of writing them: +N, <sg> or [POS=NOUN]. Other
+// multicharacter symbols, e.g. digraphs, are taken to be part of the lemma.
+pub fn is_tag(symbol: &str) -> bool {
+    let delimited = |open, close| symbol.len() > 2 &&
+        symbol.starts_with(open) && symbol.ends_with(close);
+    (symbol.len() > 1 && symbol.starts_with('+')) ||
+        delimited('<', '>') || delimited('[', ']')
+}
+
+// Fuzzy search over inflected forms against a lemma keyed index. The query
+// is corrected with the error model as usual, then each correction is
+// analysed, and keys are matched against the lemmas of the analyses. Tags
+// are followed as epsilons, so a key matches any analysis of its lemma.
+//
+// The keys aren't analysed, since the index is expected to hold lemmas
+// already, e.g. built by running the analyser over a word list.
+#[derive(Debug)]
+pub struct LemmaMatcher {
+    pub model: TransducerBox,
+    pub analyser: TransducerBox,
+    // which way the analyser is applied to corrections
+    pub direction: Direction,
+    pub determinize: bool,
+    // which of the analyser's output symbols are tags, is_tag by default
+    pub is_tag: fn(&str) -> bool,
+}
+
+impl LemmaMatcher {
+    pub fn new(model: TransducerBox, analyser: TransducerBox) -> LemmaMatcher {
+        LemmaMatcher {
+            model: model,
+            analyser: analyser,
+            direction: Direction::Down,
+            determinize: false,
+            is_tag: is_tag,
+        }
+    }
+
+    // The analyses of the query's corrections, with tags as epsilons.
+    pub fn lemma_graph(&self, query: &str) -> Result<HfstBasicTransducerBox> {
+        let mut graph = self.model.text_to_analysed_fsa(
+            query, &self.analyser, self.direction, self.determinize, false)?;
+        let tags: Vec<String> = graph.alphabet().into_iter()
+            .filter(|symbol| (self.is_tag)(symbol))
+            .collect();
+        graph.add_epsilon_symbols(tags);
+        Ok(graph)
+    }
+
+    // The lemma graph beam searched, e.g. to search a lemma keyed map with.
+    // For a CompiledHfstGraph, which keeps the tags as epsilons, compile
+    // lemma_graph's result and pass it to mk_stack.
+    pub fn mk_stack(&self, query: &str, config: &SearchConfig)
+            -> Result<AutStack<HfstBasicTransducerBox>> {
+        mk_stack(self.lemma_graph(query)?, config)
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::UnicodeNormalization;
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::char::is_combining_mark;
+
+// Custom steps are shared rather than boxed so that normalizers, and the
+// transducers holding them, can be cloned and sent to other threads.
+#[derive(Clone)]
+pub enum Normalization {
+    Lowercase,
+    #[cfg(feature = "unicode-normalization")]
+    Nfc,
+    Custom(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+impl fmt::Debug for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Normalization::Lowercase => write!(f, "Lowercase"),
+            #[cfg(feature = "unicode-normalization")]
+            Normalization::Nfc => write!(f, "Nfc"),
+            Normalization::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+// Applied to queries by TransducerBox::text_to_denoised_fsa before
+// composition, in order.
+#[derive(Clone, Debug)]
+pub struct QueryNormalizer {
+    pub steps: Vec<Normalization>,
+}
+
+// A normalized query, remembering where each of its chars came from in
+// the original.
+#[derive(Clone, Debug)]
+pub struct NormalizedQuery {
+    pub original: String,
+    pub normalized: String,
+    // byte range of the original for each char of normalized, or None when
+    // a custom step changed the length so nothing lines up any more
+    spans: Vec<Option<Range<usize>>>,
+}
+
+type Aligned = Vec<(char, Option<Range<usize>>)>;
+
+fn join_spans(chars: &[(char, Option<Range<usize>>)]) -> Option<Range<usize>> {
+    let start = chars.first().and_then(|&(_, ref span)| span.clone());
+    let end = chars.last().and_then(|&(_, ref span)| span.clone());
+    match (start, end) {
+        (Some(start), Some(end)) => Some(start.start..end.end),
+        _ => None,
+    }
+}
+
+impl Normalization {
+    fn apply(&self, chars: Aligned) -> Aligned {
+        match *self {
+            Normalization::Lowercase => chars.into_iter()
+                .flat_map(|(chr, span)|
+                    chr.to_lowercase().map(move |lower| (lower, span.clone())))
+                .collect(),
+            #[cfg(feature = "unicode-normalization")]
+            Normalization::Nfc => {
+                // each starter is composed together with the combining
+                // marks following it
+                let mut result = vec![];
+                let mut start = 0;
+                while start < chars.len() {
+                    let end = start + 1 + chars[start + 1..].iter()
+                        .take_while(|&&(chr, _)| is_combining_mark(chr)).count();
+                    let segment: String = chars[start..end].iter()
+                        .map(|&(chr, _)| chr).collect();
+                    let span = join_spans(&chars[start..end]);
+                    result.extend(segment.nfc().map(|chr| (chr, span.clone())));
+                    start = end;
+                }
+                result
+            }
+            Normalization::Custom(ref normalize) => {
+                let query: String = chars.iter().map(|&(chr, _)| chr).collect();
+                let normalized = normalize(&query);
+                if normalized.chars().count() == chars.len() {
+                    normalized.chars().zip(chars.into_iter())
+                        .map(|(chr, (_, span))| (chr, span)).collect()
+                } else {
+                    normalized.chars().map(|chr| (chr, None)).collect()
+                }
+            }
+        }
+    }
+}
+
+impl QueryNormalizer {
+    pub fn new(steps: Vec<Normalization>) -> QueryNormalizer {
+        QueryNormalizer { steps: steps }
+    }
+
+    pub fn normalize(&self, query: &str) -> NormalizedQuery {
+        let mut chars: Aligned = query.char_indices()
+            .map(|(idx, chr)| (chr, Some(idx..idx + chr.len_utf8()))).collect();
+        for step in self.steps.iter() {
+            chars = step.apply(chars);
+        }
+        NormalizedQuery {
+            original: query.to_owned(),
+            normalized: chars.iter().map(|&(chr, _)| chr).collect(),
+            spans: chars.into_iter().map(|(_, span)| span).collect(),
+        }
+    }
+}
+
+impl NormalizedQuery {
+    // The part of the original query the nth char of normalized came from.
+    pub fn original_span(&self, idx: usize) -> Option<Range<usize>> {
+        self.spans.get(idx).and_then(|span| span.clone())
+    }
+
+    // Puts back the original form of the chars of result which are the
+    // same as the normalized query at the same position, e.g. to report a
+    // result in the query's casing.
+    pub fn restore(&self, result: &str) -> String {
+        let mut restored = String::with_capacity(result.len());
+        let mut last_span = None;
+        let mut normalized = self.normalized.chars();
+        for (idx, chr) in result.chars().enumerate() {
+            let span = match (normalized.next(), self.original_span(idx)) {
+                (Some(norm_chr), Some(span)) if norm_chr == chr => span,
+                _ => {
+                    restored.push(chr);
+                    last_span = None;
+                    continue;
+                }
+            };
+            // several normalized chars can come from one original char
+            if last_span.as_ref() != Some(&span) {
+                restored.push_str(&self.original[span.clone()]);
+            }
+            last_span = Some(span);
+        }
+        restored
+    }
+}
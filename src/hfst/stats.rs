@@ -0,0 +1,184 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f32;
+
+use error::{Error, Result};
+use hfst::HfstBasicTransducerBox;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphStats {
+    pub states: u64,
+    pub arcs: u64,
+    // arcs followed by follow_epsilon, including flag diacritics
+    pub epsilon_arcs: u64,
+    pub final_states: u64,
+    // (min, max) of arc weights, if there are any arcs
+    pub arc_weights: Option<(f32, f32)>,
+    // (min, max) of final weights, if there are any final states
+    pub final_weights: Option<(f32, f32)>,
+}
+
+// Weights of the best complete paths through a graph, e.g. for picking a
+// beam threshold per query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightDistribution {
+    pub min: f32,
+    pub max: f32,
+    // number of paths in each of the equal width bins between min and max
+    pub histogram: Vec<u64>,
+    pub paths: u64,
+    // whether there may be more paths than were counted
+    pub truncated: bool,
+}
+
+impl WeightDistribution {
+    // The lowest weight at least fraction of the counted paths are within,
+    // to the resolution of the histogram.
+    pub fn quantile(&self, fraction: f64) -> f32 {
+        let wanted = (fraction * self.paths as f64).ceil() as u64;
+        let width = (self.max - self.min) / self.histogram.len() as f32;
+        let mut seen = 0;
+        for (bin, &count) in self.histogram.iter().enumerate() {
+            seen += count;
+            if seen >= wanted {
+                return self.min + width * (bin + 1) as f32;
+            }
+        }
+        self.max
+    }
+}
+
+struct PathItem {
+    weight: f32,
+    state: u64,
+    complete: bool,
+}
+
+impl PartialEq for PathItem {
+    fn eq(&self, other: &PathItem) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PathItem {}
+
+impl PartialOrd for PathItem {
+    fn partial_cmp(&self, other: &PathItem) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PathItem {
+    // reversed so BinaryHeap pops the lightest first
+    fn cmp(&self, other: &PathItem) -> Ordering {
+        other.weight.partial_cmp(&self.weight).unwrap_or(Ordering::Equal)
+    }
+}
+
+fn extend_range(range: Option<(f32, f32)>, weight: f32) -> Option<(f32, f32)> {
+    Some(match range {
+        Some((min, max)) => (min.min(weight), max.max(weight)),
+        None => (weight, weight),
+    })
+}
+
+impl HfstBasicTransducerBox {
+    pub fn stats(&self) -> GraphStats {
+        let mut stats = GraphStats::default();
+        for weight in self.final_weights() {
+            stats.states += 1;
+            if weight != f32::INFINITY {
+                stats.final_states += 1;
+                stats.final_weights = extend_range(stats.final_weights, weight);
+            }
+        }
+        let epsilons = self.epsilon_numbers();
+        for arc in self.raw_arcs() {
+            stats.arcs += 1;
+            if epsilons.contains(&arc.input) {
+                stats.epsilon_arcs += 1;
+            }
+            stats.arc_weights = extend_range(stats.arc_weights, arc.weight);
+        }
+        stats
+    }
+
+    // Finds the max_paths lightest complete paths best first, and bins
+    // their weights. NoMatch if there are no complete paths.
+    pub fn weight_distribution(&self, max_paths: u64, bins: usize)
+            -> Result<WeightDistribution> {
+        if bins == 0 {
+            return Err(Error::InvalidConfig("bins must be at least 1".to_owned()));
+        }
+        let finals = self.final_weights();
+        let mut offsets = vec![0; finals.len() + 1];
+        let mut arcs = self.raw_arcs();
+        arcs.sort_by_key(|arc| arc.source);
+        for arc in arcs.iter() {
+            offsets[arc.source as usize + 1] += 1;
+        }
+        for state in 0..finals.len() {
+            offsets[state + 1] += offsets[state];
+        }
+
+        // cycles can make the number of paths infinite, so give up after a
+        // while if they're producing nothing
+        let max_pops = max_paths.saturating_mul(finals.len() as u64 + 1);
+        let mut weights = vec![];
+        let mut heap = BinaryHeap::new();
+        let mut pops = 0;
+        let mut truncated = false;
+        if !finals.is_empty() {
+            heap.push(PathItem { weight: 0.0, state: 0, complete: false });
+        }
+        while let Some(item) = heap.pop() {
+            if weights.len() as u64 >= max_paths || pops >= max_pops {
+                truncated = true;
+                break;
+            }
+            pops += 1;
+            if item.complete {
+                weights.push(item.weight);
+                continue;
+            }
+            let state = item.state as usize;
+            if finals[state] != f32::INFINITY {
+                heap.push(PathItem {
+                    weight: item.weight + finals[state],
+                    state: item.state,
+                    complete: true,
+                });
+            }
+            for arc in arcs[offsets[state]..offsets[state + 1]].iter() {
+                heap.push(PathItem {
+                    weight: item.weight + arc.weight,
+                    state: arc.target,
+                    complete: false,
+                });
+            }
+        }
+
+        let min = match weights.first() {
+            Some(&min) => min,
+            None => return Err(Error::NoMatch),
+        };
+        let max = *weights.last().unwrap();
+        let mut histogram = vec![0; bins];
+        let width = (max - min) / bins as f32;
+        for &weight in weights.iter() {
+            let bin = if width > 0.0 {
+                ((weight - min) / width) as usize
+            } else {
+                0
+            };
+            histogram[bin.min(bins - 1)] += 1;
+        }
+        Ok(WeightDistribution {
+            min: min,
+            max: max,
+            histogram: histogram,
+            paths: weights.len() as u64,
+            truncated: truncated,
+        })
+    }
+}
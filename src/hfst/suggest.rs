@@ -0,0 +1,36 @@
+use std::hash::Hash;
+
+use fst::Map;
+
+use adapters::FollowEpsilonNFA;
+use ext::map::MapExt;
+use ext::raw::SortKey;
+use hfst::AutStack;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suggestion {
+    pub key: String,
+    // the map's value for the key
+    pub value: u64,
+    pub weight: f64,
+}
+
+impl<G> AutStack<G>
+        where G: FollowEpsilonNFA<InputType=u8>, G::State: Eq + Hash + Clone {
+    // The n lightest keys of map which this matches, best first. The stream
+    // gives each key once, so there's nothing to deduplicate. Keys which
+    // aren't UTF-8 are skipped, though with every symbol being UTF-8 there
+    // shouldn't be any.
+    pub fn suggest(&self, map: &Map, n: usize) -> Vec<Suggestion> {
+        let matches = map.search_state_stream(self).collect_sorted(Some(n), SortKey::Weight);
+        let mut suggestions = Vec::with_capacity(matches.len());
+        for (key, value, weight) in matches {
+            let key = match String::from_utf8(key) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+            suggestions.push(Suggestion { key: key, value: value, weight: weight });
+        }
+        suggestions
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+use std::iter::FromIterator;
+use std::str::from_utf8;
+
+pub const EPSILON: &'static str = "@_EPSILON_SYMBOL_@";
+pub const UNKNOWN: &'static str = "@_UNKNOWN_SYMBOL_@";
+pub const IDENTITY: &'static str = "@_IDENTITY_SYMBOL_@";
+
+// Flag diacritics look like @P.FEATURE.VALUE@ or @R.FEATURE@
+pub fn is_flag_diacritic(symbol: &str) -> bool {
+    let bytes = symbol.as_bytes();
+    bytes.len() > 4 &&
+        bytes[0] == b'@' && bytes[bytes.len() - 1] == b'@' &&
+        b"PNRDCU".contains(&bytes[1]) && bytes[2] == b'.'
+}
+
+// HFST's own symbols, like @_EPSILON_SYMBOL_@, and flag diacritics, which
+// are never spelled out in the text being matched.
+pub fn is_special(symbol: &str) -> bool {
+    (symbol.len() > 4 && symbol.starts_with("@_") && symbol.ends_with("_@")) ||
+        is_flag_diacritic(symbol)
+}
+
+// Whether bytes are the start of a single UTF-8 character, which could
+// still be matched by unknown or identity arcs.
+pub fn is_partial_char(bytes: &[u8]) -> bool {
+    match from_utf8(bytes) {
+        Ok(_) => false,
+        Err(err) => err.valid_up_to() == 0 && err.error_len().is_none(),
+    }
+}
+
+// Symbols longer than this (in bytes) can't be matched when stepping.
+pub const MAX_SYMBOL_LEN: usize = 16;
+
+// Like Utf8Buffer, but holds the bytes read so far of a symbol, which may be
+// a multicharacter one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SymbolBuffer {
+    bytes: [u8; MAX_SYMBOL_LEN],
+    len: u8,
+}
+
+impl SymbolBuffer {
+    pub fn new() -> SymbolBuffer {
+        SymbolBuffer::default()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == MAX_SYMBOL_LEN
+    }
+
+    // Once full, further bytes are dropped and the buffer stays full.
+    pub fn push(&mut self, byte: u8) {
+        if !self.is_full() {
+            self.bytes[self.len as usize] = byte;
+            self.len += 1;
+        }
+    }
+}
+
+// A set of bytes as a 256 bit bitmap, e.g. the bytes which the arcs of a
+// state can start with, so other bytes can be rejected straight away.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    pub fn new() -> ByteSet {
+        ByteSet::default()
+    }
+
+    pub fn full() -> ByteSet {
+        ByteSet([!0; 4])
+    }
+
+    pub fn insert(&mut self, byte: u8) {
+        self.0[byte as usize >> 6] |= 1 << (byte & 63);
+    }
+
+    pub fn contains(&self, byte: u8) -> bool {
+        self.0[byte as usize >> 6] & (1 << (byte & 63)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    // Adds the first byte of symbol, or every byte for symbols which match
+    // any character.
+    pub fn insert_first_byte(&mut self, symbol: &str) {
+        if symbol == UNKNOWN || symbol == IDENTITY {
+            *self = ByteSet::full();
+        } else if let Some(&first) = symbol.as_bytes().first() {
+            self.insert(first);
+        }
+    }
+}
+
+// Interns the symbols of a graph's alphabet as small integer ids, so that
+// symbols can be compared and hashed without touching their strings.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    symbols: Vec<String>,
+    ids: HashMap<String, u32>,
+    // every proper prefix of every symbol which isn't special, for buffering
+    // bytes
+    prefixes: HashSet<Vec<u8>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn intern(&mut self, symbol: &str) -> u32 {
+        if let Some(&id) = self.ids.get(symbol) {
+            return id;
+        }
+        let id = self.symbols.len() as u32;
+        self.symbols.push(symbol.to_owned());
+        self.ids.insert(symbol.to_owned(), id);
+        if !is_special(symbol) {
+            let bytes = symbol.as_bytes();
+            for len in 1..bytes.len() {
+                self.prefixes.insert(bytes[..len].to_vec());
+            }
+        }
+        id
+    }
+
+    // Whether bytes are the start of some longer symbol.
+    pub fn is_proper_prefix(&self, bytes: &[u8]) -> bool {
+        self.prefixes.contains(bytes)
+    }
+
+    pub fn id(&self, symbol: &str) -> Option<u32> {
+        self.ids.get(symbol).cloned()
+    }
+
+    pub fn symbol(&self, id: u32) -> &str {
+        self.symbols[id as usize].as_str()
+    }
+
+    pub fn symbols(&self) -> &[String] {
+        self.symbols.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for SymbolTable {
+    fn from_iter<I: IntoIterator<Item=S>>(iter: I) -> SymbolTable {
+        let mut table = SymbolTable::new();
+        for symbol in iter {
+            table.intern(symbol.as_ref());
+        }
+        table
+    }
+}
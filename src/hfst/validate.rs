@@ -0,0 +1,165 @@
+use std::error::Error;
+use std::fmt;
+
+use hfst::{HfstBasicTransducerBox, RawArc};
+
+// Weights which break the assumption of the beam search that a hypothesis
+// never gets lighter as it is extended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WeightError {
+    NanArc { source: u64, target: u64 },
+    NanFinal { state: u64 },
+    NegativeArc { source: u64, target: u64, weight: f32 },
+    NegativeFinal { state: u64, weight: f32 },
+    // a cycle of epsilon arcs which doesn't add any weight, given by one of
+    // its states
+    FreeEpsilonCycle { state: u64 },
+    // a cycle which lowers the weight every time around, so there are
+    // arbitrarily light paths and the weights can't be pushed
+    NegativeCycle { state: u64 },
+}
+
+impl fmt::Display for WeightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WeightError::NanArc { source, target } =>
+                write!(f, "arc from {} to {} has a NaN weight", source, target),
+            WeightError::NanFinal { state } =>
+                write!(f, "state {} has a NaN final weight", state),
+            WeightError::NegativeArc { source, target, weight } =>
+                write!(f, "arc from {} to {} has negative weight {}", source, target, weight),
+            WeightError::NegativeFinal { state, weight } =>
+                write!(f, "state {} has negative final weight {}", state, weight),
+            WeightError::FreeEpsilonCycle { state } =>
+                write!(f, "state {} is on a cycle of zero weight epsilon arcs", state),
+            WeightError::NegativeCycle { state } =>
+                write!(f, "state {} is on a negative weight cycle", state),
+        }
+    }
+}
+
+impl Error for WeightError {
+    fn description(&self) -> &str {
+        "invalid weights for beam search"
+    }
+}
+
+// An arc as far as validation cares.
+#[derive(Copy, Clone, Debug)]
+pub struct WeightedArc {
+    pub source: u64,
+    pub target: u64,
+    pub epsilon: bool,
+    pub weight: f32,
+}
+
+// Checks for NaN and negative weights and then for cycles of epsilon arcs
+// with no weight. finals is infinite for non-final states.
+pub fn check_weights(arcs: &[WeightedArc], finals: &[f32]) -> Result<(), WeightError> {
+    for arc in arcs {
+        if arc.weight.is_nan() {
+            return Err(WeightError::NanArc { source: arc.source, target: arc.target });
+        }
+    }
+    for (state, &weight) in finals.iter().enumerate() {
+        if weight.is_nan() {
+            return Err(WeightError::NanFinal { state: state as u64 });
+        }
+    }
+    for arc in arcs {
+        if arc.weight < 0.0 {
+            return Err(WeightError::NegativeArc {
+                source: arc.source,
+                target: arc.target,
+                weight: arc.weight,
+            });
+        }
+    }
+    for (state, &weight) in finals.iter().enumerate() {
+        if weight < 0.0 {
+            return Err(WeightError::NegativeFinal {
+                state: state as u64,
+                weight: weight,
+            });
+        }
+    }
+
+    let mut free: Vec<Vec<u64>> = vec![vec![]; finals.len()];
+    for arc in arcs.iter().filter(|arc| arc.epsilon && arc.weight == 0.0) {
+        free[arc.source as usize].push(arc.target);
+    }
+    // iterative depth first search, 1 = on the stack, 2 = done
+    let mut marks = vec![0u8; finals.len()];
+    for root in 0..finals.len() {
+        if marks[root] != 0 {
+            continue;
+        }
+        let mut stack = vec![(root, 0)];
+        marks[root] = 1;
+        while let Some(&mut (state, ref mut next)) = stack.last_mut() {
+            if let Some(&target) = free[state].get(*next) {
+                *next += 1;
+                match marks[target as usize] {
+                    0 => {
+                        marks[target as usize] = 1;
+                        stack.push((target as usize, 0));
+                    }
+                    1 => return Err(WeightError::FreeEpsilonCycle { state: target }),
+                    _ => {}
+                }
+            } else {
+                marks[state] = 2;
+                stack.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+// The lightest weight from each state to a final state, by Bellman-Ford
+// since weights may be negative. Infinite where no final state is reachable.
+pub fn distances_to_final(arcs: &[WeightedArc], finals: &[f32])
+        -> Result<Vec<f32>, WeightError> {
+    let mut distances = finals.to_vec();
+    for _ in 0..finals.len() + 1 {
+        let mut changed = false;
+        for arc in arcs {
+            let through = arc.weight + distances[arc.target as usize];
+            if through < distances[arc.source as usize] {
+                distances[arc.source as usize] = through;
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok(distances);
+        }
+    }
+    // still improving after every path without repeats has been tried
+    let state = arcs.iter()
+        .find(|arc| arc.weight + distances[arc.target as usize] <
+                    distances[arc.source as usize])
+        .map(|arc| arc.source).unwrap_or(0);
+    Err(WeightError::NegativeCycle { state: state })
+}
+
+impl HfstBasicTransducerBox {
+    // Every arc with a NaN weight, rather than just the first like
+    // validate_weights. The beam search treats them as infinite, so a broken
+    // model otherwise just loses hypotheses without saying so.
+    pub fn nan_arcs(&self) -> Vec<RawArc> {
+        self.raw_arcs().into_iter().filter(|arc| arc.weight.is_nan()).collect()
+    }
+
+    pub fn validate_weights(&self) -> Result<(), WeightError> {
+        let epsilons = self.epsilon_numbers();
+        let arcs: Vec<WeightedArc> = self.raw_arcs().into_iter().map(|arc| {
+            WeightedArc {
+                source: arc.source,
+                target: arc.target,
+                epsilon: epsilons.contains(&arc.input),
+                weight: arc.weight,
+            }
+        }).collect();
+        check_weights(&arcs, &self.final_weights())
+    }
+}
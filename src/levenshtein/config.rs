@@ -0,0 +1,91 @@
+use std::convert::TryFrom;
+use std::u32;
+
+use levenshtein::unweighted::SimpleLevenshtein;
+use levenshtein::lcs::{LcsStack, mk_lcs};
+use levenshtein::wrappers::{Levenshtein, WeightedLevenshtein};
+
+pub use config::DEFAULT_BEAM_SIZE;
+use config::SearchConfig;
+use error::{Error, Result};
+
+// What the Levenshtein automata have in common, so code can start with an
+// unweighted one and switch to the beam searched one, where distance
+// becomes the threshold, without building the query again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevenshteinConfig {
+    pub query: String,
+    pub distance: u32,
+    // only used by the weighted automaton
+    pub beam_size: usize,
+}
+
+impl LevenshteinConfig {
+    pub fn new(query: &str, distance: u32) -> LevenshteinConfig {
+        LevenshteinConfig {
+            query: query.to_owned(),
+            distance: distance,
+            beam_size: DEFAULT_BEAM_SIZE,
+        }
+    }
+
+    pub fn unweighted(&self) -> SimpleLevenshtein {
+        SimpleLevenshtein::new(&self.query, self.distance as u64)
+    }
+
+    // Fails if beam_size is 0.
+    pub fn weighted(&self) -> Result<WeightedLevenshtein> {
+        WeightedLevenshtein::new(&self.query, self.distance as f64, self.beam_size)
+    }
+
+    // Ranks by longest common subsequence instead, where distance is how
+    // many chars of the query may be left out of it. Fails if beam_size is 0.
+    pub fn lcs(&self) -> Result<LcsStack> {
+        let min_common = self.query.chars().count().saturating_sub(self.distance as usize);
+        let search = SearchConfig { beam_size: self.beam_size, ..SearchConfig::default() };
+        mk_lcs(&self.query, min_common, &search)
+    }
+}
+
+impl<'a> From<&'a SimpleLevenshtein> for LevenshteinConfig {
+    fn from(aut: &'a SimpleLevenshtein) -> LevenshteinConfig {
+        let distance = if aut.threshold() > u32::MAX as u64 {
+            u32::MAX
+        } else {
+            aut.threshold() as u32
+        };
+        LevenshteinConfig::new(&aut.query(), distance)
+    }
+}
+
+impl From<LevenshteinConfig> for SimpleLevenshtein {
+    fn from(config: LevenshteinConfig) -> SimpleLevenshtein {
+        config.unweighted()
+    }
+}
+
+// Fails if the automaton would have too many states.
+impl TryFrom<LevenshteinConfig> for Levenshtein {
+    type Error = Error;
+
+    fn try_from(config: LevenshteinConfig) -> Result<Levenshtein> {
+        Levenshtein::new(&config.query, config.distance)
+    }
+}
+
+// Fails if beam_size is 0.
+impl TryFrom<LevenshteinConfig> for WeightedLevenshtein {
+    type Error = Error;
+
+    fn try_from(config: LevenshteinConfig) -> Result<WeightedLevenshtein> {
+        config.weighted()
+    }
+}
+
+impl<'a> TryFrom<&'a SimpleLevenshtein> for WeightedLevenshtein {
+    type Error = Error;
+
+    fn try_from(aut: &'a SimpleLevenshtein) -> Result<WeightedLevenshtein> {
+        LevenshteinConfig::from(aut).weighted()
+    }
+}
@@ -0,0 +1,49 @@
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+
+// Send + Sync so a Levenshtein automaton using one can be shared between
+// threads, e.g. by ParallelBeamSearchAdapter or by a BeamSearchAdapter per
+// thread.
+pub trait CostModel: Send + Sync {
+    // The cost of reading inp where the query has query.
+    fn substitute(&self, query: char, inp: char) -> f64;
+}
+
+// Every substitution costs 1.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UnitCost;
+
+impl CostModel for UnitCost {
+    fn substitute(&self, _query: char, _inp: char) -> f64 {
+        1.0
+    }
+}
+
+// Substituting characters which are the same once their diacritics are
+// stripped (é and e, ü and u) costs folded_cost, and anything else 1.
+#[cfg(feature = "unicode-normalization")]
+#[derive(Copy, Clone, Debug)]
+pub struct DiacriticFolding {
+    pub folded_cost: f64,
+}
+
+#[cfg(feature = "unicode-normalization")]
+fn fold(chr: char) -> Option<char> {
+    let mut base = None;
+    decompose_canonical(chr, |part| {
+        if base.is_none() && !is_combining_mark(part) {
+            base = Some(part);
+        }
+    });
+    base
+}
+
+#[cfg(feature = "unicode-normalization")]
+impl CostModel for DiacriticFolding {
+    fn substitute(&self, query: char, inp: char) -> f64 {
+        match (fold(query), fold(inp)) {
+            (Some(query), Some(inp)) if query == inp => self.folded_cost,
+            _ => 1.0,
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use smallvec::{self, SmallVec};
+
+use adapters::{WeightedNFA, BeamSearchAdapter, DFAUtf8Adapter,
+               AutomatonDFAAdapter, compare_weights, WeightedStateAutomaton};
+use config::SearchConfig;
+use encoding::Utf8Encoder;
+use error::Result;
+
+// Scores keys by the negative length of their longest common subsequence
+// with the query, so the lightest key has the most in common with it. Only
+// insertions and deletions are allowed, and they're free, so there's no
+// substitution and each char in common takes 1 off the weight. Keys with
+// fewer than min_common chars in common don't match.
+//
+// Weights go down along a path rather than up, so thresholds don't prune
+// anything and it's left to the beam size and min_common.
+#[derive(Clone, Debug)]
+pub struct LcsNFA {
+    query: Vec<char>,
+    min_common: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LcsState {
+    // position in the query
+    pub chars: usize,
+    // chars in common so far, counted up to min_common so that states which
+    // have enough don't needlessly differ
+    pub common: usize,
+}
+
+impl LcsNFA {
+    pub fn new(query: &str, min_common: usize) -> LcsNFA {
+        LcsNFA {
+            query: query.chars().collect(),
+            min_common: min_common,
+        }
+    }
+
+    pub fn query(&self) -> &[char] {
+        self.query.as_slice()
+    }
+}
+
+pub type LcsNextStates = smallvec::IntoIter<[(LcsState, f64); 2]>;
+
+impl WeightedNFA for LcsNFA {
+    type State = LcsState;
+    type NextStateIter<'a> = LcsNextStates;
+    type InputType = char;
+
+    fn start(&self) -> LcsState {
+        LcsState { chars: 0, common: 0 }
+    }
+
+    fn is_match(&self, state: &LcsState) -> bool {
+        state.common >= self.min_common
+    }
+
+    fn can_match(&self, state: &LcsState) -> bool {
+        state.common + (self.query.len() - state.chars) >= self.min_common
+    }
+
+    fn will_always_match(&self, _state: &LcsState) -> bool {
+        false
+    }
+
+    fn accept<'a>(&'a self, state: &LcsState, inp: char) -> LcsNextStates {
+        let mut next: SmallVec<[(LcsState, f64); 2]> = SmallVec::new();
+        // deleting up to the next occurrence of inp and matching it is
+        // always at least as good as matching a later one
+        if let Some(offset) = self.query[state.chars..].iter().position(|&chr| chr == inp) {
+            next.push((LcsState {
+                chars: state.chars + offset + 1,
+                common: (state.common + 1).min(self.min_common),
+            }, -1.0));
+        }
+        // inserting
+        next.push((*state, 0.0));
+        next.into_iter()
+    }
+}
+
+pub type LcsStack = AutomatonDFAAdapter<DFAUtf8Adapter<BeamSearchAdapter<LcsNFA>>>;
+
+pub fn mk_lcs(query: &str, min_common: usize, config: &SearchConfig) -> Result<LcsStack> {
+    Ok(AutomatonDFAAdapter(DFAUtf8Adapter::new(Utf8Encoder,
+        config.beam_search(LcsNFA::new(query, min_common))?)))
+}
+
+// The negative length of the longest common subsequence, as far as the beam
+// kept it.
+impl WeightedStateAutomaton for LcsStack {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let weights = state.0.iter().filter_map(|&(state, weight)|
+            if (self.0).inner.aut.is_match(&state) {
+                Some(weight)
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
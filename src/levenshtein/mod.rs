@@ -1,2 +1,17 @@
 pub mod unweighted;
 pub mod weighted;
+pub mod script;
+pub mod costs;
+pub mod ocr;
+pub mod multi;
+pub mod tokens;
+pub mod myers;
+pub mod wrappers;
+pub mod config;
+pub mod lcs;
+pub mod prefix;
+#[cfg(feature = "simd")]
+pub mod scan;
+
+pub use self::wrappers::{Levenshtein, WeightedLevenshtein};
+pub use self::config::LevenshteinConfig;
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use fst::automaton::Automaton;
+
+use adapters::Utf8Buffer;
+
+#[derive(Clone, Debug, Default)]
+struct TrieNode {
+    // sorted by char
+    children: Vec<(char, u32)>,
+    // queries ending here
+    ends: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MultiLevenshteinState {
+    // (trie node, distance), at most one per node, closed under deletion
+    states: Vec<(u32, u32)>,
+    buffer: Utf8Buffer,
+}
+
+// Matches keys within max_dist edits of any of the queries. The queries are
+// put in a trie, so prefixes they share are only stepped once.
+#[derive(Clone, Debug)]
+pub struct MultiLevenshtein {
+    nodes: Vec<TrieNode>,
+    max_dist: u32,
+}
+
+impl MultiLevenshtein {
+    pub fn new(queries: &[&str], max_dist: u32) -> MultiLevenshtein {
+        let mut nodes = vec![TrieNode::default()];
+        for (query_idx, query) in queries.iter().enumerate() {
+            let mut node = 0;
+            for chr in query.chars() {
+                node = match nodes[node].children.binary_search_by_key(&chr, |&(c, _)| c) {
+                    Ok(idx) => nodes[node].children[idx].1 as usize,
+                    Err(idx) => {
+                        let child = nodes.len();
+                        nodes[node].children.insert(idx, (chr, child as u32));
+                        nodes.push(TrieNode::default());
+                        child
+                    }
+                };
+            }
+            nodes[node].ends.push(query_idx);
+        }
+        MultiLevenshtein { nodes: nodes, max_dist: max_dist }
+    }
+
+    fn child(&self, node: u32, chr: char) -> Option<u32> {
+        let children = &self.nodes[node as usize].children;
+        children.binary_search_by_key(&chr, |&(c, _)| c).ok()
+            .map(|idx| children[idx].1)
+    }
+
+    // Adds everything reachable by deleting query characters, and keeps
+    // the lowest distance for each node.
+    fn close(&self, mut best: HashMap<u32, u32>) -> Vec<(u32, u32)> {
+        let mut todo: Vec<(u32, u32)> = best.iter().map(|(&node, &dist)| (node, dist)).collect();
+        while let Some((node, dist)) = todo.pop() {
+            if dist >= self.max_dist || best.get(&node) != Some(&dist) {
+                continue;
+            }
+            for &(_, child) in self.nodes[node as usize].children.iter() {
+                let improved = best.get(&child).map_or(true, |&old| dist + 1 < old);
+                if improved {
+                    best.insert(child, dist + 1);
+                    todo.push((child, dist + 1));
+                }
+            }
+        }
+        let mut states: Vec<(u32, u32)> = best.into_iter().collect();
+        states.sort();
+        states
+    }
+
+    fn step(&self, states: &[(u32, u32)], chr: char) -> Vec<(u32, u32)> {
+        let mut best: HashMap<u32, u32> = HashMap::new();
+        {
+            let mut add = |node: u32, dist: u32| {
+                let entry = best.entry(node).or_insert(dist);
+                if dist < *entry {
+                    *entry = dist;
+                }
+            };
+            for &(node, dist) in states {
+                // match
+                if let Some(child) = self.child(node, chr) {
+                    add(child, dist);
+                }
+                if dist < self.max_dist {
+                    // insertion
+                    add(node, dist + 1);
+                    // substitution
+                    for &(_, child) in self.nodes[node as usize].children.iter() {
+                        add(child, dist + 1);
+                    }
+                }
+            }
+        }
+        self.close(best)
+    }
+
+    // Indices of the queries the key read so far is within max_dist of.
+    pub fn matching_queries(&self, state: &MultiLevenshteinState) -> Vec<usize> {
+        if !state.buffer.is_empty() {
+            return vec![];
+        }
+        let mut matching: Vec<usize> = state.states.iter()
+            .flat_map(|&(node, _)| self.nodes[node as usize].ends.iter().cloned())
+            .collect();
+        matching.sort();
+        matching
+    }
+
+    // The queries the key read so far is within max_dist of, each with its
+    // distance, by query index.
+    pub fn matching_distances(&self, state: &MultiLevenshteinState) -> Vec<(usize, u32)> {
+        if !state.buffer.is_empty() {
+            return vec![];
+        }
+        let mut matching: Vec<(usize, u32)> = state.states.iter()
+            .flat_map(|&(node, dist)|
+                self.nodes[node as usize].ends.iter().map(move |&query| (query, dist)))
+            .collect();
+        // each query ends at one node, which is in the state at most once
+        matching.sort();
+        matching
+    }
+
+    // The distance to the closest query, if any is within max_dist.
+    pub fn distance(&self, state: &MultiLevenshteinState) -> Option<u32> {
+        if !state.buffer.is_empty() {
+            return None;
+        }
+        state.states.iter()
+            .filter(|&&(node, _)| !self.nodes[node as usize].ends.is_empty())
+            .map(|&(_, dist)| dist).min()
+    }
+}
+
+impl Automaton for MultiLevenshtein {
+    type State = MultiLevenshteinState;
+
+    fn start(&self) -> MultiLevenshteinState {
+        let mut best = HashMap::new();
+        best.insert(0, 0);
+        MultiLevenshteinState {
+            states: self.close(best),
+            buffer: Utf8Buffer::new(),
+        }
+    }
+
+    fn is_match(&self, state: &MultiLevenshteinState) -> bool {
+        self.distance(state).is_some()
+    }
+
+    fn can_match(&self, state: &MultiLevenshteinState) -> bool {
+        !state.states.is_empty()
+    }
+
+    fn accept(&self, state: &MultiLevenshteinState, byte: u8) -> MultiLevenshteinState {
+        let mut buffer = state.buffer;
+        match buffer.push_utf8(byte) {
+            Some(chr) => MultiLevenshteinState {
+                states: self.step(&state.states, chr),
+                buffer: Utf8Buffer::new(),
+            },
+            None if buffer.is_full() => MultiLevenshteinState {
+                // not UTF-8, so can't match anything
+                states: vec![],
+                buffer: Utf8Buffer::new(),
+            },
+            None => MultiLevenshteinState {
+                states: state.states.clone(),
+                buffer: buffer,
+            },
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use fst::automaton::Automaton;
+
+use adapters::Utf8Buffer;
+use error::{Error, Result};
+
+// The distance to the whole query from what's been read so far, in Myers'
+// bit-vector form: bit i of pv/mv is set when the distance to the first
+// i + 1 query chars is one more/less than to the first i.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MyersState {
+    pv: u64,
+    mv: u64,
+    distance: u32,
+    chars: u32,
+    buffer: Utf8Buffer,
+}
+
+// Bounded Levenshtein for queries of up to 64 chars, stepping all of the
+// query at once using bit-parallelism rather than going through an NFA.
+#[derive(Clone, Debug)]
+pub struct MyersLevenshtein {
+    // which query positions have each char
+    peq: HashMap<char, u64>,
+    len: u32,
+    max_dist: u32,
+}
+
+impl MyersLevenshtein {
+    // Errors if the query is longer than 64 chars.
+    pub fn new(query: &str, max_dist: u32) -> Result<MyersLevenshtein> {
+        let mut peq = HashMap::new();
+        let mut len = 0;
+        for (idx, chr) in query.chars().enumerate() {
+            if idx >= 64 {
+                return Err(Error::InvalidConfig(
+                    "query too long for MyersLevenshtein, max 64 chars".to_owned()));
+            }
+            *peq.entry(chr).or_insert(0) |= 1 << idx;
+            len += 1;
+        }
+        Ok(MyersLevenshtein { peq: peq, len: len, max_dist: max_dist })
+    }
+
+    fn mask(&self) -> u64 {
+        if self.len == 64 { !0 } else { (1 << self.len) - 1 }
+    }
+
+    fn step(&self, state: &MyersState, chr: char) -> MyersState {
+        let chars = state.chars + 1;
+        if self.len == 0 {
+            return MyersState {
+                distance: state.distance + 1,
+                chars: chars,
+                buffer: Utf8Buffer::new(),
+                ..*state
+            };
+        }
+        let mask = self.mask();
+        let last = 1 << (self.len - 1);
+        let (pv, mv) = (state.pv, state.mv);
+        let eq = self.peq.get(&chr).cloned().unwrap_or(0);
+        let xv = eq | mv;
+        let xh = (((eq & pv).wrapping_add(pv)) ^ pv) | eq;
+        let mut ph = mv | !(xh | pv);
+        let mut mh = pv & xh;
+        let mut distance = state.distance;
+        if ph & last != 0 {
+            distance += 1;
+        } else if mh & last != 0 {
+            distance -= 1;
+        }
+        // the whole key is inserted before the start of the query, so the
+        // top row always goes up by one
+        ph = (ph << 1) | 1;
+        mh <<= 1;
+        MyersState {
+            pv: (mh | !(xv | ph)) & mask,
+            mv: (ph & xv) & mask,
+            distance: distance,
+            chars: chars,
+            buffer: Utf8Buffer::new(),
+        }
+    }
+
+    // The lowest distance to any prefix of the query, which can only go up
+    // as more is read.
+    fn min_distance(&self, state: &MyersState) -> u32 {
+        let mut distance = state.chars;
+        let mut min = distance;
+        for idx in 0..self.len {
+            if state.pv & (1 << idx) != 0 {
+                distance += 1;
+            } else if state.mv & (1 << idx) != 0 {
+                distance -= 1;
+            }
+            if distance < min {
+                min = distance;
+            }
+        }
+        min
+    }
+
+    // The distance to the query, if it's within max_dist.
+    pub fn distance(&self, state: &MyersState) -> Option<u32> {
+        if state.buffer.is_empty() && state.distance <= self.max_dist {
+            Some(state.distance)
+        } else {
+            None
+        }
+    }
+}
+
+impl Automaton for MyersLevenshtein {
+    type State = MyersState;
+
+    fn start(&self) -> MyersState {
+        MyersState {
+            pv: self.mask(),
+            mv: 0,
+            distance: self.len,
+            chars: 0,
+            buffer: Utf8Buffer::new(),
+        }
+    }
+
+    fn is_match(&self, state: &MyersState) -> bool {
+        self.distance(state).is_some()
+    }
+
+    fn can_match(&self, state: &MyersState) -> bool {
+        self.min_distance(state) <= self.max_dist
+    }
+
+    fn accept(&self, state: &MyersState, byte: u8) -> MyersState {
+        let mut buffer = state.buffer;
+        match buffer.push_utf8(byte) {
+            Some(chr) => self.step(state, chr),
+            // not UTF-8, so nothing can match any more
+            None if buffer.is_full() => MyersState {
+                distance: self.max_dist + 1,
+                chars: self.max_dist + 1,
+                pv: 0,
+                mv: 0,
+                buffer: Utf8Buffer::new(),
+            },
+            None => MyersState { buffer: buffer, ..*state },
+        }
+    }
+}
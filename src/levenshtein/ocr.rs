@@ -0,0 +1,155 @@
+use adapters::{WeightedNFA, BeamSearchAdapter, DFAUtf8Adapter,
+               AutomatonDFAAdapter, compare_weights, WeightedStateAutomaton};
+use config::SearchConfig;
+use encoding::Utf8Encoder;
+use error::{Error, Result};
+use levenshtein::weighted::{WeightedLevenshteinNFA, LevenshteinNextStates, LevenshteinState};
+
+// from in the query may be read as to in the key at the given cost, e.g.
+// rn -> m when the OCR has split an m.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OcrRule {
+    pub from: Vec<char>,
+    pub to: Vec<char>,
+    pub cost: f64,
+}
+
+impl OcrRule {
+    pub fn new(from: &str, to: &str, cost: f64) -> Result<OcrRule> {
+        if from.is_empty() || to.is_empty() {
+            return Err(Error::InvalidConfig(
+                format!("OCR rule {:?} -> {:?} can't have an empty side", from, to)));
+        }
+        Ok(OcrRule {
+            from: from.chars().collect(),
+            to: to.chars().collect(),
+            cost: cost,
+        })
+    }
+}
+
+// Weighted Levenshtein plus merge/split operations from a table of rules.
+#[derive(Debug)]
+pub struct OcrLevenshteinNFA {
+    levenshtein: WeightedLevenshteinNFA,
+    rules: Vec<OcrRule>,
+}
+
+impl OcrLevenshteinNFA {
+    pub fn new(query: &str, rules: Vec<OcrRule>) -> OcrLevenshteinNFA {
+        OcrLevenshteinNFA {
+            levenshtein: WeightedLevenshteinNFA::new(query),
+            rules: rules,
+        }
+    }
+}
+
+// Position in the query, and the rule and how much of its to part has been
+// read if part way through one.
+pub type OcrState = (usize, Option<(usize, usize)>);
+
+pub struct OcrNextStates<'a> {
+    levenshtein: Option<LevenshteinNextStates<'a>>,
+    rules: &'a [OcrRule],
+    query: &'a [char],
+    chars: usize,
+    pending: Option<(usize, usize)>,
+    // next rule to try starting
+    rule: usize,
+    inp: char,
+}
+
+impl<'a> OcrNextStates<'a> {
+    fn continue_rule(&self, rule: usize, read: usize) -> Option<(OcrState, f64)> {
+        let rule_def = &self.rules[rule];
+        if rule_def.to[read] != self.inp {
+            return None;
+        }
+        if read + 1 == rule_def.to.len() {
+            Some(((self.chars + rule_def.from.len(), None), rule_def.cost))
+        } else {
+            Some(((self.chars, Some((rule, read + 1))), 0.0))
+        }
+    }
+}
+
+impl<'a> Iterator for OcrNextStates<'a> {
+    type Item = (OcrState, f64);
+
+    fn next(&mut self) -> Option<(OcrState, f64)> {
+        if let Some((state, weight)) = self.levenshtein.as_mut().and_then(Iterator::next) {
+            return Some(((state.chars, None), weight));
+        }
+        self.levenshtein = None;
+        if let Some((rule, read)) = self.pending.take() {
+            return self.continue_rule(rule, read);
+        }
+        while self.rule < self.rules.len() {
+            let rule = self.rule;
+            self.rule += 1;
+            if self.query[self.chars..].starts_with(&self.rules[rule].from) {
+                if let Some(next) = self.continue_rule(rule, 0) {
+                    return Some(next);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl WeightedNFA for OcrLevenshteinNFA {
+    type State = OcrState;
+    type NextStateIter<'a> = OcrNextStates<'a>;
+    type InputType = char;
+
+    fn start(&self) -> Self::State {
+        (self.levenshtein.start().chars, None)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        let &(chars, pending) = state;
+        pending.is_none() && self.levenshtein.is_match(&LevenshteinState::new(chars))
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: char) -> OcrNextStates<'a> {
+        let &(chars, pending) = state;
+        OcrNextStates {
+            // no ordinary edits in the middle of a rule
+            levenshtein: match pending {
+                Some(_) => None,
+                None => Some(self.levenshtein.accept(&LevenshteinState::new(chars), inp)),
+            },
+            rules: self.rules.as_slice(),
+            query: self.levenshtein.query(),
+            chars: chars,
+            pending: pending,
+            rule: match pending {
+                Some(_) => self.rules.len(),
+                None => 0,
+            },
+            inp: inp,
+        }
+    }
+}
+
+pub type OcrLevenshteinStack = AutomatonDFAAdapter<
+    DFAUtf8Adapter<BeamSearchAdapter<OcrLevenshteinNFA>>>;
+
+pub fn mk_ocr_levenshtein(query: &str, rules: Vec<OcrRule>, config: &SearchConfig)
+        -> Result<OcrLevenshteinStack> {
+    Ok(AutomatonDFAAdapter(DFAUtf8Adapter::new(Utf8Encoder,
+        config.beam_search(OcrLevenshteinNFA::new(query, rules))?)))
+}
+
+impl WeightedStateAutomaton for OcrLevenshteinStack {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let weights = state.0.iter().filter_map(|&(ref state, weight)|
+            if (self.0).inner.aut.is_match(state) {
+                Some(weight)
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
@@ -0,0 +1,163 @@
+use adapters::{WeightedNFA, FollowEpsilonNFA, BeamSearchAdapter, DFAUtf8Adapter,
+               AutomatonDFAAdapter, compare_weights, WeightedStateAutomaton};
+use config::SearchConfig;
+use encoding::Utf8Encoder;
+use error::Result;
+use levenshtein::weighted::WeightedLevenshteinNFA;
+
+// The prefix length Jaro-Winkler stops rewarding at.
+pub const DEFAULT_MAX_PREFIX: usize = 4;
+
+// Adds boost to the weight for each of the query's first max_prefix chars
+// which the key doesn't share as an exact prefix, approximating how
+// Jaro-Winkler ranks names sharing a longer prefix with the query higher.
+// Wrapping weighted Levenshtein with a boost well under 1 keeps distance
+// the main criterion and breaks ties by prefix, as Jaro-Winkler does.
+// Charging for the prefix missed rather than crediting the prefix matched
+// ranks the same, but keeps weights from shrinking along a path, which the
+// beam search relies on. The threshold has to allow for the charge.
+//
+// The state is the wrapped one and how much of the prefix has been
+// matched, or None once it's been broken or fully matched. The rest of the
+// charge is taken when it's broken, or as a final weight if the key ends
+// first.
+#[derive(Clone, Debug)]
+pub struct PrefixBoostNFA<Wrapped: WeightedNFA<InputType=char>> {
+    pub aut: Wrapped,
+    query: Vec<char>,
+    pub boost: f64,
+    pub max_prefix: usize,
+}
+
+impl<Wrapped: WeightedNFA<InputType=char>> PrefixBoostNFA<Wrapped> {
+    pub fn new(aut: Wrapped, query: &str, boost: f64) -> PrefixBoostNFA<Wrapped> {
+        PrefixBoostNFA {
+            aut: aut,
+            query: query.chars().collect(),
+            boost: boost,
+            max_prefix: DEFAULT_MAX_PREFIX,
+        }
+    }
+
+    fn prefix_len(&self) -> usize {
+        self.max_prefix.min(self.query.len())
+    }
+
+    // The charge for a prefix which stops at len.
+    fn missed(&self, len: usize) -> f64 {
+        (self.prefix_len() - len) as f64 * self.boost
+    }
+
+    // The prefix matched after reading inp, and the charge.
+    fn extend(&self, prefix: Option<usize>, inp: char) -> (Option<usize>, f64) {
+        match prefix {
+            Some(len) if self.query.get(len) == Some(&inp) => {
+                let len = len + 1;
+                if len < self.prefix_len() {
+                    (Some(len), 0.0)
+                } else {
+                    (None, 0.0)
+                }
+            }
+            Some(len) => (None, self.missed(len)),
+            None => (None, 0.0),
+        }
+    }
+}
+
+pub struct PrefixBoostNextStates<I> {
+    inner: I,
+    prefix: Option<usize>,
+    delta: f64,
+}
+
+impl<S, I: Iterator<Item=(S, f64)>> Iterator for PrefixBoostNextStates<I> {
+    type Item = ((S, Option<usize>), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(state, weight)|
+            ((state, self.prefix), weight + self.delta))
+    }
+}
+
+impl<Wrapped: WeightedNFA<InputType=char>> WeightedNFA for PrefixBoostNFA<Wrapped> {
+    type State = (Wrapped::State, Option<usize>);
+    type NextStateIter<'a> = PrefixBoostNextStates<Wrapped::NextStateIter<'a>> where Self: 'a;
+    type InputType = char;
+
+    fn start(&self) -> Self::State {
+        let prefix = if self.max_prefix > 0 && !self.query.is_empty() {
+            Some(0)
+        } else {
+            None
+        };
+        (self.aut.start(), prefix)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.aut.is_match(&state.0)
+    }
+
+    fn final_weight(&self, state: &Self::State) -> f64 {
+        self.aut.final_weight(&state.0) + state.1.map_or(0.0, |len| self.missed(len))
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.aut.can_match(&state.0)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.aut.will_always_match(&state.0)
+    }
+
+    // nothing more is charged once the prefix is settled
+    fn is_free_sink(&self, state: &Self::State) -> bool {
+        state.1.is_none() && self.aut.is_free_sink(&state.0)
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: char) -> Self::NextStateIter<'a> {
+        let &(ref inner, prefix) = state;
+        let (prefix, delta) = self.extend(prefix, inp);
+        PrefixBoostNextStates {
+            inner: self.aut.accept(inner, inp),
+            prefix: prefix,
+            delta: delta,
+        }
+    }
+}
+
+impl<Wrapped> FollowEpsilonNFA for PrefixBoostNFA<Wrapped>
+        where Wrapped: FollowEpsilonNFA<InputType=char> {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        let &(ref inner, prefix) = state;
+        PrefixBoostNextStates {
+            inner: self.aut.follow_epsilon(inner),
+            prefix: prefix,
+            delta: 0.0,
+        }
+    }
+}
+
+pub type JaroWinklerStack = AutomatonDFAAdapter<
+    DFAUtf8Adapter<BeamSearchAdapter<PrefixBoostNFA<WeightedLevenshteinNFA>>>>;
+
+// Weighted Levenshtein with a prefix boost, for ranking names.
+pub fn mk_jaro_winkler(query: &str, boost: f64, config: &SearchConfig)
+        -> Result<JaroWinklerStack> {
+    let aut = PrefixBoostNFA::new(WeightedLevenshteinNFA::new(query), query, boost);
+    Ok(AutomatonDFAAdapter(DFAUtf8Adapter::new(Utf8Encoder, config.beam_search(aut)?)))
+}
+
+impl WeightedStateAutomaton for JaroWinklerStack {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let aut = &(self.0).inner.aut;
+        let weights = state.0.iter().filter_map(|&(ref state, weight)|
+            if aut.is_match(state) {
+                Some(weight + aut.final_weight(state))
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
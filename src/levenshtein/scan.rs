@@ -0,0 +1,50 @@
+// Finding which of the query positions a deletion chain can reach match an
+// input char, a window at a time, rather than comparing position by
+// position as the chain is walked.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__m128i, _mm_set1_epi32, _mm_loadu_si128, _mm_cmpeq_epi32,
+                        _mm_castsi128_ps, _mm_movemask_ps};
+
+// How many positions a mask covers.
+pub const WINDOW: usize = 64;
+
+// Bit i is set if query[from + i] is inp, for the WINDOW positions from
+// from on.
+pub fn match_mask(query: &[char], from: usize, inp: char) -> u64 {
+    if from >= query.len() {
+        return 0;
+    }
+    let end = query.len().min(from + WINDOW);
+    mask_in(&query[from..end], inp)
+}
+
+// SSE2 is always there on x86_64, so needs no runtime detection.
+#[cfg(target_arch = "x86_64")]
+fn mask_in(haystack: &[char], inp: char) -> u64 {
+    let needle = unsafe { _mm_set1_epi32(inp as i32) };
+    let mut chunks = haystack.chunks_exact(4);
+    let mut mask = 0u64;
+    let mut offset = 0;
+    for chunk in &mut chunks {
+        // one bit per char
+        let bits = unsafe {
+            let chars = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            _mm_movemask_ps(_mm_castsi128_ps(_mm_cmpeq_epi32(chars, needle)))
+        };
+        mask |= (bits as u64) << offset;
+        offset += 4;
+    }
+    mask | mask_chunked(chunks.remainder(), inp) << offset
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn mask_in(haystack: &[char], inp: char) -> u64 {
+    mask_chunked(haystack, inp)
+}
+
+// Compares without branching, which the compiler can vectorize.
+fn mask_chunked(haystack: &[char], inp: char) -> u64 {
+    haystack.iter().enumerate()
+        .fold(0u64, |mask, (idx, &chr)| mask | (((chr == inp) as u64) << idx))
+}
@@ -0,0 +1,117 @@
+use std::f64;
+
+use levenshtein::weighted::WeightedLevenshteinNFA;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EditOp {
+    Match(char),
+    // query char, key char
+    Substitute(char, char),
+    // key char which isn't in the query
+    Insert(char),
+    // query char which isn't in the key
+    Delete(char),
+}
+
+// Which way each cell was reached, for backtracking.
+#[derive(Copy, Clone)]
+enum Back {
+    Start,
+    // from after a key char, or from after a deletion
+    Consumed(bool),
+    Inserted,
+    // from after a key char, or from after another deletion
+    Deleted(bool),
+}
+
+impl WeightedLevenshteinNFA {
+    // The lightest way of editing the query into key, and its weight, e.g.
+    // to highlight what was corrected in a result. This is worked out again
+    // from scratch by dynamic programming rather than tracked in the beam
+    // so states stay small. As in the automaton, deletions have to be
+    // followed by a match or substitution. Neither max_insert_run nor
+    // max_edits is taken into account, so with either set the script can
+    // be lighter than the weight the automaton gives key, or be given for a
+    // key the automaton doesn't match. None if key can't be reached at all.
+    pub fn edit_script(&self, key: &str) -> Option<(f64, Vec<EditOp>)> {
+        let query = self.query();
+        let key: Vec<char> = key.chars().collect();
+        let (rows, cols) = (query.len() + 1, key.len() + 1);
+        // consumed[i][j]: the last thing done read key char j - 1
+        // deleted[i][j]: the last thing done was deleting query char i - 1
+        let mut consumed = vec![(f64::INFINITY, Back::Start); rows * cols];
+        let mut deleted = vec![(f64::INFINITY, Back::Start); rows * cols];
+        consumed[0] = (0.0, Back::Start);
+        for i in 0..rows {
+            for j in 0..cols {
+                let idx = i * cols + j;
+                if i > 0 && i < query.len() {
+                    let from_consumed = consumed[idx - cols].0 + 1.0;
+                    let from_deleted = deleted[idx - cols].0 + 1.0;
+                    deleted[idx] = if from_deleted < from_consumed {
+                        (from_deleted, Back::Deleted(true))
+                    } else {
+                        (from_consumed, Back::Deleted(false))
+                    };
+                }
+                if j == 0 {
+                    continue;
+                }
+                if i > 0 {
+                    let cost = if query[i - 1] == key[j - 1] {
+                        0.0
+                    } else {
+                        self.costs().substitute(query[i - 1], key[j - 1])
+                    };
+                    let from_consumed = consumed[idx - cols - 1].0 + cost;
+                    let from_deleted = deleted[idx - cols - 1].0 + cost;
+                    consumed[idx] = if from_deleted < from_consumed {
+                        (from_deleted, Back::Consumed(true))
+                    } else {
+                        (from_consumed, Back::Consumed(false))
+                    };
+                }
+                let inserted = consumed[idx - 1].0 + 1.0;
+                if inserted < consumed[idx].0 {
+                    consumed[idx] = (inserted, Back::Inserted);
+                }
+            }
+        }
+
+        let weight = consumed[rows * cols - 1].0;
+        if weight == f64::INFINITY {
+            return None;
+        }
+        let mut script = vec![];
+        let (mut i, mut j, mut in_deleted) = (query.len(), key.len(), false);
+        loop {
+            let idx = i * cols + j;
+            let back = if in_deleted { deleted[idx].1 } else { consumed[idx].1 };
+            match back {
+                Back::Start => break,
+                Back::Consumed(from_deleted) => {
+                    script.push(if query[i - 1] == key[j - 1] {
+                        EditOp::Match(key[j - 1])
+                    } else {
+                        EditOp::Substitute(query[i - 1], key[j - 1])
+                    });
+                    i -= 1;
+                    j -= 1;
+                    in_deleted = from_deleted;
+                }
+                Back::Inserted => {
+                    script.push(EditOp::Insert(key[j - 1]));
+                    j -= 1;
+                    in_deleted = false;
+                }
+                Back::Deleted(from_deleted) => {
+                    script.push(EditOp::Delete(query[i - 1]));
+                    i -= 1;
+                    in_deleted = from_deleted;
+                }
+            }
+        }
+        script.reverse();
+        Some((weight, script))
+    }
+}
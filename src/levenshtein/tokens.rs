@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use smallvec::{self, SmallVec};
+
+use adapters::{WeightedNFA, FollowEpsilonNFA,
+               EpsilonExpandingBeamSearchAdapter, DFATokenAdapter, DFA,
+               AutomatonDFAAdapter, compare_weights, WeightedStateAutomaton};
+use config::SearchConfig;
+use encoding::{InputEncoder, TokenEncoder};
+use error::Result;
+use tokenize::Tokenizer;
+
+// Levenshtein where the alphabet is whole tokens, numbered as by the
+// TokenEncoder from encoder. Deletions are epsilons, so it should be stepped
+// with EpsilonExpandingBeamSearchAdapter.
+#[derive(Clone, Debug)]
+pub struct TokenLevenshteinNFA {
+    query: Vec<u64>,
+    // the query's distinct tokens, which the numbers in query index
+    tokens: Arc<Vec<Vec<u8>>>,
+}
+
+impl TokenLevenshteinNFA {
+    pub fn new(query: &str, separator: u8) -> TokenLevenshteinNFA {
+        TokenLevenshteinNFA::from_tokens(query.as_bytes()
+            .split(|&byte| byte == separator)
+            .filter(|token| !token.is_empty()))
+    }
+
+    // Splits the query with a tokenizer rather than on the separator, for
+    // keys built with join_tokens.
+    pub fn with_tokenizer(query: &str, tokenizer: &dyn Tokenizer) -> TokenLevenshteinNFA {
+        TokenLevenshteinNFA::from_tokens(tokenizer.tokenize(query).into_iter()
+            .map(|span| query[span].as_bytes()))
+    }
+
+    fn from_tokens<'a, I: Iterator<Item=&'a [u8]>>(query: I) -> TokenLevenshteinNFA {
+        let mut tokens: Vec<Vec<u8>> = vec![];
+        let query = query.map(|token| {
+            match tokens.iter().position(|known| known.as_slice() == token) {
+                Some(number) => number as u64,
+                None => {
+                    tokens.push(token.to_vec());
+                    (tokens.len() - 1) as u64
+                }
+            }
+        }).collect();
+        TokenLevenshteinNFA { query: query, tokens: Arc::new(tokens) }
+    }
+
+    // Splits keys on the separator and numbers their tokens to match.
+    pub fn encoder(&self, separator: u8) -> TokenEncoder {
+        TokenEncoder::new(separator, self.tokens.clone())
+    }
+}
+
+pub type TokenNextStates = smallvec::IntoIter<[(usize, f64); 2]>;
+
+impl WeightedNFA for TokenLevenshteinNFA {
+    type State = usize;
+    type NextStateIter<'a> = TokenNextStates;
+    type InputType = u64;
+
+    fn start(&self) -> Self::State {
+        0
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == self.query.len()
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: u64) -> TokenNextStates {
+        let mut next: SmallVec<[(usize, f64); 2]> = SmallVec::new();
+        let tokens = *state;
+        if tokens < self.query.len() {
+            // match or substitution
+            let cost = if self.query[tokens] == inp { 0.0 } else { 1.0 };
+            next.push((tokens + 1, cost));
+        }
+        // insertion
+        next.push((tokens, 1.0));
+        next.into_iter()
+    }
+}
+
+impl FollowEpsilonNFA for TokenLevenshteinNFA {
+    // deletion
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> TokenNextStates {
+        let mut next: SmallVec<[(usize, f64); 2]> = SmallVec::new();
+        if *state < self.query.len() {
+            next.push((*state + 1, 1.0));
+        }
+        next.into_iter()
+    }
+}
+
+pub type TokenLevenshteinStack = AutomatonDFAAdapter<
+    DFATokenAdapter<EpsilonExpandingBeamSearchAdapter<TokenLevenshteinNFA>>>;
+
+pub fn mk_token_levenshtein(query: &str, separator: u8, config: &SearchConfig)
+        -> Result<TokenLevenshteinStack> {
+    let nfa = TokenLevenshteinNFA::new(query, separator);
+    Ok(AutomatonDFAAdapter(DFATokenAdapter::new(
+        nfa.encoder(separator),
+        EpsilonExpandingBeamSearchAdapter::new(config.beam_search(nfa)?))))
+}
+
+impl WeightedStateAutomaton for TokenLevenshteinStack {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let inner = &(self.0).inner;
+        let beam = match (self.0).encoder.finish(&state.1) {
+            Some(token) => inner.accept(&state.0, token),
+            None => state.0.clone(),
+        };
+        let weights = beam.iter().filter_map(|&(state, weight)|
+            if inner.inner().aut.is_match(&state) {
+                Some(weight)
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
@@ -70,13 +70,13 @@ impl SimpleLevenshteinState {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CompoundLevenshteinState {
     states: Vec<SimpleLevenshteinState>,
     buffer: Vec<u8>
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SimpleLevenshtein {
     query: Vec<char>,
     threshold: u64
@@ -87,6 +87,14 @@ impl SimpleLevenshtein {
         let query_vec = query.chars().collect();
         SimpleLevenshtein { query: query_vec, threshold: threshold }
     }
+
+    pub fn query(&self) -> String {
+        self.query.iter().collect()
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
 }
 
 impl Automaton for SimpleLevenshtein {
@@ -1,19 +1,88 @@
 use adapters::{WeightedNFA, BeamSearchAdapter, DFAUtf8Adapter,
                AutomatonDFAAdapter, compare_weights, WeightedStateAutomaton};
+use config::SearchConfig;
+use encoding::Utf8Encoder;
+use error::Result;
+use levenshtein::costs::{CostModel, UnitCost};
+#[cfg(feature = "simd")]
+use levenshtein::scan::{match_mask, WINDOW};
+
+use std::fmt;
 
-use std::sync::Arc;
 use fst::Automaton;
 
 pub struct WeightedLevenshteinNFA {
-    query: Arc<Vec<char>>
+    query: Vec<char>,
+    costs: Box<dyn CostModel>,
+    max_insert_run: Option<u32>,
+    max_edits: Option<u32>,
+}
+
+// The cost model is a trait object, so is left out.
+impl fmt::Debug for WeightedLevenshteinNFA {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WeightedLevenshteinNFA")
+            .field("query", &self.query)
+            .field("max_insert_run", &self.max_insert_run)
+            .field("max_edits", &self.max_edits)
+            .finish()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LevenshteinState {
+    // position in the query
+    pub chars: usize,
+    // insertions since the last other operation, only counted when there's
+    // a max_insert_run so that states don't needlessly differ otherwise
+    pub insert_run: u32,
+    // edits so far, only counted when there's a max_edits
+    pub edits: u32,
+}
+
+impl LevenshteinState {
+    pub fn new(chars: usize) -> LevenshteinState {
+        LevenshteinState { chars: chars, insert_run: 0, edits: 0 }
+    }
 }
 
 impl WeightedLevenshteinNFA {
     pub fn new(query: &str) -> WeightedLevenshteinNFA {
+        WeightedLevenshteinNFA::with_costs(query, Box::new(UnitCost))
+    }
+
+    pub fn with_costs(query: &str, costs: Box<dyn CostModel>) -> WeightedLevenshteinNFA {
         let mut query_chars = Vec::with_capacity(query.len());
         query_chars.extend(query.chars());
         query_chars.shrink_to_fit();
-        WeightedLevenshteinNFA { query: Arc::new(query_chars) }
+        WeightedLevenshteinNFA {
+            query: query_chars,
+            costs: costs,
+            max_insert_run: None,
+            max_edits: None,
+        }
+    }
+
+    // Stop inserting after this many insertions in a row, so that with a
+    // high threshold the beam doesn't fill up with hypotheses which insert
+    // the whole rest of a long key.
+    pub fn set_max_insert_run(&mut self, max_insert_run: Option<u32>) {
+        self.max_insert_run = max_insert_run;
+    }
+
+    // Limits the number of edits regardless of their weight, which the
+    // beam's threshold limits. With a cost model where some edits are cheap,
+    // many small edits could otherwise beat one expensive one.
+    pub fn set_max_edits(&mut self, max_edits: Option<u32>) {
+        self.max_edits = max_edits;
+    }
+
+    pub fn query(&self) -> &[char] {
+        self.query.as_slice()
+    }
+
+    pub fn costs(&self) -> &dyn CostModel {
+        &*self.costs
     }
 }
 
@@ -21,32 +90,82 @@ enum NextStatesState {
     Match, Insert, Substitute, Delete
 }
 
-pub struct LevenshteinNextStates {
+pub struct LevenshteinNextStates<'a> {
+    nfa: &'a WeightedLevenshteinNFA,
     chars: usize,
-    query: Arc<Vec<char>>,
+    insert_run: u32,
+    edits: u32,
     inp: char,
     state: NextStatesState,
     extra_weight: f64,
-    deleted: bool
+    deleted: bool,
+    // whether the char at this position matched, which makes substituting
+    // it pointless
+    matched: bool,
+    // matches of inp from mask_base on, see scan::match_mask
+    #[cfg(feature = "simd")]
+    mask: u64,
+    #[cfg(feature = "simd")]
+    mask_base: usize
 }
 
-impl LevenshteinNextStates {
-    pub fn new(chars: usize, query: &Arc<Vec<char>>, inp: char) -> LevenshteinNextStates {
+impl<'a> LevenshteinNextStates<'a> {
+    pub fn new(nfa: &'a WeightedLevenshteinNFA, state: LevenshteinState, inp: char)
+            -> LevenshteinNextStates<'a> {
         LevenshteinNextStates {
-            chars: chars,
-            query: Arc::clone(query),
+            nfa: nfa,
+            chars: state.chars,
+            insert_run: state.insert_run,
+            edits: state.edits,
             inp: inp,
             state: NextStatesState::Match,
             extra_weight: 0.0,
-            deleted: false
+            deleted: false,
+            matched: false,
+            #[cfg(feature = "simd")]
+            mask: match_mask(nfa.query(), state.chars, inp),
+            #[cfg(feature = "simd")]
+            mask_base: state.chars
         }
     }
+
+    #[cfg(feature = "simd")]
+    fn is_match_here(&mut self) -> bool {
+        if self.chars >= self.mask_base + WINDOW {
+            self.mask = match_mask(self.nfa.query(), self.chars, self.inp);
+            self.mask_base = self.chars;
+        }
+        self.mask & (1 << (self.chars - self.mask_base)) != 0
+    }
+
+    #[cfg(not(feature = "simd"))]
+    fn is_match_here(&mut self) -> bool {
+        let query = self.nfa.query();
+        self.chars < query.len() && query[self.chars] == self.inp
+    }
+
+    // The edit count after extra more edits, or None if that's too many.
+    fn edits_after(&self, extra: u32) -> Option<u32> {
+        match self.nfa.max_edits {
+            None => Some(0),
+            Some(max) if self.edits + extra <= max => Some(self.edits + extra),
+            Some(_) => None,
+        }
+    }
+
+    fn next_state(&self, chars: usize, extra: u32) -> Option<LevenshteinState> {
+        self.edits_after(extra).map(|edits| LevenshteinState {
+            chars: chars,
+            insert_run: 0,
+            edits: edits,
+        })
+    }
 }
 
-impl Iterator for LevenshteinNextStates {
-    type Item = (usize, f64);
+impl<'a> Iterator for LevenshteinNextStates<'a> {
+    type Item = (LevenshteinState, f64);
 
-    fn next(&mut self) -> Option<(usize, f64)> {
+    fn next(&mut self) -> Option<(LevenshteinState, f64)> {
         // I heard you like state machines...
         loop {
             match self.state {
@@ -55,8 +174,11 @@ impl Iterator for LevenshteinNextStates {
                 // should match: a|bc
                 NextStatesState::Match => {
                     self.state = NextStatesState::Substitute;
-                    if self.chars < self.query.len() && self.query[self.chars] == self.inp {
-                        return Some((self.chars + 1, 0.0 + self.extra_weight));
+                    self.matched = self.is_match_here();
+                    if self.matched {
+                        if let Some(next) = self.next_state(self.chars + 1, 0) {
+                            return Some((next, 0.0 + self.extra_weight));
+                        }
                     }
                 }
                 // substitution
@@ -64,10 +186,16 @@ impl Iterator for LevenshteinNextStates {
                 // should match: a|sc
                 // => increment query pointer
                 // &  let to match pointer move on one step
+                // (skipped after a match, which reaches the same state for
+                // less weight and fewer edits)
                 NextStatesState::Substitute => {
                     self.state = NextStatesState::Insert;
-                    if self.chars < self.query.len() {
-                        return Some((self.chars + 1, 1.0 + self.extra_weight));
+                    let query = self.nfa.query();
+                    if !self.matched && self.chars < query.len() {
+                        if let Some(next) = self.next_state(self.chars + 1, 1) {
+                            let cost = self.nfa.costs().substitute(query[self.chars], self.inp);
+                            return Some((next, cost + self.extra_weight));
+                        }
                     }
                 }
                 // insertion
@@ -78,7 +206,16 @@ impl Iterator for LevenshteinNextStates {
                 NextStatesState::Insert => {
                     self.state = NextStatesState::Delete;
                     if !self.deleted { // eliminate dupes: insertion + deletion = substitution
-                        return Some((self.chars, 1.0 + self.extra_weight));
+                        let insert_run = match self.nfa.max_insert_run {
+                            Some(max) if self.insert_run >= max => None,
+                            Some(_) => Some(self.insert_run + 1),
+                            None => Some(0),
+                        };
+                        if let (Some(insert_run), Some(mut next)) =
+                                (insert_run, self.next_state(self.chars, 1)) {
+                            next.insert_run = insert_run;
+                            return Some((next, 1.0 + self.extra_weight));
+                        }
                     }
                 }
                 // deletion
@@ -87,10 +224,14 @@ impl Iterator for LevenshteinNextStates {
                 // => move query pointer forward one
                 // &  recurse so as to avoid moving match pointer onwards
                 NextStatesState::Delete => {
-                    if self.chars + 1 >= self.query.len() {
+                    if self.chars + 1 >= self.nfa.query().len() {
                         // deleted last character from match, so nothing else can match
                         return None;
                     }
+                    match self.edits_after(1) {
+                        Some(edits) => self.edits = edits,
+                        None => return None,
+                    }
                     self.chars += 1;
                     self.state = NextStatesState::Match;
                     self.extra_weight += 1.0;
@@ -101,18 +242,17 @@ impl Iterator for LevenshteinNextStates {
     }
 }
 
-// A cleaner solution would be probably be possible with associated lifetimes
 impl WeightedNFA for WeightedLevenshteinNFA {
-    type State = usize;
-    type NextStateIter = LevenshteinNextStates;
+    type State = LevenshteinState;
+    type NextStateIter<'a> = LevenshteinNextStates<'a>;
     type InputType = char;
 
     fn start(&self) -> Self::State {
-        0
+        LevenshteinState::new(0)
     }
 
     fn is_match(&self, state: &Self::State) -> bool {
-        *state == self.query.len()
+        state.chars == self.query.len()
     }
 
     fn can_match(&self, _state: &Self::State) -> bool {
@@ -123,26 +263,23 @@ impl WeightedNFA for WeightedLevenshteinNFA {
         false
     }
 
-    fn accept(&self, state: &Self::State, inp: char) -> LevenshteinNextStates {
-        LevenshteinNextStates::new(*state, &self.query, inp)
+    fn accept<'a>(&'a self, state: &Self::State, inp: char) -> LevenshteinNextStates<'a> {
+        LevenshteinNextStates::new(self, *state, inp)
     }
 }
 
 pub type LevenshteinStack = AutomatonDFAAdapter<
     DFAUtf8Adapter<BeamSearchAdapter<WeightedLevenshteinNFA>>>;
 
-pub fn mk_levenshtein(query: &str, threshold: f64, beam_size: usize) -> LevenshteinStack {
-    AutomatonDFAAdapter(DFAUtf8Adapter(BeamSearchAdapter {
-        aut: WeightedLevenshteinNFA::new(query),
-        threshold: threshold,
-        beam_size: beam_size
-    }))
+pub fn mk_levenshtein(query: &str, config: &SearchConfig) -> Result<LevenshteinStack> {
+    Ok(AutomatonDFAAdapter(DFAUtf8Adapter::new(Utf8Encoder,
+        config.beam_search(WeightedLevenshteinNFA::new(query))?)))
 }
 
 impl WeightedStateAutomaton for LevenshteinStack {
     fn get_weight(&self, state: &Self::State) -> f64 {
         let weights = state.0.iter().filter_map(|&(state, weight)|
-            if (self.0).0.aut.is_match(&state) {
+            if (self.0).inner.aut.is_match(&state) {
                 Some(weight)
             } else {
                 None
@@ -153,12 +290,9 @@ impl WeightedStateAutomaton for LevenshteinStack {
 }
 
 pub fn get_levenshtein_weights(aut: &LevenshteinStack, result: &[u8]) -> f64 {
-    let mut state = aut.start();
-    for inp in result {
-        state = aut.accept(&state, *inp);
-    }
+    let state = aut.accept_bytes(&aut.start(), result);
     let weights = state.0.iter().filter_map(|&(state, weight)|
-        if (aut.0).0.aut.is_match(&state) {
+        if (aut.0).inner.aut.is_match(&state) {
             Some(weight)
         } else {
             None
@@ -0,0 +1,84 @@
+use fst::Automaton;
+
+use adapters::WeightedStateAutomaton;
+use config::SearchConfig;
+use error::Result;
+use levenshtein::multi::{MultiLevenshtein, MultiLevenshteinState};
+use levenshtein::weighted::{LevenshteinStack, mk_levenshtein};
+
+// Built in one line like fst-levenshtein's Levenshtein, so either can be
+// dropped in for the other.
+#[derive(Clone, Debug)]
+pub struct Levenshtein(MultiLevenshtein);
+
+impl Levenshtein {
+    // Never fails, but returns a Result to match fst-levenshtein.
+    pub fn new(query: &str, distance: u32) -> Result<Levenshtein> {
+        Ok(Levenshtein(MultiLevenshtein::new(&[query], distance)))
+    }
+}
+
+impl Automaton for Levenshtein {
+    type State = MultiLevenshteinState;
+
+    fn start(&self) -> Self::State {
+        self.0.start()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.0.is_match(state)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.0.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.0.will_always_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        self.0.accept(state, byte)
+    }
+}
+
+// The same for the beam searched weighted Levenshtein, hiding the adapter
+// stack of mk_levenshtein.
+#[derive(Debug)]
+pub struct WeightedLevenshtein(LevenshteinStack);
+
+impl WeightedLevenshtein {
+    pub fn new(query: &str, threshold: f64, beam_size: usize) -> Result<WeightedLevenshtein> {
+        mk_levenshtein(query, &SearchConfig::new(threshold, beam_size)).map(WeightedLevenshtein)
+    }
+}
+
+impl Automaton for WeightedLevenshtein {
+    type State = <LevenshteinStack as Automaton>::State;
+
+    fn start(&self) -> Self::State {
+        self.0.start()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.0.is_match(state)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.0.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.0.will_always_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        self.0.accept(state, byte)
+    }
+}
+
+impl WeightedStateAutomaton for WeightedLevenshtein {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        self.0.get_weight(state)
+    }
+}
@@ -1,7 +1,47 @@
+#[cfg(any(feature = "hfst", feature = "openfst"))]
 #[macro_use] extern crate cpp;
 extern crate fst;
+extern crate smallvec;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "futures")]
+extern crate futures;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
+#[cfg(feature = "unicode-segmentation")]
+extern crate unicode_segmentation;
+#[cfg(feature = "regex")]
+extern crate regex;
+#[cfg(feature = "quickcheck")]
+extern crate quickcheck;
 
+pub mod error;
+pub mod config;
 pub mod levenshtein;
+#[cfg(feature = "hfst")]
 pub mod hfst;
+#[cfg(feature = "openfst")]
+pub mod openfst;
 pub mod adapters;
+pub mod encoding;
+pub mod pruning;
+pub mod quantize;
+pub mod trace;
+#[cfg(feature = "profiling")]
+pub mod profile;
 pub mod ext;
+pub mod lm;
+pub mod dict;
+pub mod charclass;
+pub mod translit;
+pub mod confusion;
+pub mod acronym;
+pub mod rank_suggestions;
+pub mod sidecar;
+pub mod correct;
+pub mod tokenize;
+pub mod prelude;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+
+pub use error::{Error, Result};
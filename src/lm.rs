@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use adapters::{WeightedNFA, FollowEpsilonNFA};
+
+// A language model over keys, e.g. a character n-gram model, so that
+// corrections are ranked by the channel model together with how likely the
+// correction is. Scores are negative log probabilities, like weights.
+// Send + Sync so an LmRescoringNFA is as shareable between threads as the
+// automaton it wraps (though not the BeamSearchAdapter over it).
+pub trait LmScorer: Send + Sync {
+    fn score(&self, prefix_bytes: &[u8]) -> f64;
+}
+
+impl<F: Fn(&[u8]) -> f64 + Send + Sync> LmScorer for F {
+    fn score(&self, prefix_bytes: &[u8]) -> f64 {
+        self(prefix_bytes)
+    }
+}
+
+// Every prefix seen so far as a trie, with its LM score, so each is only
+// scored once however many hypotheses and steps reach it.
+struct Prefixes {
+    // the parent, last byte and score of each prefix, the empty one first
+    nodes: Vec<(u32, u8, f64)>,
+    children: HashMap<(u32, u8), u32>,
+}
+
+impl Prefixes {
+    fn new(empty_score: f64) -> Prefixes {
+        Prefixes {
+            nodes: vec![(0, 0, empty_score)],
+            children: HashMap::new(),
+        }
+    }
+
+    fn bytes(&self, mut prefix: u32) -> Vec<u8> {
+        let mut bytes = vec![];
+        while prefix != 0 {
+            let (parent, byte, _) = self.nodes[prefix as usize];
+            bytes.push(byte);
+            prefix = parent;
+        }
+        bytes.reverse();
+        bytes
+    }
+}
+
+// Adds the change in LM score for each byte to the weight of every
+// hypothesis it extends, so the beam adapters prune with the LM included.
+// Each state keeps the id of the prefix so far; all hypotheses in a beam
+// share it. Prefixes are kept for the life of the NFA, so make a new one
+// rather than swapping lm.
+pub struct LmRescoringNFA<Wrapped: WeightedNFA<InputType=u8>> {
+    pub aut: Wrapped,
+    pub lm: Box<dyn LmScorer>,
+    // multiplies the LM's contribution
+    pub scale: f64,
+    prefixes: Mutex<Prefixes>,
+}
+
+impl<Wrapped: WeightedNFA<InputType=u8>> LmRescoringNFA<Wrapped> {
+    pub fn new(aut: Wrapped, lm: Box<dyn LmScorer>) -> LmRescoringNFA<Wrapped> {
+        LmRescoringNFA::with_scale(aut, lm, 1.0)
+    }
+
+    pub fn with_scale(aut: Wrapped, lm: Box<dyn LmScorer>, scale: f64)
+            -> LmRescoringNFA<Wrapped> {
+        let prefixes = Mutex::new(Prefixes::new(lm.score(&[])));
+        LmRescoringNFA { aut: aut, lm: lm, scale: scale, prefixes: prefixes }
+    }
+
+    // The id of prefix extended by byte, and the change in LM score.
+    fn extend(&self, prefix: u32, byte: u8) -> (u32, f64) {
+        // nothing is left half updated by a panicking LM
+        let mut prefixes = self.prefixes.lock().unwrap_or_else(|err| err.into_inner());
+        let parent_score = prefixes.nodes[prefix as usize].2;
+        if let Some(&child) = prefixes.children.get(&(prefix, byte)) {
+            return (child, prefixes.nodes[child as usize].2 - parent_score);
+        }
+        let mut bytes = prefixes.bytes(prefix);
+        bytes.push(byte);
+        let score = self.lm.score(&bytes);
+        let child = prefixes.nodes.len() as u32;
+        prefixes.nodes.push((prefix, byte, score));
+        prefixes.children.insert((prefix, byte), child);
+        (child, score - parent_score)
+    }
+}
+
+pub struct LmNextStates<I> {
+    inner: I,
+    prefix: u32,
+    delta: f64,
+}
+
+impl<S, I: Iterator<Item=(S, f64)>> Iterator for LmNextStates<I> {
+    type Item = ((S, u32), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(state, weight)|
+            ((state, self.prefix), weight + self.delta))
+    }
+}
+
+impl<Wrapped: WeightedNFA<InputType=u8>> WeightedNFA for LmRescoringNFA<Wrapped> {
+    type State = (Wrapped::State, u32);
+    type NextStateIter<'a> = LmNextStates<Wrapped::NextStateIter<'a>> where Self: 'a;
+    type InputType = u8;
+
+    fn start(&self) -> Self::State {
+        (self.aut.start(), 0)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.aut.is_match(&state.0)
+    }
+
+    fn final_weight(&self, state: &Self::State) -> f64 {
+        self.aut.final_weight(&state.0)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.aut.can_match(&state.0)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.aut.will_always_match(&state.0)
+    }
+
+    // never a free sink, since the LM scores every byte
+    fn is_free_sink(&self, _state: &Self::State) -> bool {
+        false
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: u8) -> Self::NextStateIter<'a> {
+        let &(ref inner, prefix) = state;
+        let (prefix, delta) = self.extend(prefix, inp);
+        LmNextStates {
+            inner: self.aut.accept(inner, inp),
+            prefix: prefix,
+            delta: self.scale * delta,
+        }
+    }
+}
+
+impl<Wrapped> FollowEpsilonNFA for LmRescoringNFA<Wrapped>
+        where Wrapped: FollowEpsilonNFA<InputType=u8> {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        let &(ref inner, prefix) = state;
+        LmNextStates {
+            inner: self.aut.follow_epsilon(inner),
+            prefix: prefix,
+            delta: 0.0,
+        }
+    }
+}
@@ -0,0 +1,406 @@
+cpp!({
+    #include <cinttypes>
+    #include <limits>
+    #include <string>
+
+    #include <fst/fstlib.h>
+
+    struct OpenFstNextState {
+        uint64_t state;
+        float weight;
+    };
+    struct OpenFstSymbol {
+        int64_t label;
+        const char *symbol;
+        uint64_t len;
+    };
+});
+
+use std::os::raw::c_void;
+use std::collections::HashMap;
+use std::fmt;
+use std::ffi::CString;
+use std::io;
+use std::iter::Peekable;
+use std::u64;
+use adapters::Utf8Buffer;
+use error::{Error, Result};
+#[cfg(feature = "profiling")]
+use profile::{Timer, Phase};
+
+fn c_path(filename: &str) -> io::Result<CString> {
+    CString::new(filename).map_err(|_| io::Error::new(
+        io::ErrorKind::InvalidInput, "path has a NUL in it"))
+}
+
+// OpenFST's label for epsilon
+pub const EPSILON: i64 = 0;
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct OpenFstNextState {
+    state: u64,
+    weight: f32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct OpenFstSymbol {
+    label: i64,
+    symbol: *const u8,
+    len: u64,
+}
+
+// A StdVectorFst. Used for both error models and the graphs made from them
+// for a query. Symbols are taken from the input symbol table, or if there
+// isn't one, labels are taken to be Unicode code points.
+pub struct OpenFst {
+    fst: *mut c_void,
+    labels: Option<HashMap<String, i64>>,
+}
+
+impl OpenFst {
+    fn new(fst: *mut c_void) -> OpenFst {
+        let mut fst = OpenFst { fst: fst, labels: None };
+        fst.labels = fst.input_symbols();
+        fst
+    }
+
+    fn input_symbols(&self) -> Option<HashMap<String, i64>> {
+        let fst = self.fst;
+        let num_symbols = unsafe {
+            cpp!([fst as "fst::StdVectorFst*"] -> i64 as "int64_t" {
+                const fst::SymbolTable *syms = fst->InputSymbols();
+                return syms == NULL ? -1 : syms->NumSymbols();
+            })
+        };
+        if num_symbols < 0 {
+            return None;
+        }
+        let mut labels = HashMap::with_capacity(num_symbols as usize);
+        for i in 0..num_symbols {
+            let symbol = unsafe {
+                cpp!([fst as "fst::StdVectorFst*", i as "int64_t"]
+                        -> OpenFstSymbol as "struct OpenFstSymbol" {
+                    const fst::SymbolTable *syms = fst->InputSymbols();
+                    int64_t label = syms->GetNthKey(i);
+                    // kept until the next call, by which time it's been copied
+                    static thread_local std::string symbol;
+                    symbol = syms->Find(label);
+                    return ((struct OpenFstSymbol) { label, symbol.data(), symbol.size() });
+                })
+            };
+            let bytes = unsafe {
+                ::std::slice::from_raw_parts(symbol.symbol, symbol.len as usize)
+            };
+            labels.insert(String::from_utf8_lossy(bytes).into_owned(), symbol.label);
+        }
+        Some(labels)
+    }
+
+    pub fn from_file(filename: &str) -> Result<OpenFst> {
+        let filename_cp = c_path(filename)?;
+        let filename_ptr = filename_cp.as_ptr();
+        let fst = unsafe {
+            cpp!([filename_ptr as "const char*"] -> *mut c_void as "fst::StdVectorFst*" {
+                fst::StdVectorFst *fst = fst::StdVectorFst::Read(std::string(filename_ptr));
+                // sorted once here rather than for each query it's composed with
+                if (fst != NULL) {
+                    fst::ArcSort(fst, fst::StdILabelCompare());
+                }
+                return fst;
+            })
+        };
+        if fst.is_null() {
+            Err(Error::Load(filename.to_owned()))
+        } else {
+            Ok(OpenFst::new(fst))
+        }
+    }
+
+    pub fn write_to_file(&self, filename: &str) -> Result<()> {
+        let filename_cp = c_path(filename)?;
+        let filename_ptr = filename_cp.as_ptr();
+        let fst = self.fst;
+        let written = unsafe {
+            cpp!([fst as "fst::StdVectorFst*", filename_ptr as "const char*"] -> bool as "bool" {
+                return fst->Write(std::string(filename_ptr));
+            })
+        };
+        if written {
+            Ok(())
+        } else {
+            Err(Error::Io(io::Error::new(io::ErrorKind::Other, "couldn't write fst")))
+        }
+    }
+
+    // The label of an input symbol, matching single characters by code point
+    // if there's no symbol table.
+    pub fn label(&self, symbol: &str) -> Option<i64> {
+        match self.labels {
+            Some(ref labels) => labels.get(symbol).cloned(),
+            None => {
+                let mut chars = symbol.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c as i64),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn num_states(&self) -> u64 {
+        let fst = self.fst;
+        unsafe {
+            cpp!([fst as "fst::StdVectorFst*"] -> u64 as "uint64_t" {
+                return fst->NumStates();
+            })
+        }
+    }
+
+    // u64::MAX if the fst is empty
+    pub fn start_state(&self) -> u64 {
+        let fst = self.fst;
+        let start = unsafe {
+            cpp!([fst as "fst::StdVectorFst*"] -> i64 as "int64_t" {
+                return fst->Start();
+            })
+        };
+        if start < 0 { u64::MAX } else { start as u64 }
+    }
+
+    // Infinite for non-final states.
+    pub fn final_weight(&self, stateno: u64) -> f32 {
+        let fst = self.fst;
+        unsafe {
+            cpp!([fst as "fst::StdVectorFst*", stateno as "uint64_t"] -> f32 as "float" {
+                if (stateno >= (uint64_t) fst->NumStates()) {
+                    return std::numeric_limits<float>::infinity();
+                }
+                return fst->Final(stateno).Value();
+            })
+        }
+    }
+
+    pub fn is_final_state(&self, stateno: u64) -> bool {
+        self.final_weight(stateno) != ::std::f32::INFINITY
+    }
+
+    // Composes with other on the right, sorting a copy of its arcs if they
+    // aren't already, as they are for a model from from_file.
+    pub fn compose(&self, other: &OpenFst) -> Result<OpenFst> {
+        #[cfg(feature = "profiling")]
+        let _timer = Timer::start(Phase::Composition);
+        let left = self.fst;
+        let right = other.fst;
+        let fst = unsafe {
+            cpp!([left as "fst::StdVectorFst*", right as "fst::StdVectorFst*"]
+                    -> *mut c_void as "fst::StdVectorFst*" {
+                fst::StdVectorFst *composed = new fst::StdVectorFst();
+                if (right->Properties(fst::kILabelSorted, true)) {
+                    fst::Compose(*left, *right, composed);
+                } else {
+                    fst::StdVectorFst sorted(*right);
+                    fst::ArcSort(&sorted, fst::StdILabelCompare());
+                    fst::Compose(*left, sorted, composed);
+                }
+                if (composed->Properties(fst::kError, false)) {
+                    delete composed;
+                    return NULL;
+                }
+                return composed;
+            })
+        };
+        if fst.is_null() {
+            Err(Error::Compose)
+        } else {
+            Ok(OpenFst::new(fst))
+        }
+    }
+
+    // The n lightest paths.
+    pub fn shortest_path(&self, n: u32) -> Result<OpenFst> {
+        let input = self.fst;
+        let fst = unsafe {
+            cpp!([input as "fst::StdVectorFst*", n as "uint32_t"]
+                    -> *mut c_void as "fst::StdVectorFst*" {
+                fst::StdVectorFst *shortest = new fst::StdVectorFst();
+                fst::ShortestPath(*input, shortest, n);
+                if (shortest->Properties(fst::kError, false)) {
+                    delete shortest;
+                    return NULL;
+                }
+                return shortest;
+            })
+        };
+        if fst.is_null() {
+            Err(Error::Convert)
+        } else {
+            Ok(OpenFst::new(fst))
+        }
+    }
+
+    pub fn project_output(&mut self) {
+        let fst = self.fst;
+        unsafe {
+            cpp!([fst as "fst::StdVectorFst*"] {
+                fst::Project(fst, fst::PROJECT_OUTPUT);
+            });
+        }
+        self.labels = self.input_symbols();
+    }
+
+    // Like TransducerBox::text_to_denoised_fsa: composes an acceptor for the
+    // query with the error model and keeps the output side. NoMatch if the
+    // query has characters the model doesn't know.
+    pub fn text_to_denoised_fsa(&self, query: &str) -> Result<OpenFst> {
+        let mut labels = Vec::with_capacity(query.len());
+        let mut buf = [0; 4];
+        for c in query.chars() {
+            labels.push(self.label(c.encode_utf8(&mut buf)).ok_or(Error::NoMatch)?);
+        }
+        let labels_ptr = labels.as_ptr();
+        let labels_len = labels.len() as u64;
+        let model = self.fst;
+        let query_fsa = unsafe {
+            cpp!([model as "fst::StdVectorFst*",
+                  labels_ptr as "const int64_t*",
+                  labels_len as "uint64_t"] -> *mut c_void as "fst::StdVectorFst*" {
+                fst::StdVectorFst *query_fsa = new fst::StdVectorFst();
+                fst::StdArc::StateId state = query_fsa->AddState();
+                query_fsa->SetStart(state);
+                for (uint64_t i = 0; i < labels_len; i++) {
+                    fst::StdArc::StateId next = query_fsa->AddState();
+                    query_fsa->AddArc(state, fst::StdArc(
+                        labels_ptr[i], labels_ptr[i], fst::TropicalWeight::One(), next));
+                    state = next;
+                }
+                query_fsa->SetFinal(state, fst::TropicalWeight::One());
+                query_fsa->SetInputSymbols(model->InputSymbols());
+                query_fsa->SetOutputSymbols(model->InputSymbols());
+                return query_fsa;
+            })
+        };
+        let query_fsa = OpenFst::new(query_fsa);
+        let mut graph = query_fsa.compose(self)?;
+        graph.project_output();
+        Ok(graph)
+    }
+
+    // Arcs from stateno with the given input label.
+    pub fn arcs<'a>(&'a self, stateno: u64, label: i64) -> OpenFstArcs<'a> {
+        OpenFstArcs {
+            fst: self,
+            stateno: stateno,
+            label: label,
+            arc: 0,
+        }
+    }
+
+    // Arcs from stateno with the given symbol as input, or None if the
+    // symbol isn't known.
+    pub fn symbol_arcs<'a>(&'a self, stateno: u64, symbol: &[u8])
+            -> Option<OpenFstArcs<'a>> {
+        let symbol = match ::std::str::from_utf8(symbol) {
+            Ok(symbol) => symbol,
+            Err(_) => return None,
+        };
+        self.label(symbol).map(|label| self.arcs(stateno, label))
+    }
+
+    pub fn epsilon_arcs<'a>(&'a self, stateno: u64) -> OpenFstArcs<'a> {
+        self.arcs(stateno, EPSILON)
+    }
+}
+
+impl fmt::Debug for OpenFst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpenFst")
+            .field("num_states", &self.num_states())
+            .field("labels", &self.labels)
+            .finish()
+    }
+}
+
+impl Drop for OpenFst {
+    fn drop(&mut self) {
+        let fst = self.fst;
+        unsafe {
+            cpp!([fst as "fst::StdVectorFst*"] {
+                delete fst;
+            });
+        }
+    }
+}
+
+// Walks the arcs of a state in place, yielding those with the given input
+// label.
+pub struct OpenFstArcs<'a> {
+    fst: &'a OpenFst,
+    stateno: u64,
+    label: i64,
+    arc: u64,
+}
+
+impl<'a> Iterator for OpenFstArcs<'a> {
+    type Item = ((u64, Utf8Buffer), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fst = self.fst.fst;
+        let stateno = self.stateno;
+        let label = self.label;
+        let mut arc = self.arc;
+        let mut found = false;
+        let next_state;
+        unsafe {
+            next_state = cpp!(
+                    [fst as "fst::StdVectorFst*",
+                     stateno as "uint64_t",
+                     label as "int64_t",
+                     mut arc as "uint64_t",
+                     mut found as "bool"] ->
+                        OpenFstNextState as "struct OpenFstNextState" {
+                if (stateno >= (uint64_t) fst->NumStates()) {
+                    return ((struct OpenFstNextState) { 0, 0.0 });
+                }
+                fst::ArcIterator<fst::StdVectorFst> aiter(*fst, stateno);
+                for (aiter.Seek(arc); !aiter.Done(); aiter.Next()) {
+                    arc++;
+                    const fst::StdArc &transition = aiter.Value();
+                    if ((int64_t) transition.ilabel == label) {
+                        found = true;
+                        return ((struct OpenFstNextState) {
+                            (uint64_t) transition.nextstate,
+                            transition.weight.Value()
+                        });
+                    }
+                }
+                return ((struct OpenFstNextState) { 0, 0.0 });
+            });
+        }
+        self.arc = arc;
+        if found {
+            Some(((next_state.state, Utf8Buffer::new()), next_state.weight as f64))
+        } else {
+            None
+        }
+    }
+}
+
+pub enum OpenFstNextStates<'a> {
+    Empty,
+    Once(Option<((u64, Utf8Buffer), f64)>),
+    Many(Peekable<OpenFstArcs<'a>>),
+}
+
+impl<'a> Iterator for OpenFstNextStates<'a> {
+    type Item = ((u64, Utf8Buffer), f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            OpenFstNextStates::Empty => None,
+            OpenFstNextStates::Once(ref mut next) => next.take(),
+            OpenFstNextStates::Many(ref mut arcs) => arcs.next(),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// Binds OpenFST directly, for models which are plain OpenFST, so HFST isn't
+// needed. The trait impls live here for the same reason as in hfst.
+mod ffi;
+
+pub use self::ffi::{OpenFst, OpenFstArcs, OpenFstNextStates, EPSILON};
+
+use fst::Automaton;
+use adapters::{WeightedNFA, AutomatonDFAAdapter, EpsilonExpandingBeamSearchAdapter, FollowEpsilonNFA, WeightedStateAutomaton, Utf8Buffer};
+use config::SearchConfig;
+use error::Result;
+
+impl FollowEpsilonNFA for OpenFst {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        let &(stateno, ref buf) = state;
+        if !buf.is_empty() {
+            return OpenFstNextStates::Empty;
+        }
+        OpenFstNextStates::Many(self.epsilon_arcs(stateno).peekable())
+    }
+}
+
+impl WeightedNFA for OpenFst {
+    type State = (u64, Utf8Buffer);
+    type NextStateIter<'a> = OpenFstNextStates<'a>;
+    type InputType = u8;
+
+    fn start(&self) -> Self::State {
+        (self.start_state(), Utf8Buffer::new())
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        let &(stateno, ref buf) = state;
+        buf.is_empty() && self.is_final_state(stateno)
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, byte: u8) -> Self::NextStateIter<'a> {
+        let &(stateno, buf) = state;
+        let mut new_buf = buf;
+        new_buf.push(byte);
+        if let Some(mut arcs) = self.symbol_arcs(stateno, new_buf.as_slice())
+                .map(Iterator::peekable) {
+            if arcs.peek().is_some() {
+                return OpenFstNextStates::Many(arcs);
+            }
+        }
+        if new_buf.is_full() {
+            // unlike HFST, only symbols of up to 4 bytes are supported
+            OpenFstNextStates::Empty
+        } else {
+            OpenFstNextStates::Once(Some(((stateno, new_buf), 0.0)))
+        }
+    }
+}
+
+pub type OpenFstStack = AutomatonDFAAdapter<EpsilonExpandingBeamSearchAdapter<OpenFst>>;
+
+pub fn mk_stack(graph: OpenFst, config: &SearchConfig) -> Result<OpenFstStack> {
+    Ok(AutomatonDFAAdapter(EpsilonExpandingBeamSearchAdapter::new(config.beam_search(graph)?)))
+}
+
+pub fn get_weights(aut: &OpenFstStack, result: &[u8]) -> f64 {
+    let state = aut.accept_bytes(&aut.start(), result);
+    aut.get_weight(&state)
+}
@@ -0,0 +1,43 @@
+// The traits and the most used types and constructors, for glob importing:
+//
+//     use fst_extra_aut::prelude::*;
+//
+// Everything here is also reachable by its own module path, which is the
+// one to use in type bounds.
+pub use fst::Automaton;
+
+pub use adapters::{WeightedNFA, DFA, FollowEpsilonNFA, WeightedStateAutomaton,
+                   BeamSearchAdapter, EpsilonExpandingBeamSearchAdapter, EncodedDFAAdapter,
+                   DFAUtf8Adapter, DFATokenAdapter, DFAInitialsAdapter, AutomatonDFAAdapter,
+                   RcStateNFA, Beam, Combine, compare_weights};
+#[cfg(feature = "rayon")]
+pub use adapters::ParallelBeamSearchAdapter;
+pub use encoding::{InputEncoder, Utf8Encoder, TokenEncoder, InitialEncoder};
+#[cfg(feature = "unicode-segmentation")]
+pub use adapters::DFAGraphemeAdapter;
+#[cfg(feature = "unicode-segmentation")]
+pub use encoding::GraphemeEncoder;
+pub use pruning::{Prune, PruningStrategy};
+pub use config::{SearchConfig, ConfigError};
+pub use quantize::{Quantization, QuantizedNFA};
+pub use levenshtein::{Levenshtein, WeightedLevenshtein};
+pub use levenshtein::unweighted::SimpleLevenshtein;
+pub use levenshtein::weighted::{LevenshteinStack, mk_levenshtein};
+pub use levenshtein::multi::MultiLevenshtein;
+pub use levenshtein::myers::MyersLevenshtein;
+pub use levenshtein::ocr::{OcrLevenshteinStack, mk_ocr_levenshtein};
+pub use levenshtein::lcs::{LcsStack, mk_lcs};
+pub use levenshtein::prefix::{PrefixBoostNFA, JaroWinklerStack, mk_jaro_winkler};
+pub use levenshtein::tokens::{TokenLevenshteinStack, mk_token_levenshtein};
+pub use charclass::{CharClassAutomaton, CharClass, ClassItem};
+pub use translit::{Mapping, TranslitStack, mk_translit};
+pub use confusion::{ConfusionStack, mk_ocr_confusion};
+pub use acronym::{AcronymNFA, AcronymStack, mk_acronym};
+pub use dict::{DictAutomaton, DictStack, mk_dict_stack};
+pub use ext::raw::{FstExt, SortKey};
+pub use ext::map::MapExt;
+pub use ext::set::SetExt;
+pub use ext::many::{ManyConfig, search_many};
+pub use ext::dedup::{dedup_by_key_min_weight, dedup_vec_by_key_min_weight, KeyNormalization,
+                     dedup_normalized};
+pub use ext::fuzzy::{MapFuzzySearchBuilder, Ranker};
@@ -0,0 +1,88 @@
+use std::cell::RefCell;
+use std::ops::Add;
+use std::time::{Duration, Instant};
+
+// Wall-clock time spent in each part of answering queries, to tell whether
+// composition or streaming dominates. Collected per thread, so whatever runs
+// on other threads, e.g. ParallelBeamSearchAdapter's workers, isn't counted.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct QueryProfile {
+    // composing queries with error models
+    pub composition: Duration,
+    // turning transducers into graphs to search, e.g. CompiledHfstGraph
+    pub conversion: Duration,
+    // beam search, including any lazy composition done while stepping
+    pub stepping: Duration,
+    pub steps: u64,
+}
+
+impl QueryProfile {
+    pub fn total(&self) -> Duration {
+        self.composition + self.conversion + self.stepping
+    }
+}
+
+impl Add for QueryProfile {
+    type Output = QueryProfile;
+
+    fn add(self, other: QueryProfile) -> QueryProfile {
+        QueryProfile {
+            composition: self.composition + other.composition,
+            conversion: self.conversion + other.conversion,
+            stepping: self.stepping + other.stepping,
+            steps: self.steps + other.steps,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Composition,
+    Conversion,
+    Stepping,
+}
+
+thread_local!(static CURRENT: RefCell<QueryProfile> = RefCell::new(QueryProfile::default()));
+
+// What's been recorded on this thread since the last take, e.g. call before
+// and after each query.
+pub fn take() -> QueryProfile {
+    CURRENT.with(|current| current.replace(QueryProfile::default()))
+}
+
+pub fn current() -> QueryProfile {
+    CURRENT.with(|current| *current.borrow())
+}
+
+pub fn record(phase: Phase, elapsed: Duration) {
+    CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        match phase {
+            Phase::Composition => current.composition += elapsed,
+            Phase::Conversion => current.conversion += elapsed,
+            Phase::Stepping => current.stepping += elapsed,
+        }
+    });
+}
+
+pub fn count_step() {
+    CURRENT.with(|current| current.borrow_mut().steps += 1);
+}
+
+// Records the time until it's dropped against a phase.
+pub struct Timer {
+    phase: Phase,
+    started: Instant,
+}
+
+impl Timer {
+    pub fn start(phase: Phase) -> Timer {
+        Timer { phase: phase, started: Instant::now() }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        record(self.phase, self.started.elapsed());
+    }
+}
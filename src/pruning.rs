@@ -0,0 +1,111 @@
+// What BeamSearchAdapter should do with a hypothesis. They're offered in
+// order of weight, lightest first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Prune {
+    Keep,
+    // keep it, but it's the last one this step
+    KeepLast,
+    Drop,
+}
+
+// Send + Sync so a beam search using one can be moved to another thread.
+// The search itself can't be shared between threads, see
+// BeamSearchAdapter::scratch, so give each thread its own.
+pub trait PruningStrategy: Send + Sync {
+    // weight is the hypothesis' accumulated weight, best the weight of the
+    // lightest hypothesis this step and kept how many have been kept so far.
+    fn prune(&self, weight: f64, best: f64, kept: usize) -> Prune;
+
+    // Whether a hypothesis this heavy would be dropped whatever else is in
+    // the beam, along with everything reached from it, since weights only
+    // grow along a path. Infinite and NaN weights are always dead ends.
+    fn is_hopeless(&self, weight: f64) -> bool {
+        weight == f64::INFINITY || weight.is_nan()
+    }
+}
+
+// Drops hypotheses heavier than the threshold.
+#[derive(Copy, Clone, Debug)]
+pub struct AbsoluteThreshold(pub f64);
+
+impl PruningStrategy for AbsoluteThreshold {
+    fn prune(&self, weight: f64, _best: f64, _kept: usize) -> Prune {
+        if weight > self.0 {
+            Prune::Drop
+        } else {
+            Prune::Keep
+        }
+    }
+
+    fn is_hopeless(&self, weight: f64) -> bool {
+        !(weight <= self.0)
+    }
+}
+
+// Drops hypotheses more than the threshold heavier than the step's best,
+// so it still means something for long inputs where weights pile up. The
+// weights themselves are untouched, so final weights stay exact.
+#[derive(Copy, Clone, Debug)]
+pub struct RelativeThreshold(pub f64);
+
+impl PruningStrategy for RelativeThreshold {
+    fn prune(&self, weight: f64, best: f64, _kept: usize) -> Prune {
+        if weight - best > self.0 {
+            Prune::Drop
+        } else {
+            Prune::Keep
+        }
+    }
+}
+
+// Keeps at most this many hypotheses per step.
+#[derive(Copy, Clone, Debug)]
+pub struct Histogram(pub usize);
+
+impl PruningStrategy for Histogram {
+    fn prune(&self, _weight: f64, _best: f64, kept: usize) -> Prune {
+        if kept + 1 >= self.0 {
+            Prune::KeepLast
+        } else {
+            Prune::Keep
+        }
+    }
+}
+
+// A relative threshold which narrows as the beam fills up, down to nothing
+// at beam_size, so a step with many close hypotheses is cut off sooner.
+#[derive(Copy, Clone, Debug)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub beam_size: usize,
+}
+
+impl PruningStrategy for Adaptive {
+    fn prune(&self, weight: f64, best: f64, kept: usize) -> Prune {
+        if kept + 1 >= self.beam_size {
+            return Prune::KeepLast;
+        }
+        let fill = kept as f64 / self.beam_size as f64;
+        if weight - best > self.threshold * (1.0 - fill) {
+            Prune::Drop
+        } else {
+            Prune::Keep
+        }
+    }
+}
+
+// Applies every strategy, going with the harshest.
+pub struct Composite(pub Vec<Box<dyn PruningStrategy>>);
+
+impl PruningStrategy for Composite {
+    fn prune(&self, weight: f64, best: f64, kept: usize) -> Prune {
+        self.0.iter()
+            .map(|strategy| strategy.prune(weight, best, kept))
+            .max().unwrap_or(Prune::Keep)
+    }
+
+    fn is_hopeless(&self, weight: f64) -> bool {
+        weight == f64::INFINITY || weight.is_nan() ||
+            self.0.iter().any(|strategy| strategy.is_hopeless(weight))
+    }
+}
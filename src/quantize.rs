@@ -0,0 +1,144 @@
+use std::f64;
+use std::u32;
+
+use adapters::{WeightedNFA, FollowEpsilonNFA};
+
+// Rounding of weights to fixed point with frac_bits bits after the point.
+// Only the values are rounded: arcs and beams still hold them as floats, so
+// this doesn't make stepping faster or beam states smaller. What it gives
+// is that rounded weights are multiples of a power of two, so sums of them
+// are exact and ties between paths really are ties.
+//
+// The cost is precision: each weight moves by up to half a step, so a path
+// of n arcs can be off by n / 2 steps, and paths closer than that can swap
+// places. Weights above the largest fixed point value are infinite, as are
+// NaNs, and negative weights become 0. The default of 8 bits gives steps of
+// about 0.004 and weights up to about 16.7 million.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Quantization {
+    pub frac_bits: u32,
+}
+
+impl Default for Quantization {
+    fn default() -> Quantization {
+        Quantization { frac_bits: 8 }
+    }
+}
+
+impl Quantization {
+    pub fn new(frac_bits: u32) -> Quantization {
+        Quantization { frac_bits: frac_bits }
+    }
+
+    // The difference between neighbouring weights.
+    pub fn step(&self) -> f64 {
+        1.0 / self.scale()
+    }
+
+    fn scale(&self) -> f64 {
+        (2.0f64).powi(self.frac_bits as i32)
+    }
+
+    // u32::MAX stands for an infinite weight.
+    fn to_fixed(&self, weight: f64) -> u32 {
+        if weight.is_nan() {
+            return u32::MAX;
+        }
+        let fixed = (weight * self.scale()).round();
+        if fixed >= u32::MAX as f64 {
+            u32::MAX
+        } else if fixed <= 0.0 {
+            0
+        } else {
+            fixed as u32
+        }
+    }
+
+    fn to_weight(&self, fixed: u32) -> f64 {
+        if fixed == u32::MAX {
+            f64::INFINITY
+        } else {
+            fixed as f64 / self.scale()
+        }
+    }
+
+    // Rounds weight to the nearest weight fixed point can hold.
+    pub fn quantize(&self, weight: f64) -> f64 {
+        self.to_weight(self.to_fixed(weight))
+    }
+}
+
+// Rounds the weights of the wrapped automaton's arcs as they're taken,
+// for automata which aren't compiled. See CompiledHfstGraph::quantize_weights
+// to do it once up front.
+#[derive(Clone, Debug)]
+pub struct QuantizedNFA<Wrapped: WeightedNFA> {
+    pub inner: Wrapped,
+    pub quantization: Quantization,
+}
+
+impl<Wrapped: WeightedNFA> QuantizedNFA<Wrapped> {
+    pub fn new(inner: Wrapped, quantization: Quantization) -> QuantizedNFA<Wrapped> {
+        QuantizedNFA { inner: inner, quantization: quantization }
+    }
+}
+
+pub struct QuantizedNextStates<I> {
+    inner: I,
+    quantization: Quantization,
+}
+
+impl<S, I: Iterator<Item=(S, f64)>> Iterator for QuantizedNextStates<I> {
+    type Item = (S, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(state, weight)| (state, self.quantization.quantize(weight)))
+    }
+}
+
+impl<Wrapped: WeightedNFA> WeightedNFA for QuantizedNFA<Wrapped> {
+    type State = Wrapped::State;
+    type NextStateIter<'a> = QuantizedNextStates<Wrapped::NextStateIter<'a>> where Self: 'a;
+    type InputType = Wrapped::InputType;
+
+    fn start(&self) -> Self::State {
+        self.inner.start()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.inner.is_match(state)
+    }
+
+    fn final_weight(&self, state: &Self::State) -> f64 {
+        self.quantization.quantize(self.inner.final_weight(state))
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.inner.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.inner.will_always_match(state)
+    }
+
+    fn is_free_sink(&self, state: &Self::State) -> bool {
+        self.inner.is_free_sink(state)
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: Self::InputType)
+            -> Self::NextStateIter<'a> {
+        QuantizedNextStates {
+            inner: self.inner.accept(state, inp),
+            quantization: self.quantization,
+        }
+    }
+}
+
+impl<Wrapped: FollowEpsilonNFA> FollowEpsilonNFA for QuantizedNFA<Wrapped> {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        QuantizedNextStates {
+            inner: self.inner.follow_epsilon(state),
+            quantization: self.quantization,
+        }
+    }
+}
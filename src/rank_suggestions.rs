@@ -0,0 +1,72 @@
+use std::f64;
+
+use fst::Streamer;
+
+use adapters::compare_weights;
+
+// Noisy channel scoring of matches from a map of counts: the edit weight is
+// the channel model and the count gives the prior, so the final score is
+// edit_weight - lambda * ln(count + smoothing).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NoisyChannel {
+    pub lambda: f64,
+    // added to every count, so that keys with a count of 0 can still win
+    pub smoothing: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RankedSuggestion {
+    pub key: Vec<u8>,
+    pub count: u64,
+    pub edit_weight: f64,
+    pub score: f64,
+}
+
+impl NoisyChannel {
+    pub fn new(lambda: f64) -> NoisyChannel {
+        NoisyChannel::with_smoothing(lambda, 1.0)
+    }
+
+    pub fn with_smoothing(lambda: f64, smoothing: f64) -> NoisyChannel {
+        NoisyChannel { lambda: lambda, smoothing: smoothing }
+    }
+
+    // Lower is better, like weights. Infinite if the smoothed count is 0.
+    pub fn score(&self, edit_weight: f64, count: u64) -> f64 {
+        let count = count as f64 + self.smoothing;
+        if count <= 0.0 {
+            return f64::INFINITY;
+        }
+        edit_weight - self.lambda * count.ln()
+    }
+
+    // Scores and sorts owned (key, count, edit weight) matches, best first,
+    // keeping up to limit of them.
+    pub fn rank_iter<I>(&self, matches: I, limit: Option<usize>) -> Vec<RankedSuggestion>
+            where I: IntoIterator<Item=(Vec<u8>, u64, f64)> {
+        let mut ranked: Vec<RankedSuggestion> = matches.into_iter()
+            .map(|(key, count, edit_weight)| RankedSuggestion {
+                key: key,
+                count: count,
+                edit_weight: edit_weight,
+                score: self.score(edit_weight, count),
+            })
+            .collect();
+        ranked.sort_by(|a, b|
+            compare_weights(&a.score, &b.score).then_with(|| a.key.cmp(&b.key)));
+        if let Some(limit) = limit {
+            ranked.truncate(limit);
+        }
+        ranked
+    }
+
+    // Like rank_iter, straight from a map's weighted stream.
+    pub fn rank<S>(&self, mut stream: S, limit: Option<usize>) -> Vec<RankedSuggestion>
+            where S: for<'a> Streamer<'a, Item=(&'a [u8], u64, f64)> {
+        let mut matches = vec![];
+        while let Some((key, count, edit_weight)) = stream.next() {
+            matches.push((key.to_vec(), count, edit_weight));
+        }
+        self.rank_iter(matches, limit)
+    }
+}
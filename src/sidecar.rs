@@ -0,0 +1,106 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use fst::raw::MmapReadOnly;
+
+use error::Result;
+use ext::fuzzy::Ranker;
+
+enum ScoreData {
+    Mmap(MmapReadOnly),
+    Owned(Vec<u8>),
+}
+
+// Extra per-key scores kept next to a map rather than in it, e.g. for
+// popularity or recency, as a file of little endian f32s indexed by the
+// map's output values. Updating them doesn't need the fst rebuilding.
+pub struct ScoreStore {
+    data: ScoreData,
+}
+
+// The scores could be a lot of bytes, so only the count is shown.
+impl fmt::Debug for ScoreStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScoreStore")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl ScoreStore {
+    // Memory maps a file written by write_scores.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ScoreStore> {
+        Ok(ScoreStore { data: ScoreData::Mmap(MmapReadOnly::open_path(path)?) })
+    }
+
+    pub fn from_scores(scores: &[f32]) -> ScoreStore {
+        let mut bytes = Vec::with_capacity(scores.len() * 4);
+        write_scores(&mut bytes, scores).unwrap();
+        ScoreStore { data: ScoreData::Owned(bytes) }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self.data {
+            // the file is expected to be left alone while it's mapped, as
+            // with fst's own mmapped maps
+            ScoreData::Mmap(ref mmap) => unsafe { mmap.as_slice() },
+            ScoreData::Owned(ref bytes) => bytes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes().len() / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The score of the key with output out, if there is one.
+    pub fn get(&self, out: u64) -> Option<f32> {
+        if out >= self.len() as u64 {
+            return None;
+        }
+        let start = out as usize * 4;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.bytes()[start..start + 4]);
+        Some(f32::from_le_bytes(bytes))
+    }
+}
+
+pub fn write_scores<W: io::Write>(mut wtr: W, scores: &[f32]) -> io::Result<()> {
+    for score in scores {
+        wtr.write_all(&score.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// Takes each key's stored score, times scale, off the score given by the
+// wrapped ranker, so that keys with higher stored scores rank higher.
+// Keys without one get missing.
+#[derive(Debug)]
+pub struct SidecarRanker<R> {
+    pub ranker: R,
+    pub scores: ScoreStore,
+    pub scale: f64,
+    pub missing: f64,
+}
+
+impl<R: Ranker> SidecarRanker<R> {
+    pub fn new(ranker: R, scores: ScoreStore, scale: f64) -> SidecarRanker<R> {
+        SidecarRanker {
+            ranker: ranker,
+            scores: scores,
+            scale: scale,
+            missing: 0.0,
+        }
+    }
+}
+
+impl<R: Ranker> Ranker for SidecarRanker<R> {
+    fn score(&self, edit_weight: f64, out: u64) -> f64 {
+        let extra = self.scores.get(out).map_or(self.missing, |score| score as f64);
+        self.ranker.score(edit_weight, out) - self.scale * extra
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::vec;
+
+use quickcheck::{Arbitrary, Gen};
+
+use adapters::{WeightedNFA, FollowEpsilonNFA};
+
+// Brute-force reference implementations to check the beam searched automata
+// against, and quickcheck generators for small queries and graphs to feed
+// them. Everything here is exponential, so keep inputs small.
+
+// The symbols generated queries and graphs are made of, kept few so that
+// random queries and graphs actually share paths.
+pub const ALPHABET: &'static [u8] = b"abc";
+
+// Levenshtein distance over chars, by the textbook dynamic programme.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, a_chr) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &b_chr) in b.iter().enumerate() {
+            let sub = prev[j] + if a_chr == b_chr { 0 } else { 1 };
+            cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+fn keep_lightest(best: &mut Option<f64>, weight: f64) {
+    if best.map_or(true, |best| weight < best) {
+        *best = Some(weight);
+    }
+}
+
+fn walk<N: WeightedNFA>(aut: &N, state: N::State, weight: f64, input: &[N::InputType],
+                        best: &mut Option<f64>) {
+    match input.split_first() {
+        None => if aut.is_match(&state) {
+            keep_lightest(best, weight);
+        },
+        Some((&inp, rest)) => for (next, arc_weight) in aut.accept(&state, inp) {
+            walk(aut, next, weight + arc_weight, rest, best);
+        },
+    }
+}
+
+// The lightest weight of any path through aut reading input, found by
+// following every path with no beam or threshold. None if none match.
+pub fn exhaustive_weight<N: WeightedNFA>(aut: &N, input: &[N::InputType]) -> Option<f64> {
+    let mut best = None;
+    walk(aut, aut.start(), 0.0, input, &mut best);
+    best
+}
+
+fn walk_epsilon<N: FollowEpsilonNFA>(aut: &N, state: N::State, weight: f64,
+                                     input: &[N::InputType], epsilons_left: usize,
+                                     max_epsilons: usize, best: &mut Option<f64>) {
+    if input.is_empty() && aut.is_match(&state) {
+        keep_lightest(best, weight);
+    }
+    if epsilons_left > 0 {
+        for (next, arc_weight) in aut.follow_epsilon(&state) {
+            walk_epsilon(aut, next, weight + arc_weight, input, epsilons_left - 1,
+                         max_epsilons, best);
+        }
+    }
+    if let Some((&inp, rest)) = input.split_first() {
+        for (next, arc_weight) in aut.accept(&state, inp) {
+            walk_epsilon(aut, next, weight + arc_weight, rest, max_epsilons,
+                         max_epsilons, best);
+        }
+    }
+}
+
+// Like exhaustive_weight, also following epsilons, at most max_epsilons of
+// them in a row so that epsilon cycles terminate.
+pub fn exhaustive_epsilon_weight<N: FollowEpsilonNFA>(aut: &N, input: &[N::InputType],
+                                                      max_epsilons: usize) -> Option<f64> {
+    let mut best = None;
+    walk_epsilon(aut, aut.start(), 0.0, input, max_epsilons, max_epsilons, &mut best);
+    best
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SmallArc {
+    pub source: u32,
+    // None for epsilon
+    pub input: Option<u8>,
+    pub target: u32,
+    pub weight: f64,
+}
+
+// A weighted NFA as a plain arc list, starting at state 0.
+#[derive(Clone, Debug)]
+pub struct SmallGraph {
+    pub num_states: u32,
+    pub arcs: Vec<SmallArc>,
+    pub finals: Vec<u32>,
+}
+
+impl SmallGraph {
+    fn next_states(&self, state: u32, input: Option<u8>) -> vec::IntoIter<(u32, f64)> {
+        self.arcs.iter()
+            .filter(|arc| arc.source == state && arc.input == input)
+            .map(|arc| (arc.target, arc.weight))
+            .collect::<Vec<_>>().into_iter()
+    }
+
+    // Every string accepted by a path of up to max_len arcs, epsilons
+    // included, with the lightest weight of those paths.
+    pub fn paths(&self, max_len: usize) -> HashMap<Vec<u8>, f64> {
+        let mut paths = HashMap::new();
+        let mut agenda = vec![(0, vec![], 0.0, 0)];
+        while let Some((state, string, weight, arcs)) = agenda.pop() {
+            if self.finals.contains(&state) {
+                let best = paths.entry(string.clone()).or_insert(weight);
+                if weight < *best {
+                    *best = weight;
+                }
+            }
+            if arcs == max_len {
+                continue;
+            }
+            for arc in self.arcs.iter().filter(|arc| arc.source == state) {
+                let mut next_string = string.clone();
+                if let Some(inp) = arc.input {
+                    next_string.push(inp);
+                }
+                agenda.push((arc.target, next_string, weight + arc.weight, arcs + 1));
+            }
+        }
+        paths
+    }
+}
+
+impl WeightedNFA for SmallGraph {
+    type State = u32;
+    type NextStateIter<'a> = vec::IntoIter<(u32, f64)>;
+    type InputType = u8;
+
+    fn start(&self) -> Self::State {
+        0
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.finals.contains(state)
+    }
+
+    fn accept<'a>(&'a self, state: &Self::State, inp: u8) -> Self::NextStateIter<'a> {
+        self.next_states(*state, Some(inp))
+    }
+}
+
+impl FollowEpsilonNFA for SmallGraph {
+    fn follow_epsilon<'a>(&'a self, state: &Self::State) -> Self::NextStateIter<'a> {
+        self.next_states(*state, None)
+    }
+}
+
+fn below<G: Gen>(g: &mut G, n: usize) -> usize {
+    usize::arbitrary(g) % n
+}
+
+fn symbol<G: Gen>(g: &mut G) -> u8 {
+    ALPHABET[below(g, ALPHABET.len())]
+}
+
+// A short query over ALPHABET.
+#[derive(Clone, Debug)]
+pub struct SmallQuery(pub String);
+
+impl Arbitrary for SmallQuery {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallQuery {
+        let len = below(g, g.size().min(6) + 1);
+        SmallQuery((0..len).map(|_| symbol(g) as char).collect())
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item=SmallQuery>> {
+        let query = self.0.clone();
+        Box::new((0..query.len()).map(move |i| {
+            let mut shorter = query.clone();
+            shorter.remove(i);
+            SmallQuery(shorter)
+        }))
+    }
+}
+
+// Up to 4 states over ALPHABET, with small whole number weights so ties
+// come up, and about one arc in four an epsilon.
+impl Arbitrary for SmallGraph {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallGraph {
+        let num_states = below(g, 4) as u32 + 1;
+        let num_arcs = below(g, g.size().min(10) + 1);
+        let arcs = (0..num_arcs).map(|_| SmallArc {
+            source: below(g, num_states as usize) as u32,
+            input: if below(g, 4) == 0 { None } else { Some(symbol(g)) },
+            target: below(g, num_states as usize) as u32,
+            weight: below(g, 4) as f64,
+        }).collect();
+        let finals = (0..num_states).filter(|_| bool::arbitrary(g)).collect();
+        SmallGraph { num_states: num_states, arcs: arcs, finals: finals }
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item=SmallGraph>> {
+        let graph = self.clone();
+        Box::new((0..graph.arcs.len()).map(move |i| {
+            let mut smaller = graph.clone();
+            smaller.arcs.remove(i);
+            smaller
+        }))
+    }
+}
@@ -0,0 +1,96 @@
+use std::ops::Range;
+
+#[cfg(feature = "regex")]
+use regex::Regex;
+#[cfg(feature = "unicode-segmentation")]
+use unicode_segmentation::UnicodeSegmentation;
+
+// Splits text into tokens, given as byte spans. The same tokenizer should
+// be used to build multi-word keys with join_tokens and to search them, so
+// that both agree on where the tokens are.
+pub trait Tokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Range<usize>>;
+}
+
+// Runs of alphanumeric characters.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Alphanumeric;
+
+// Runs of characters other than whitespace.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Whitespace;
+
+// Spans of characters which match pred.
+fn runs<F: Fn(char) -> bool>(text: &str, pred: F) -> Vec<Range<usize>> {
+    let mut spans = vec![];
+    let mut start = None;
+    for (idx, c) in text.char_indices() {
+        match (pred(c), start) {
+            (true, None) => start = Some(idx),
+            (false, Some(begin)) => {
+                spans.push(begin..idx);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        spans.push(begin..text.len());
+    }
+    spans
+}
+
+impl Tokenizer for Alphanumeric {
+    fn tokenize(&self, text: &str) -> Vec<Range<usize>> {
+        runs(text, char::is_alphanumeric)
+    }
+}
+
+impl Tokenizer for Whitespace {
+    fn tokenize(&self, text: &str) -> Vec<Range<usize>> {
+        runs(text, |c| !c.is_whitespace())
+    }
+}
+
+// Words according to the Unicode word boundary rules (UAX #29), leaving out
+// punctuation and whitespace.
+#[cfg(feature = "unicode-segmentation")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UnicodeWords;
+
+#[cfg(feature = "unicode-segmentation")]
+impl Tokenizer for UnicodeWords {
+    fn tokenize(&self, text: &str) -> Vec<Range<usize>> {
+        text.unicode_word_indices()
+            .map(|(idx, word)| idx..idx + word.len())
+            .collect()
+    }
+}
+
+// Each match of the regex is a token.
+#[cfg(feature = "regex")]
+#[derive(Clone, Debug)]
+pub struct RegexTokenizer(pub Regex);
+
+#[cfg(feature = "regex")]
+impl Tokenizer for RegexTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Range<usize>> {
+        self.0.find_iter(text)
+            .filter(|found| found.end() > found.start())
+            .map(|found| found.start()..found.end())
+            .collect()
+    }
+}
+
+// The tokens of text joined by separator, as a key for the token level
+// Levenshtein automaton.
+pub fn join_tokens(tokenizer: &dyn Tokenizer, text: &str, separator: u8) -> Vec<u8> {
+    let mut key = Vec::with_capacity(text.len());
+    for span in tokenizer.tokenize(text) {
+        if !key.is_empty() {
+            key.push(separator);
+        }
+        key.extend_from_slice(text[span].as_bytes());
+    }
+    key
+}
@@ -0,0 +1,146 @@
+use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+use adapters::DFA;
+
+pub trait TraceLabel {
+    fn trace_label(&self) -> String;
+}
+
+impl TraceLabel for u8 {
+    fn trace_label(&self) -> String {
+        (*self as char).escape_default().collect()
+    }
+}
+
+impl TraceLabel for char {
+    fn trace_label(&self) -> String {
+        self.escape_default().collect()
+    }
+}
+
+// The part of the search space a TracingAdapter has been through. States
+// are told apart by their Debug output, which for beams includes weights.
+#[derive(Clone, Debug, Default)]
+pub struct SearchTrace {
+    states: Vec<String>,
+    ids: HashMap<String, usize>,
+    transitions: Vec<(usize, usize, String)>,
+    seen_transitions: HashSet<(usize, usize, String)>,
+    start: Option<usize>,
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl SearchTrace {
+    pub fn new() -> SearchTrace {
+        SearchTrace::default()
+    }
+
+    fn state_id(&mut self, label: String) -> usize {
+        if let Some(&id) = self.ids.get(&label) {
+            return id;
+        }
+        let id = self.states.len();
+        self.states.push(label.clone());
+        self.ids.insert(label, id);
+        id
+    }
+
+    fn record(&mut self, from: String, inp: String, to: String) {
+        let from = self.state_id(from);
+        let to = self.state_id(to);
+        let transition = (from, to, inp);
+        if self.seen_transitions.insert(transition.clone()) {
+            self.transitions.push(transition);
+        }
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn num_transitions(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "digraph search {{")?;
+        writeln!(out, "    node [shape=box];")?;
+        for (id, label) in self.states.iter().enumerate() {
+            let shape = if Some(id) == self.start { ", peripheries=2" } else { "" };
+            writeln!(out, "    s{} [label=\"{}\"{}];", id, escape_dot(label), shape)?;
+        }
+        for &(from, to, ref inp) in self.transitions.iter() {
+            writeln!(out, "    s{} -> s{} [label=\"{}\"];", from, to, escape_dot(inp))?;
+        }
+        writeln!(out, "}}")
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = vec![];
+        self.write_dot(&mut dot).unwrap();
+        String::from_utf8(dot).unwrap()
+    }
+}
+
+// Records every state and transition the wrapped DFA goes through, e.g.
+// to see where beam search lost a key which should have matched.
+pub struct TracingAdapter<Wrapped: DFA> {
+    pub inner: Wrapped,
+    trace: RefCell<SearchTrace>,
+}
+
+impl<Wrapped: DFA> TracingAdapter<Wrapped> {
+    pub fn new(inner: Wrapped) -> TracingAdapter<Wrapped> {
+        TracingAdapter {
+            inner: inner,
+            trace: RefCell::new(SearchTrace::new()),
+        }
+    }
+
+    pub fn trace(&self) -> Ref<SearchTrace> {
+        self.trace.borrow()
+    }
+
+    pub fn take_trace(&self) -> SearchTrace {
+        self.trace.replace(SearchTrace::new())
+    }
+}
+
+impl<Wrapped: DFA> DFA for TracingAdapter<Wrapped>
+        where Wrapped::State: Debug, Wrapped::InputType: TraceLabel + Copy {
+    type State = Wrapped::State;
+    type InputType = Wrapped::InputType;
+
+    fn start(&self) -> Self::State {
+        let start = self.inner.start();
+        let mut trace = self.trace.borrow_mut();
+        let id = trace.state_id(format!("{:?}", start));
+        trace.start = Some(id);
+        start
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.inner.is_match(state)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.inner.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.inner.will_always_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, inp: Self::InputType) -> Self::State {
+        let next = self.inner.accept(state, inp);
+        self.trace.borrow_mut().record(
+            format!("{:?}", state), inp.trace_label(), format!("{:?}", next));
+        next
+    }
+}
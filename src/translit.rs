@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use smallvec::{self, SmallVec};
+
+use adapters::{WeightedNFA, BeamSearchAdapter, DFAUtf8Adapter,
+               AutomatonDFAAdapter, compare_weights, WeightedStateAutomaton};
+use config::SearchConfig;
+use encoding::Utf8Encoder;
+use error::{Error, Result};
+
+// from in the query may be written as to in the key at the given weight,
+// e.g. ш as sh. Either side can be several chars.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub from: Vec<char>,
+    pub to: Vec<char>,
+    pub weight: f64,
+}
+
+impl Mapping {
+    pub fn new(from: &str, to: &str, weight: f64) -> Mapping {
+        assert!(!from.is_empty() && !to.is_empty(), "mappings can't be empty");
+        Mapping {
+            from: from.chars().collect(),
+            to: to.chars().collect(),
+            weight: weight,
+        }
+    }
+
+    // The same mapping the other way round, e.g. to turn a Cyrillic to
+    // Latin table into a Latin to Cyrillic one.
+    pub fn reversed(&self) -> Mapping {
+        Mapping {
+            from: self.to.clone(),
+            to: self.from.clone(),
+            weight: self.weight,
+        }
+    }
+}
+
+// Reads a table with a mapping per line, given as from, to and optionally
+// a weight, which defaults to 0, separated by whitespace. Blank lines and
+// lines starting with # are skipped.
+pub fn parse_table(table: &str) -> Result<Vec<Mapping>> {
+    let mut mappings = vec![];
+    for (lineno, line) in table.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || Error::InvalidConfig(
+            format!("line {} of mapping table isn't from, to and weight", lineno + 1));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let weight = match fields.len() {
+            2 => 0.0,
+            3 => fields[2].parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        mappings.push(Mapping::new(fields[0], fields[1], weight));
+    }
+    Ok(mappings)
+}
+
+// Matches keys which are the query with each part of it written according
+// to a mapping, weighted by the sum of the mappings used, so that queries
+// in one script find keys stored in another. Chars of the query which no
+// mapping is used for have to be the same in the key, at identity_weight,
+// or can't be kept at all if it's None.
+#[derive(Clone, Debug)]
+pub struct TranslitNFA {
+    query: Vec<char>,
+    mappings: Vec<Mapping>,
+    // mappings by the first char of their to part
+    by_first: HashMap<char, Vec<usize>>,
+    pub identity_weight: Option<f64>,
+}
+
+// Position in the query, and the mapping and how much of its to part has
+// been read if part way through one.
+pub type TranslitState = (usize, Option<(usize, usize)>);
+
+pub type TranslitNextStates = smallvec::IntoIter<[(TranslitState, f64); 4]>;
+
+impl TranslitNFA {
+    pub fn new(query: &str, mappings: Vec<Mapping>) -> TranslitNFA {
+        let mut by_first = HashMap::new();
+        for (idx, mapping) in mappings.iter().enumerate() {
+            by_first.entry(mapping.to[0]).or_insert_with(Vec::new).push(idx);
+        }
+        TranslitNFA {
+            query: query.chars().collect(),
+            mappings: mappings,
+            by_first: by_first,
+            identity_weight: Some(0.0),
+        }
+    }
+
+    pub fn query(&self) -> &[char] {
+        self.query.as_slice()
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    // Having read read chars of mapping's to part, and now inp.
+    fn continue_mapping(&self, chars: usize, mapping: usize, read: usize, inp: char)
+            -> Option<TranslitState> {
+        let mapping_def = &self.mappings[mapping];
+        if mapping_def.to[read] != inp {
+            None
+        } else if read + 1 == mapping_def.to.len() {
+            Some((chars + mapping_def.from.len(), None))
+        } else {
+            Some((chars, Some((mapping, read + 1))))
+        }
+    }
+}
+
+impl WeightedNFA for TranslitNFA {
+    type State = TranslitState;
+    type NextStateIter<'a> = TranslitNextStates;
+    type InputType = char;
+
+    fn start(&self) -> TranslitState {
+        (0, None)
+    }
+
+    fn is_match(&self, state: &TranslitState) -> bool {
+        let &(chars, pending) = state;
+        pending.is_none() && chars == self.query.len()
+    }
+
+    fn can_match(&self, state: &TranslitState) -> bool {
+        state.0 < self.query.len() || self.is_match(state)
+    }
+
+    fn will_always_match(&self, _state: &TranslitState) -> bool {
+        false
+    }
+
+    fn accept<'a>(&'a self, state: &TranslitState, inp: char) -> TranslitNextStates {
+        let &(chars, pending) = state;
+        let mut next: SmallVec<[(TranslitState, f64); 4]> = SmallVec::new();
+        match pending {
+            // a mapping's weight is paid when it's started
+            Some((mapping, read)) => {
+                if let Some(state) = self.continue_mapping(chars, mapping, read, inp) {
+                    next.push((state, 0.0));
+                }
+            }
+            None => {
+                if let Some(identity_weight) = self.identity_weight {
+                    if self.query.get(chars) == Some(&inp) {
+                        next.push(((chars + 1, None), identity_weight));
+                    }
+                }
+                let rest = &self.query[chars..];
+                for &mapping in self.by_first.get(&inp).map_or(&[][..], Vec::as_slice) {
+                    let mapping_def = &self.mappings[mapping];
+                    if !rest.starts_with(&mapping_def.from) {
+                        continue;
+                    }
+                    if let Some(state) = self.continue_mapping(chars, mapping, 0, inp) {
+                        next.push((state, mapping_def.weight));
+                    }
+                }
+                // the beam stops taking states from an iterator at the first
+                // it drops, so they have to come lightest first
+                next.sort_by(|&(_, ref w1), &(_, ref w2)| compare_weights(w1, w2));
+            }
+        }
+        next.into_iter()
+    }
+}
+
+pub type TranslitStack = AutomatonDFAAdapter<DFAUtf8Adapter<BeamSearchAdapter<TranslitNFA>>>;
+
+pub fn mk_translit(query: &str, mappings: Vec<Mapping>, config: &SearchConfig)
+        -> Result<TranslitStack> {
+    Ok(AutomatonDFAAdapter(DFAUtf8Adapter::new(Utf8Encoder,
+        config.beam_search(TranslitNFA::new(query, mappings))?)))
+}
+
+impl WeightedStateAutomaton for TranslitStack {
+    fn get_weight(&self, state: &Self::State) -> f64 {
+        let weights = state.0.iter().filter_map(|&(ref state, weight)|
+            if (self.0).inner.aut.is_match(state) {
+                Some(weight)
+            } else {
+                None
+            }
+        );
+        weights.min_by(compare_weights).unwrap()
+    }
+}
@@ -0,0 +1,460 @@
+// Checks the beam searched automata against the brute-force references in
+// test_support, and against naive implementations here. Beams are made wide
+// enough that nothing which could match inputs this small is pruned, so
+// results should be exact.
+//
+//     cargo test --features test-support
+
+extern crate fst;
+extern crate fst_extra_aut;
+extern crate quickcheck;
+
+use std::collections::HashMap;
+
+use fst::{Automaton, IntoStreamer, Map, Set, Streamer};
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+
+use fst_extra_aut::acronym::mk_acronym;
+use fst_extra_aut::adapters::{AutomatonDFAAdapter, DFAUtf8Adapter, WeightedStateAutomaton};
+use fst_extra_aut::charclass::{CharClass, CharClassAutomaton, ClassItem};
+use fst_extra_aut::config::SearchConfig;
+use fst_extra_aut::dict::{DictAutomaton, mk_dict_stack};
+use fst_extra_aut::encoding::Utf8Encoder;
+use fst_extra_aut::ext::dedup::{dedup_by_key_min_weight, dedup_vec_by_key_min_weight};
+use fst_extra_aut::ext::sharded::ShardedIndex;
+use fst_extra_aut::levenshtein::LevenshteinConfig;
+use fst_extra_aut::levenshtein::costs::CostModel;
+use fst_extra_aut::levenshtein::myers::MyersLevenshtein;
+use fst_extra_aut::levenshtein::weighted::{WeightedLevenshteinNFA, mk_levenshtein};
+use fst_extra_aut::test_support::{ALPHABET, SmallQuery, edit_distance, exhaustive_weight};
+use fst_extra_aut::translit::{Mapping, mk_translit};
+
+const TESTS: usize = 500;
+const BEAM_SIZE: usize = 1000;
+
+fn check<A: quickcheck::Testable>(prop: A) {
+    QuickCheck::new().tests(TESTS).quickcheck(prop);
+}
+
+fn below<G: Gen>(g: &mut G, n: usize) -> usize {
+    usize::arbitrary(g) % n
+}
+
+fn symbol<G: Gen>(g: &mut G) -> char {
+    ALPHABET[below(g, ALPHABET.len())] as char
+}
+
+fn chars(string: &str) -> Vec<char> {
+    string.chars().collect()
+}
+
+// The state aut is in after reading key.
+fn run<A: Automaton>(aut: &A, key: &[u8]) -> A::State {
+    key.iter().fold(aut.start(), |state, &byte| aut.accept(&state, byte))
+}
+
+fn stack_weight<A: WeightedStateAutomaton>(aut: &A, key: &[u8]) -> Option<f64> {
+    let state = run(aut, key);
+    if aut.is_match(&state) {
+        Some(aut.get_weight(&state))
+    } else {
+        None
+    }
+}
+
+// Whether searching a set of keys finds the same keys as stepping each on
+// its own, i.e. can_match never gives up on a key which would match.
+fn search_agrees<A: Automaton>(aut: &A, keys: &[&str]) -> bool {
+    let mut sorted = keys.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    let set = Set::from_iter(sorted.iter().cloned()).unwrap();
+    let mut found = vec![];
+    let mut stream = set.search(aut).into_stream();
+    while let Some(key) = stream.next() {
+        found.push(key.to_vec());
+    }
+    let expected: Vec<Vec<u8>> = sorted.iter()
+        .filter(|key| aut.is_match(&run(aut, key.as_bytes())))
+        .map(|key| key.as_bytes().to_vec())
+        .collect();
+    found == expected
+}
+
+// Substituting b for a is cheap, anything else costs 1.
+struct CheapAB;
+
+impl CostModel for CheapAB {
+    fn substitute(&self, query: char, inp: char) -> f64 {
+        if query == 'a' && inp == 'b' { 0.5 } else { 1.0 }
+    }
+}
+
+#[test]
+fn weighted_levenshtein_is_exhaustive() {
+    fn prop(query: SmallQuery, key: SmallQuery) -> bool {
+        let threshold = 2.0;
+        let nfa = WeightedLevenshteinNFA::with_costs(&query.0, Box::new(CheapAB));
+        let expected = exhaustive_weight(&nfa, &chars(&key.0))
+            .filter(|&weight| weight <= threshold);
+        let config = SearchConfig::new(threshold, BEAM_SIZE);
+        let aut = AutomatonDFAAdapter(DFAUtf8Adapter::new(
+            Utf8Encoder, config.beam_search(nfa).unwrap()));
+        stack_weight(&aut, key.0.as_bytes()) == expected &&
+            search_agrees(&aut, &[&key.0, &query.0])
+    }
+    check(prop as fn(SmallQuery, SmallQuery) -> bool);
+}
+
+// Query chars left over at the end can only go as substitutions, so the
+// weight can be more than the edit distance, but never less.
+#[test]
+fn weighted_levenshtein_is_at_least_edit_distance() {
+    fn prop(query: SmallQuery, key: SmallQuery) -> bool {
+        let aut = mk_levenshtein(&query.0, &SearchConfig::new(10.0, BEAM_SIZE)).unwrap();
+        let distance = edit_distance(&query.0, &key.0) as f64;
+        match stack_weight(&aut, key.0.as_bytes()) {
+            Some(weight) => weight >= distance && (weight == 0.0) == (query.0 == key.0),
+            None => key.0.is_empty() && !query.0.is_empty(),
+        }
+    }
+    check(prop as fn(SmallQuery, SmallQuery) -> bool);
+}
+
+// Swaps b and c for chars of two and three bytes, so that keys read by a
+// byte automaton leave partial chars buffered.
+fn multi_byte(string: &str) -> String {
+    string.chars().map(|chr| match chr {
+        'b' => 'é',
+        'c' => '日',
+        chr => chr,
+    }).collect()
+}
+
+#[test]
+fn myers_is_edit_distance() {
+    fn prop(query: SmallQuery, key: SmallQuery, max_dist: usize) -> bool {
+        let max_dist = (max_dist % 4) as u32;
+        let key = multi_byte(&key.0);
+        // the empty query steps without the bit vectors
+        ["".to_owned(), multi_byte(&query.0)].iter().all(|query| {
+            let aut = MyersLevenshtein::new(query, max_dist).unwrap();
+            let distance = edit_distance(query, &key) as u32;
+            let expected = if distance <= max_dist { Some(distance) } else { None };
+            aut.distance(&run(&aut, key.as_bytes())) == expected &&
+                search_agrees(&aut, &[&key, query])
+        })
+    }
+    check(prop as fn(SmallQuery, SmallQuery, usize) -> bool);
+}
+
+fn lcs_len(a: &[char], b: &[char]) -> usize {
+    let mut prev = vec![0; b.len() + 1];
+    for &a_chr in a {
+        let mut cur = vec![0; b.len() + 1];
+        for (j, &b_chr) in b.iter().enumerate() {
+            cur[j + 1] = if a_chr == b_chr {
+                prev[j] + 1
+            } else {
+                prev[j + 1].max(cur[j])
+            };
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[test]
+fn lcs_is_longest_common_subsequence() {
+    fn prop(query: SmallQuery, key: SmallQuery, distance: usize) -> bool {
+        let distance = distance % 4;
+        let mut config = LevenshteinConfig::new(&query.0, distance as u32);
+        config.beam_size = BEAM_SIZE;
+        let aut = config.lcs().unwrap();
+        let query_chars = chars(&query.0);
+        let common = lcs_len(&query_chars, &chars(&key.0));
+        let expected = if common + distance >= query_chars.len() {
+            Some(-(common as f64))
+        } else {
+            None
+        };
+        stack_weight(&aut, key.0.as_bytes()) == expected
+    }
+    check(prop as fn(SmallQuery, SmallQuery, usize) -> bool);
+}
+
+#[derive(Clone, Debug)]
+struct SmallDict(Vec<(String, f64)>);
+
+impl Arbitrary for SmallDict {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallDict {
+        let len = below(g, 8);
+        SmallDict((0..len).map(|_| (SmallQuery::arbitrary(g).0, below(g, 5) as f64)).collect())
+    }
+}
+
+#[test]
+fn dict_gives_lightest_entry() {
+    fn prop(dict: SmallDict, key: SmallQuery) -> bool {
+        let threshold = 3.0;
+        let aut = mk_dict_stack(DictAutomaton::new(dict.0.clone()),
+                                &SearchConfig::new(threshold, BEAM_SIZE)).unwrap();
+        let lightest = dict.0.iter()
+            .filter(|&&(ref entry, _)| *entry == key.0)
+            .map(|&(_, weight)| weight)
+            .fold(None, |best: Option<f64>, weight| Some(best.map_or(weight, |best| best.min(weight))));
+        let expected = lightest.filter(|&weight| weight <= threshold);
+        let keys: Vec<&str> = dict.0.iter().map(|&(ref entry, _)| entry.as_str()).collect();
+        stack_weight(&aut, key.0.as_bytes()) == expected && search_agrees(&aut, &keys)
+    }
+    check(prop as fn(SmallDict, SmallQuery) -> bool);
+}
+
+#[derive(Clone, Debug)]
+struct SmallTemplate(Vec<ClassItem>);
+
+impl Arbitrary for SmallTemplate {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallTemplate {
+        let len = below(g, 4);
+        SmallTemplate((0..len).map(|_| {
+            let class = match below(g, 4) {
+                0 => CharClass::single(symbol(g)),
+                1 => CharClass::range('a', 'b'),
+                2 => CharClass::any(),
+                _ => CharClass { ranges: vec![(symbol(g), symbol(g))], negated: true },
+            };
+            let min = below(g, 3) as u32;
+            let max = match below(g, 3) {
+                0 => None,
+                _ => Some(min + below(g, 3) as u32),
+            };
+            ClassItem { class: class, min: min, max: max }
+        }).collect())
+    }
+}
+
+fn template_matches(items: &[ClassItem], key: &[char]) -> bool {
+    match items.split_first() {
+        None => key.is_empty(),
+        Some((item, rest)) => {
+            let max = item.max.map_or(key.len(), |max| key.len().min(max as usize));
+            (item.min as usize..max + 1).any(|reps|
+                key[..reps].iter().all(|&chr| item.class.contains(chr)) &&
+                    template_matches(rest, &key[reps..]))
+        }
+    }
+}
+
+#[test]
+fn char_class_matches_template() {
+    fn prop(template: SmallTemplate, key: SmallQuery) -> bool {
+        let aut = CharClassAutomaton::new(template.0.clone());
+        let expected = template_matches(&template.0, &chars(&key.0));
+        aut.is_match(&run(&aut, key.0.as_bytes())) == expected &&
+            search_agrees(&aut, &[&key.0])
+    }
+    check(prop as fn(SmallTemplate, SmallQuery) -> bool);
+}
+
+#[derive(Clone, Debug)]
+struct SmallTable(Vec<Mapping>);
+
+impl Arbitrary for SmallTable {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallTable {
+        fn part<G: Gen>(g: &mut G) -> String {
+            (0..below(g, 2) + 1).map(|_| symbol(g)).collect()
+        }
+        let len = below(g, 4);
+        SmallTable((0..len).map(|_| {
+            let from = part(g);
+            let to = part(g);
+            Mapping::new(&from, &to, below(g, 3) as f64)
+        }).collect())
+    }
+}
+
+fn translit_weight(query: &[char], key: &[char], mappings: &[Mapping]) -> Option<f64> {
+    let mut best: Option<f64> = None;
+    {
+        let mut keep = |weight: Option<f64>| if let Some(weight) = weight {
+            best = Some(best.map_or(weight, |best| best.min(weight)));
+        };
+        if query.is_empty() && key.is_empty() {
+            keep(Some(0.0));
+        }
+        if !query.is_empty() && query.first() == key.first() {
+            keep(translit_weight(&query[1..], &key[1..], mappings));
+        }
+        for mapping in mappings {
+            if query.starts_with(&mapping.from) && key.starts_with(&mapping.to) {
+                let rest = translit_weight(&query[mapping.from.len()..],
+                                           &key[mapping.to.len()..], mappings);
+                keep(rest.map(|weight| weight + mapping.weight));
+            }
+        }
+    }
+    best
+}
+
+#[test]
+fn translit_is_lightest_segmentation() {
+    fn prop(table: SmallTable, query: SmallQuery, key: SmallQuery) -> bool {
+        let threshold = 4.0;
+        let aut = mk_translit(&query.0, table.0.clone(),
+                              &SearchConfig::new(threshold, BEAM_SIZE)).unwrap();
+        let expected = translit_weight(&chars(&query.0), &chars(&key.0), &table.0)
+            .filter(|&weight| weight <= threshold);
+        stack_weight(&aut, key.0.as_bytes()) == expected &&
+            search_agrees(&aut, &[&key.0, &query.0])
+    }
+    check(prop as fn(SmallTable, SmallQuery, SmallQuery) -> bool);
+}
+
+// Words over ALPHABET, to be joined by spaces.
+#[derive(Clone, Debug)]
+struct SmallPhrase(Vec<String>);
+
+impl Arbitrary for SmallPhrase {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallPhrase {
+        let len = below(g, 5);
+        SmallPhrase((0..len).map(|_| (0..below(g, 3) + 1).map(|_| symbol(g)).collect())
+            .collect())
+    }
+}
+
+fn is_subsequence(needle: &[char], haystack: &[char]) -> bool {
+    let mut haystack = haystack.iter();
+    needle.iter().all(|chr| haystack.any(|other| other == chr))
+}
+
+#[test]
+fn acronym_skips_fewest_words() {
+    fn prop(query: SmallQuery, phrase: SmallPhrase) -> bool {
+        let threshold = 2.0;
+        let aut = mk_acronym(&query.0, b' ', &SearchConfig::new(threshold, BEAM_SIZE)).unwrap();
+        let key = phrase.0.join(" ");
+        let initials: Vec<char> = phrase.0.iter()
+            .map(|word| word.chars().next().unwrap()).collect();
+        let query_chars = chars(&query.0);
+        let expected = if is_subsequence(&query_chars, &initials) {
+            Some((initials.len() - query_chars.len()) as f64)
+        } else {
+            None
+        };
+        stack_weight(&aut, key.as_bytes()) == expected.filter(|&weight| weight <= threshold) &&
+            search_agrees(&aut, &[&key])
+    }
+    check(prop as fn(SmallQuery, SmallPhrase) -> bool);
+}
+
+type Results = Vec<(Vec<u8>, u64, f64)>;
+
+#[derive(Clone, Debug)]
+struct SmallResults(Results);
+
+impl Arbitrary for SmallResults {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallResults {
+        let len = below(g, 10);
+        SmallResults((0..len).map(|out| {
+            let key = SmallQuery::arbitrary(g).0.into_bytes();
+            (key, out as u64, below(g, 3) as f64)
+        }).collect())
+    }
+}
+
+// The first of the lightest results for each key, in key order.
+fn lightest_by_key(results: &Results) -> Results {
+    let mut lightest: HashMap<Vec<u8>, (u64, f64)> = HashMap::new();
+    for &(ref key, out, weight) in results {
+        let best = lightest.entry(key.clone()).or_insert((out, weight));
+        if weight < best.1 {
+            *best = (out, weight);
+        }
+    }
+    let mut expected: Results = lightest.into_iter()
+        .map(|(key, (out, weight))| (key, out, weight)).collect();
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+    expected
+}
+
+struct VecStream(Results, usize);
+
+impl<'a> Streamer<'a> for VecStream {
+    type Item = (&'a [u8], u64, f64);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        self.1 += 1;
+        self.0.get(self.1 - 1).map(|&(ref key, out, weight)| (&key[..], out, weight))
+    }
+}
+
+#[test]
+fn dedup_keeps_lightest_per_key() {
+    fn prop(results: SmallResults) -> bool {
+        let expected = lightest_by_key(&results.0);
+        let mut in_key_order = results.0.clone();
+        in_key_order.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut streamed = vec![];
+        let mut stream = dedup_by_key_min_weight(VecStream(in_key_order, 0));
+        while let Some((key, out, weight)) = stream.next() {
+            streamed.push((key.to_vec(), out, weight));
+        }
+        let mut collected = results.0.clone();
+        dedup_vec_by_key_min_weight(&mut collected);
+        streamed == expected && collected == expected
+    }
+    check(prop as fn(SmallResults) -> bool);
+}
+
+#[derive(Clone, Debug)]
+struct SmallShards(Vec<Vec<String>>);
+
+impl Arbitrary for SmallShards {
+    fn arbitrary<G: Gen>(g: &mut G) -> SmallShards {
+        let num_shards = below(g, 3) + 1;
+        SmallShards((0..num_shards).map(|_| {
+            let len = below(g, 6);
+            let mut keys: Vec<String> = (0..len).map(|_| SmallQuery::arbitrary(g).0).collect();
+            keys.sort();
+            keys.dedup();
+            keys
+        }).collect())
+    }
+}
+
+#[test]
+fn sharded_search_is_global_top_k() {
+    fn prop(shards: SmallShards, query: SmallQuery, k: usize) -> bool {
+        let k = k % 5 + 1;
+        let config = SearchConfig::new(2.0, BEAM_SIZE);
+        let aut = mk_levenshtein(&query.0, &config).unwrap();
+        // each key's output says which shard and key it came from
+        let maps = shards.0.iter().enumerate().map(|(shard, keys)| {
+            Map::from_iter(keys.iter().enumerate()
+                .map(|(idx, key)| (key.as_bytes(), (shard * 100 + idx) as u64))).unwrap()
+        }).collect();
+        let mut expected: Results = vec![];
+        for (shard, keys) in shards.0.iter().enumerate() {
+            for (idx, key) in keys.iter().enumerate() {
+                if expected.iter().any(|&(ref seen, _, _)| seen == key.as_bytes()) {
+                    continue;
+                }
+                if let Some(weight) = stack_weight(&aut, key.as_bytes()) {
+                    expected.push((key.as_bytes().to_vec(), (shard * 100 + idx) as u64, weight));
+                }
+            }
+        }
+        expected.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap().then_with(|| a.0.cmp(&b.0)));
+        expected.truncate(k);
+        let index = ShardedIndex::new(maps);
+        #[cfg(feature = "rayon")]
+        {
+            let make_aut = || mk_levenshtein(&query.0, &config).unwrap();
+            if index.par_search(make_aut, k) != expected {
+                return false;
+            }
+        }
+        index.search(&aut, k) == expected
+    }
+    check(prop as fn(SmallShards, SmallQuery, usize) -> bool);
+}